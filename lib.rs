@@ -0,0 +1,11013 @@
+//! Collects the local and remote state of the Git repos listed in
+//! `~/.config/jiancha/config.toml`. The `jiancha` binary is a thin wrapper around [`run`].
+
+use comfy_table::presets::ASCII_FULL;
+use comfy_table::{
+    Attribute, Cell, CellAlignment, Color, ColumnConstraint, ContentArrangement, Table, Width,
+};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::io::{IsTerminal, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A parsed config file: `[settings]`, `[theme]`, and one table per section.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    settings: Settings,
+    #[serde(default)]
+    theme: ThemeConfig,
+    /// Sections skipped entirely, as if they weren't in the file.
+    #[serde(default)]
+    ignore_sections: Vec<String>,
+    /// Sections listed here come first, in this order; the rest follow in file order.
+    #[serde(default)]
+    display_order: Vec<String>,
+    /// Self-hosted forges whose web pages aren't at `https://<domain>/`.
+    #[serde(default)]
+    git_hosts: Vec<GitHost>,
+    #[serde(flatten)]
+    pub sections: IndexMap<String, Section>,
+    /// Sections dropped on load by `enabled = false` or `ignore_sections`.
+    #[serde(skip)]
+    disabled_sections: Vec<String>,
+    /// What loading raised; see `Config::warnings`.
+    #[serde(skip)]
+    warnings: Vec<String>,
+}
+
+/// A `git_hosts` entry: remotes on `domain` are browsed under `https_base`,
+/// e.g. `{ domain = "git.mycompany.com", https_base = "https://code.mycompany.com/git" }`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GitHost {
+    pub domain: String,
+    pub https_base: String,
+}
+
+/// One config section: repo names mapped to their directories.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(from = "RawSection")]
+pub struct Section {
+    /// Show paths instead of names for this section.
+    pub paths: Option<bool>,
+    /// `enabled = false` skips the section like `ignore_sections` does.
+    pub enabled: Option<bool>,
+    /// At most this many of the section's repos are scanned at once, e.g. `1`
+    /// for a section on a slow external drive.
+    pub jobs: Option<usize>,
+    /// With `--watch`, the section is scanned at most this often, reusing its last
+    /// rows in between; for sections that rarely change.
+    pub refresh_interval_secs: Option<u64>,
+    #[serde(flatten)]
+    pub repos: IndexMap<String, String>,
+    /// Where `jiancha clone` gets each repo from, for repos written as tables.
+    #[serde(skip)]
+    pub clone_urls: IndexMap<String, String>,
+    /// Repos written with `shallow = true`, cloned with `--depth 1`.
+    #[serde(skip)]
+    pub shallow: Vec<String>,
+}
+
+/// A section as written: each repo is either `name = "path"` or a
+/// `[section.name]` table with a `path`, an optional clone `url` and `shallow`.
+#[derive(Deserialize)]
+struct RawSection {
+    paths: Option<bool>,
+    enabled: Option<bool>,
+    jobs: Option<usize>,
+    refresh_interval_secs: Option<u64>,
+    #[serde(flatten)]
+    repos: IndexMap<String, RepoEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RepoEntry {
+    Path(String),
+    Table {
+        path: String,
+        url: Option<String>,
+        shallow: Option<bool>,
+    },
+}
+
+impl From<RawSection> for Section {
+    fn from(raw: RawSection) -> Self {
+        let mut section = Section {
+            paths: raw.paths,
+            enabled: raw.enabled,
+            jobs: raw.jobs,
+            refresh_interval_secs: raw.refresh_interval_secs,
+            ..Section::default()
+        };
+        for (name, entry) in raw.repos {
+            let path = match entry {
+                RepoEntry::Path(path) => path,
+                RepoEntry::Table { path, url, shallow } => {
+                    if let Some(url) = url {
+                        section.clone_urls.insert(name.clone(), url);
+                    }
+                    if shallow == Some(true) {
+                        section.shallow.push(name.clone());
+                    }
+                    path
+                }
+            };
+            section.repos.insert(name, path);
+        }
+        section
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    #[serde(default)]
+    symbols: SymbolsConfig,
+    pager: Option<bool>,
+    check_upstream: Option<bool>,
+    size_scope: Option<SizeScope>,
+    /// Group the digits of counts by thousands, as `--format-number` does.
+    number_format: Option<NumberFormat>,
+    highlight_errors: Option<bool>,
+    /// Show the leading "#" column of row numbers, as `--numbers` does.
+    numbers: Option<bool>,
+    /// How many repos are scanned at once, as `--jobs` sets.
+    jobs: Option<usize>,
+    /// Seconds a repo's local state is reused while its `.git` files are untouched.
+    cache_ttl: Option<u64>,
+    /// Seconds each repo's git commands may take, as `--timeout` sets; `0` for no limit.
+    timeout: Option<u64>,
+    icons: Option<bool>,
+    ascii: Option<bool>,
+    base_branch: Option<String>,
+    wrap: Option<bool>,
+    /// Tint branches other than the default one; `false` turns this off.
+    branch_colors: Option<bool>,
+    feature_branch_color: Option<String>,
+    detached_head_color: Option<String>,
+    /// Fade old commits in the Last Commit column; `false` turns this off.
+    commit_age_colors: Option<bool>,
+    /// Day thresholds between the today, week, month, older and stale buckets.
+    commit_age_days: Option<[u64; 4]>,
+    /// Badge repos on their default branch that have been clean this many runs in a row.
+    min_clean_streak: Option<u32>,
+    /// How `jiancha open` launches a repo, e.g. `code {path}`; `$VISUAL` or `$EDITOR` without it.
+    open_command: Option<String>,
+    /// Columns to show, in order; see `Column::key`.
+    columns: Option<Vec<String>>,
+    #[serde(default)]
+    align: AlignConfig,
+    #[serde(default)]
+    truncate: TruncateConfig,
+    /// Leading characters kept by middle truncation.
+    truncate_lead: Option<usize>,
+    /// Spaces to the (left, right) of every cell's content.
+    padding: Option<(u16, u16)>,
+}
+
+/// Per-column cell alignment, keyed by column name; unset columns are left-aligned.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AlignConfig {
+    number: Option<Align>,
+    section: Option<Align>,
+    repository: Option<Align>,
+    branch: Option<Align>,
+    status: Option<Align>,
+    last_commit: Option<Align>,
+    committed: Option<Align>,
+    fetched: Option<Align>,
+    remote: Option<Align>,
+    size: Option<Align>,
+    contributors: Option<Align>,
+    lfs: Option<Align>,
+    hooks: Option<Align>,
+    envrc: Option<Align>,
+    docs: Option<Align>,
+    diffstat: Option<Align>,
+    security: Option<Align>,
+    duration: Option<Align>,
+    todos: Option<Align>,
+    action: Option<Align>,
+    changes: Option<Align>,
+    error: Option<Align>,
+}
+
+/// How each text column shortens values that don't fit.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TruncateConfig {
+    section: Option<Truncate>,
+    repository: Option<Truncate>,
+    branch: Option<Truncate>,
+    last_commit: Option<Truncate>,
+    error: Option<Truncate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Truncate {
+    /// `feature/JIRA-1...`
+    End,
+    /// `feature/…billing-flow`
+    Middle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// How counts in the table are written: `1000`, or `1,000` with `locale`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NumberFormat {
+    #[default]
+    Plain,
+    Locale,
+}
+
+/// What `--show-size` measures: just the object store or the whole work tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SizeScope {
+    #[default]
+    Git,
+    Worktree,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DiskSize {
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeConfig {
+    name: Option<String>,
+    date_format: Option<DateFormat>,
+    #[serde(default)]
+    section_header: SectionHeaderConfig,
+}
+
+/// `[theme.section_header]`: overrides for the theme's section title style.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SectionHeaderConfig {
+    bold: Option<bool>,
+    underline: Option<bool>,
+    italic: Option<bool>,
+    color: Option<String>,
+    background: Option<String>,
+}
+
+/// How the Committed column shows HEAD's commit time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DateFormat {
+    /// `3 days ago`
+    Relative,
+    /// `2024-01-15T09:30:00Z`
+    Iso,
+    /// `Mon, 15 Jan 2024`
+    Rfc,
+    /// The system locale's date representation, via `date +%x`.
+    Locale,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SymbolsConfig {
+    clean: Option<SymbolSpec>,
+    unpushed: Option<SymbolSpec>,
+    no_upstream: Option<SymbolSpec>,
+    dirty: Option<SymbolSpec>,
+    conflict: Option<SymbolSpec>,
+    error: Option<SymbolSpec>,
+    up_to_date: Option<SymbolSpec>,
+    behind: Option<SymbolSpec>,
+    fetch_error: Option<SymbolSpec>,
+    pending: Option<SymbolSpec>,
+    skipped: Option<SymbolSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SymbolSpec {
+    glyph: Option<String>,
+    color: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Symbol {
+    glyph: String,
+    color: Color,
+}
+
+#[derive(Debug, Clone)]
+struct Symbols {
+    clean: Symbol,
+    unpushed: Symbol,
+    no_upstream: Symbol,
+    dirty: Symbol,
+    conflict: Symbol,
+    error: Symbol,
+    up_to_date: Symbol,
+    behind: Symbol,
+    fetch_error: Symbol,
+    pending: Symbol,
+    skipped: Symbol,
+}
+
+/// Whether the terminal can take 24-bit colors, needs the xterm 256 palette, or
+/// only has the 16 basic colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorMode {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+/// How section titles and their rules are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SectionHeaderStyle {
+    bold: bool,
+    underline: bool,
+    italic: bool,
+    fg: Color,
+    bg: Color,
+}
+
+impl SectionHeaderStyle {
+    const fn bold(fg: Color) -> Self {
+        SectionHeaderStyle {
+            bold: true,
+            underline: false,
+            italic: false,
+            fg,
+            bg: Color::Reset,
+        }
+    }
+}
+
+/// Every color the renderer uses, already adapted to the terminal's `ColorMode`.
+/// `Color::Reset` means "leave the terminal default alone".
+#[derive(Debug, Clone)]
+struct Theme {
+    mode: ColorMode,
+    section_header: SectionHeaderStyle,
+    repo_header: Color,
+    branch_header: Color,
+    status_header: Color,
+    last_commit_header: Color,
+    remote_header: Color,
+    error_header: Color,
+    clean: Color,
+    unpushed: Color,
+    no_upstream: Color,
+    dirty: Color,
+    conflict: Color,
+    error: Color,
+    up_to_date: Color,
+    behind: Color,
+    fetch_error: Color,
+    pending: Color,
+    skipped: Color,
+    extra_header: Color,
+    warning: Color,
+    error_row: Color,
+    /// Branch cell when HEAD isn't on the default branch.
+    feature_branch: Color,
+    detached_head: Color,
+    /// Security column: HEAD isn't signed, or its signature is bad.
+    unsigned: Color,
+    /// Security column: signed with a key that isn't in the local keyring.
+    unknown_key: Color,
+    /// Status of a clean repo with branches whose upstream is gone.
+    stale_branches: Color,
+    /// Last Commit text by commit age, newest bucket first (see `age_bucket`).
+    commit_age: [Color; 5],
+}
+
+const THEME_NAMES: [&str; 3] = ["default", "mono", "solarized"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RepoState {
+    Clean,
+    Unpushed,
+    NoUpstream,
+    Dirty,
+    Conflict,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FetchStatus {
+    Pending,
+    UpToDate,
+    Behind(u32),
+    Error,
+    Skipped,
+}
+
+/// One ref a `jiancha fetch` changed, from fetch's summary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefUpdate {
+    /// A branch or tag that's new on the remote.
+    New(String),
+    /// Moved from `old` to `new` (short hashes); `forced` when it wasn't a fast-forward.
+    Updated {
+        name: String,
+        old: String,
+        new: String,
+        forced: bool,
+    },
+    /// Gone from the remote, so pruned.
+    Deleted(String),
+}
+
+/// HEAD's signature, as `git log --format=%G?` judges it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signature {
+    Unsigned,
+    /// Signed, but with a key the local keyring doesn't have or trust.
+    UnknownKey,
+    /// The signature doesn't match, or the key was revoked.
+    Bad,
+    /// Verified; expired keys and signatures count, as they were good when made.
+    Good {
+        fingerprint: String,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    last_run_at: Option<u64>,
+    repos: HashMap<String, CacheEntry>,
+    /// Local git state by canonical path, reused for `settings.cache_ttl` seconds.
+    #[serde(default)]
+    info: HashMap<String, InfoCacheEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InfoCacheEntry {
+    checked_at: u64,
+    check_upstream: bool,
+    /// From `info_signals`; any change means the repo has to be read again.
+    signals: Vec<u64>,
+    info: GitInfo,
+    /// Whether `info.diffstat` was read; an entry without it can't serve a run that needs it.
+    #[serde(default)]
+    diffstat: bool,
+    /// `--base-branch` as it was; the two fields below depend on it.
+    #[serde(default)]
+    base_branch: Option<String>,
+    #[serde(default)]
+    default_branch: Option<String>,
+    #[serde(default)]
+    ahead_of_base: Option<u32>,
+    /// `--min-clean-streak` as it was, and what `recent_on_base` found for it.
+    #[serde(default)]
+    min_clean_streak: Option<u32>,
+    #[serde(default)]
+    recent_on_base: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    repo_key: String,
+    branch: String,
+    upstream: Option<String>,
+    fetched_at: u64,
+    fetch_status: FetchStatus,
+}
+
+/// Bumped only for incompatible changes; new fields use `#[serde(default)]`.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The previous run's results, kept under the XDG data dir for `--diff`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    taken_at: u64,
+    #[serde(default)]
+    repos: Vec<SnapshotEntry>,
+}
+
+/// The last run's row numbers, kept under the XDG state dir for `jiancha path <number>`.
+#[derive(Debug, Serialize, Deserialize)]
+struct RowIndex {
+    #[serde(default)]
+    rows: Vec<RowEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RowEntry {
+    section: String,
+    repo: String,
+    directory: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SnapshotEntry {
+    section: String,
+    repo: String,
+    #[serde(default)]
+    directory: String,
+    state: String,
+    #[serde(default)]
+    branch: Option<String>,
+    #[serde(default)]
+    ahead: Option<u32>,
+    #[serde(default)]
+    behind: Option<u32>,
+    #[serde(default)]
+    todos: Option<u32>,
+    /// The HEAD `todos` was counted at.
+    #[serde(default)]
+    head: Option<String>,
+    /// Runs in a row, this one included, that found the work tree clean.
+    #[serde(default)]
+    clean_streak: u32,
+}
+
+#[derive(Debug)]
+struct Args {
+    fresh: bool,
+    offline: bool,
+    profile: Option<String>,
+    /// `--config`: read this file instead of the profile's.
+    config: Option<PathBuf>,
+    /// `--dirty`: only show repos with uncommitted changes.
+    dirty: bool,
+    /// `--filter-stale-branches`: only show repos with a branch whose upstream is gone.
+    filter_stale_branches: bool,
+    theme: Option<String>,
+    no_pager: bool,
+    /// `--no-header`: leave the header row out of `--format tsv`.
+    no_header: bool,
+    no_fetch_check: bool,
+    paths: bool,
+    show_size: bool,
+    size_threshold: Option<DiskSize>,
+    group_by: GroupBy,
+    date_format: Option<DateFormat>,
+    number_format: Option<NumberFormat>,
+    icons: bool,
+    ascii: bool,
+    strict: bool,
+    show_fetch_time: bool,
+    show_contributors: bool,
+    show_lfs: bool,
+    show_hooks: bool,
+    show_envrc: bool,
+    /// `--load-envrc`: run git through `direnv exec` in repos with an `.envrc`.
+    load_envrc: bool,
+    show_contributing: bool,
+    show_diffstat: bool,
+    security: bool,
+    timings: bool,
+    deep: bool,
+    quiet: bool,
+    no_cache: bool,
+    relative_paths: bool,
+    /// `--machine-json`: stream one JSON object per repo instead of rendering.
+    machine_json: bool,
+    /// `--stream`: print table rows as repos finish instead of all at the end.
+    stream: bool,
+    cache_ttl: Option<u64>,
+    timeout: Option<u64>,
+    jobs: Option<usize>,
+    /// `--max-errors`: stop scanning once this many repos have failed.
+    max_errors: Option<usize>,
+    min_clean_streak: Option<u32>,
+    numbers: bool,
+    columns: Option<Vec<String>>,
+    /// `--column-alias Repository=Dépôt`, repeatable: header text for a column.
+    column_aliases: Vec<(String, String)>,
+    wrap: bool,
+    check_force_push: bool,
+    base_branch: Option<String>,
+    /// `--remote-filter`, repeatable: keep repos whose remote URL contains any of these.
+    remote_filters: Vec<String>,
+    color: ColorChoice,
+    /// `--ansi-strip`: remove color codes from the finished output, whatever wrote them.
+    ansi_strip: bool,
+    output: Option<PathBuf>,
+    diff: bool,
+    section: Option<String>,
+    /// `--watch [secs]`: redraw the report on this interval until interrupted.
+    watch: Option<Duration>,
+    /// Directories given on the command line; when set, they replace the config.
+    dirs: Vec<String>,
+    /// `status <dir>`: the one directory in `dirs` is looked up in the config first,
+    /// and the exit status reports its state.
+    single: bool,
+    format: OutputFormat,
+    command: Subcommand,
+}
+
+/// `check-stale [--days <n>] [--json] [--github-archive]`
+#[derive(Debug)]
+struct StaleOpts {
+    days: u64,
+    json: bool,
+    github_archive: bool,
+}
+
+/// `push [--dry-run] [--set-upstream] [--confirm]`
+#[derive(Debug)]
+struct PushOpts {
+    dry_run: bool,
+    /// Push branches without an upstream too, with `-u origin <branch>`.
+    set_upstream: bool,
+    /// List what would be pushed and ask before pushing any of it.
+    confirm: bool,
+}
+
+/// `exec [--fail-fast] -- <command>...`
+#[derive(Debug)]
+struct ExecOpts {
+    /// The program and its arguments, with `{path}` and `{name}` still in them.
+    command: Vec<String>,
+    /// Start no more repos once one has failed.
+    fail_fast: bool,
+}
+
+/// `browse [--branch | --commit]`: resolved to a `BrowseTarget` once the repo is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BrowseKind {
+    Repo,
+    Branch,
+    Commit,
+}
+
+/// Options shared by subcommands that act on repos rather than just report.
+#[derive(Debug, Default)]
+struct CommandOpts {
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Prometheus,
+    /// GitHub Actions workflow commands, then the usual tables for the log.
+    Github,
+    /// `--oneline`: a single summary line for prompts and status bars.
+    Oneline {
+        counts: bool,
+    },
+    /// `--porcelain`: one ` M section/repo` line per repo that needs attention.
+    Porcelain,
+    /// `--compact`: one tab-separated line per repo, with no borders or headings.
+    Compact,
+    /// `--format tsv`: tab-separated values with a header row, for scripts.
+    Tsv,
+    /// `--format yaml`: the fields `--machine-json` writes, as one YAML list.
+    Yaml,
+}
+
+impl OutputFormat {
+    /// Formats read by other programs: no progress, no pager, and errors spelled
+    /// out on stderr since they only show as fields.
+    fn is_machine(self) -> bool {
+        !matches!(self, OutputFormat::Table | OutputFormat::Github)
+    }
+}
+
+#[derive(Debug)]
+enum Subcommand {
+    Status,
+    Fetch(CommandOpts),
+    /// Fetches, then fast-forwards every repo where that can't lose or merge anything.
+    Pull(CommandOpts),
+    /// Pushes every clean branch that's ahead of its upstream; never forced.
+    Push(PushOpts),
+    /// Runs `git maintenance run` in every repo that isn't mid-operation.
+    Gc(CommandOpts),
+    /// Runs any command in every repo, exiting with the worst status it returned.
+    Exec(ExecOpts),
+    List {
+        json: bool,
+    },
+    /// Prints one repo's directory, picked by name or by its row number from the last run;
+    /// with `--all`, every repo's name and directory.
+    Path(Option<String>),
+    /// Opens a repo's `origin` page in the web browser.
+    Browse {
+        repo: String,
+        target: BrowseKind,
+    },
+    /// Opens a repo in the editor, or with `--shell` prints its directory.
+    Open {
+        repo: String,
+        shell: bool,
+    },
+    /// Clones a configured repo that isn't on disk yet from its `url`; without a
+    /// name, every such repo.
+    Clone {
+        repo: Option<String>,
+        dry_run: bool,
+    },
+    /// Lists clean repos with nothing recent and no other branches: archiving candidates.
+    CheckStale(StaleOpts),
+    ConfigDiff,
+    ProfilesList,
+    ProfilesCreate(String),
+    /// Runs each git command behind a repo's row and explains what jiancha makes of it.
+    Diagnose {
+        dir: String,
+        json: bool,
+    },
+    /// Prints a completion script for a shell.
+    Completions(Shell),
+    /// Prints the man page, in roff.
+    Man,
+    /// `__complete <kind>`: names for the completion scripts to offer; not in `--help`.
+    Complete(String),
+    /// `--update-self`: pulls jiancha's own source checkout and reinstalls from it.
+    UpdateSelf,
+}
+
+const REMOTE_TTL_SECS: u64 = 30 * 60;
+const ERROR_RETRY_TTL_SECS: u64 = 2 * 60;
+const SESSION_GAP_REFRESH_SECS: u64 = 90 * 60;
+const DEFAULT_CACHE_TTL_SECS: u64 = 60;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_WATCH_SECS: u64 = 30;
+/// The whole scan gets this many per-repo budgets before everything left is cut off.
+const SCAN_DEADLINE_FACTOR: u32 = 4;
+
+/// Everything jiancha knows about one configured repo.
+#[derive(Debug, Clone)]
+pub struct RepoResult {
+    pub repo_key: String,
+    pub section: String,
+    pub directory: String,
+    pub branch: Option<String>,
+    pub last_commit: Option<String>,
+    /// Committer time of HEAD, seconds since the epoch.
+    pub last_commit_at: Option<u64>,
+    pub clean: Option<bool>,
+    pub conflicted: bool,
+    /// Commits on HEAD not yet on the upstream; `None` without an upstream.
+    pub ahead: Option<u32>,
+    /// Commits on HEAD not yet on the base branch (`--base-branch`).
+    pub ahead_of_base: Option<u32>,
+    /// The resolved base branch: `--base-branch`, `origin/HEAD`, or `main`/`master`.
+    pub default_branch: Option<String>,
+    /// The `--min-clean-streak` newest commits on local branches are all on the
+    /// base branch; only checked with `--min-clean-streak`.
+    pub recent_on_base: bool,
+    pub upstream: Option<String>,
+    /// HEAD has more than one parent.
+    pub is_merge_commit: bool,
+    /// When the repo was last fetched, seconds since the epoch.
+    pub last_fetch_at: Option<u64>,
+    /// A plain push would be rejected; only checked with `--check-force-push`.
+    pub needs_force_push: Option<bool>,
+    /// Distinct authors reachable from HEAD; only counted with `--show-contributors`.
+    pub contributors: Option<u32>,
+    /// Some paths are stored with Git LFS.
+    pub lfs_enabled: bool,
+    /// Total size of the LFS objects in HEAD; only added up with `--show-lfs`.
+    pub lfs_storage_bytes: Option<u64>,
+    /// A committed `.lfsconfig` tells fresh clones where the LFS objects live.
+    pub has_lfsconfig: bool,
+    /// URL of the upstream's remote, or `origin`'s; only read for `--remote-filter`.
+    pub remote_url: Option<String>,
+    /// How HEAD is signed; only checked with `--security`.
+    pub signature: Option<Signature>,
+    pub bisect_state: Option<BisectState>,
+    pub precommit_configured: Option<bool>,
+    /// Local branches whose upstream is gone from the remote.
+    pub stale_tracking_count: u32,
+    pub envrc_present: bool,
+    pub has_contributing: bool,
+    pub has_readme: bool,
+    /// Size of the uncommitted changes, staged and unstaged; only in dirty work trees.
+    pub diffstat: Option<DiffStat>,
+    /// Lines mentioning TODO or FIXME in tracked files; only counted with `--deep`,
+    /// and only in clean work trees.
+    pub todos: Option<u32>,
+    /// HEAD's commit id, resolved alongside `todos` so counts can be reused.
+    pub head: Option<String>,
+    /// Consecutive runs that found the work tree clean; see `compute_streak`.
+    pub clean_streak: u32,
+    /// Wall-clock time spent on this repo, fetch included.
+    pub scan_duration: Duration,
+    /// The local git state came from the result cache instead of running git.
+    pub info_cached: bool,
+    /// Why the repo couldn't be inspected, if it couldn't.
+    pub local_error: Option<String>,
+    pub fetch_status: FetchStatus,
+    /// Remote refs the `fetch` subcommand changed.
+    pub ref_updates: Vec<RefUpdate>,
+    pub size: Option<DiskSize>,
+    pub language: Option<Language>,
+    /// What a `--dry-run` subcommand would do to this repo.
+    pub action: Option<String>,
+}
+
+impl RepoResult {
+    fn new(repo_key: &str, section: &str, directory: String) -> Self {
+        RepoResult {
+            repo_key: repo_key.to_string(),
+            section: section.to_string(),
+            directory,
+            branch: None,
+            last_commit: None,
+            last_commit_at: None,
+            clean: None,
+            conflicted: false,
+            ahead: None,
+            ahead_of_base: None,
+            default_branch: None,
+            recent_on_base: false,
+            upstream: None,
+            is_merge_commit: false,
+            last_fetch_at: None,
+            needs_force_push: None,
+            contributors: None,
+            lfs_enabled: false,
+            lfs_storage_bytes: None,
+            has_lfsconfig: false,
+            remote_url: None,
+            signature: None,
+            bisect_state: None,
+            precommit_configured: None,
+            stale_tracking_count: 0,
+            envrc_present: false,
+            has_contributing: false,
+            has_readme: false,
+            diffstat: None,
+            todos: None,
+            head: None,
+            clean_streak: 0,
+            scan_duration: Duration::ZERO,
+            info_cached: false,
+            local_error: None,
+            fetch_status: FetchStatus::Pending,
+            ref_updates: Vec::new(),
+            size: None,
+            language: None,
+            action: None,
+        }
+    }
+
+    fn with_error(mut self, error: &str) -> Self {
+        self.local_error = Some(error.to_string());
+        self
+    }
+}
+
+struct CollectOptions {
+    check_upstream: bool,
+    show_size: bool,
+    size_scope: SizeScope,
+    icons: bool,
+    /// `--base-branch`; `None` tries `main`, then `master`.
+    base_branch: Option<String>,
+    /// `--check-force-push`: ask the remote whether a plain push would be rejected.
+    check_force_push: bool,
+    /// `--show-contributors`: walk all of HEAD's history to count authors.
+    show_contributors: bool,
+    /// `--min-clean-streak`: check that this many of the newest commits are on
+    /// the base branch, which the streak badge also asks for.
+    min_clean_streak: Option<u32>,
+    /// `--show-diffstat`, `--machine-json` or `--format yaml`: sum the lines
+    /// changed in dirty work trees, which runs `git diff` twice per repo.
+    diffstat: bool,
+    /// Prompt redraws only show what `repo_state` reads, so HEAD's commit and the
+    /// base-branch comparison aren't looked up. What's read then isn't cached.
+    brief: bool,
+    /// `--offline` (or a prompt redraw): nothing may touch the network, whatever
+    /// else was asked for. Every fetch and push probe checks this.
+    offline: bool,
+    /// `--show-lfs`: add up the LFS objects of repos that use LFS.
+    show_lfs: bool,
+    /// `--security`: verify HEAD's signature, which runs gpg.
+    security: bool,
+    /// `--remote-filter`: read each repo's remote URL to filter on.
+    remote_url: bool,
+    /// `--jobs`: how many repos are scanned at once, across all sections.
+    jobs: usize,
+    /// Sections left out of the scan: in `--watch`, ones whose
+    /// `refresh_interval_secs` hasn't passed yet.
+    skip_sections: HashSet<String>,
+    /// `--max-errors`: once this many repos have failed, the rest aren't scanned.
+    max_errors: Option<usize>,
+    progress: Progress,
+    /// `--timeout`: how long each repo's git commands may take, fetch included.
+    timeout: Option<Duration>,
+    /// Nothing runs past this, however many repos are left.
+    scan_deadline: Option<Instant>,
+    /// `--load-envrc`, for the git commands run on the scan's threads.
+    load_envrc: bool,
+    /// Unexpired result-cache entries by canonical path; empty with `--no-cache` or `--fresh`.
+    info_cache: HashMap<String, InfoCacheEntry>,
+    /// Repos read from git this run, for the next run's cache; `None` with `--no-cache`.
+    fresh_info: Option<Mutex<HashMap<String, InfoCacheEntry>>>,
+    /// `--deep`: count TODO/FIXME lines, reusing the last snapshot's count for a
+    /// directory (HEAD commit, count) while its HEAD hasn't moved.
+    todo_counts: Option<HashMap<String, (String, u32)>>,
+}
+
+/// The "checking 23/61: ~/code/api" line on stderr while repos are scanned and
+/// fetched. Each update overwrites the last, and `clear` erases it before the
+/// report prints. Callers serialize updates.
+struct Progress {
+    enabled: bool,
+    home: Option<String>,
+    width: usize,
+    ellipsis: &'static str,
+}
+
+impl Progress {
+    fn hidden() -> Self {
+        Progress {
+            enabled: false,
+            home: None,
+            width: 0,
+            ellipsis: "",
+        }
+    }
+
+    fn show(&self, verb: &str, done: usize, total: usize, path: &str) {
+        if !self.enabled {
+            return;
+        }
+        let prefix = format!("{verb} {done}/{total}: ");
+        let room = self.width.saturating_sub(prefix.chars().count() + 1);
+        let path = shorten_path(path, self.home.as_deref(), room, self.ellipsis);
+        eprint!("\r\x1b[2K{prefix}{path}");
+    }
+
+    fn clear(&self) {
+        if self.enabled {
+            eprint!("\r\x1b[2K");
+        }
+    }
+}
+
+impl CollectOptions {
+    /// When a repo started at `started` has to stop: its own budget or the
+    /// whole scan's, whichever comes first.
+    fn deadline_from(&self, started: Instant) -> Option<Instant> {
+        let own = self.timeout.map(|timeout| started + timeout);
+        match (own, self.scan_deadline) {
+            (Some(own), Some(scan)) => Some(own.min(scan)),
+            (own, scan) => own.or(scan),
+        }
+    }
+
+    /// The git options for a repo started at `started`; see `deadline_from`.
+    fn git_options(&self, started: Instant) -> GitOptions {
+        GitOptions {
+            deadline: self.deadline_from(started),
+            load_envrc: self.load_envrc,
+        }
+    }
+
+    fn timeout_message(&self, started: Instant) -> String {
+        match self.timeout {
+            Some(timeout) if started.elapsed() >= timeout => {
+                format!("timed out after {}s", timeout.as_secs())
+            }
+            _ => "scan deadline passed".to_string(),
+        }
+    }
+}
+
+/// The CPU count, used when `--jobs` isn't given.
+fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Local state of every configured repo, in config order. Remote state is left
+/// [`FetchStatus::Pending`]; nothing is fetched.
+pub fn collect_repo_results(config: &Config) -> Vec<RepoResult> {
+    let opts = CollectOptions {
+        check_upstream: true,
+        show_size: false,
+        size_scope: SizeScope::default(),
+        icons: false,
+        base_branch: config.settings.base_branch.clone(),
+        check_force_push: false,
+        show_contributors: false,
+        min_clean_streak: None,
+        diffstat: true,
+        brief: false,
+        offline: false,
+        show_lfs: false,
+        security: false,
+        remote_url: false,
+        jobs: default_jobs(),
+        skip_sections: HashSet::new(),
+        max_errors: None,
+        progress: Progress::hidden(),
+        timeout: None,
+        scan_deadline: None,
+        load_envrc: false,
+        info_cache: HashMap::new(),
+        fresh_info: None,
+        todo_counts: None,
+    };
+    // Without `max_errors` every repo is scanned, so this can't fail.
+    collect_all(config, &opts).unwrap_or_default()
+}
+
+/// Runs `collect_repo` for every configured repo on `opts.jobs` worker threads,
+/// returning rows in config order whatever order they finish in. A section with
+/// its own `jobs` limit never has more repos in flight than that, but workers
+/// skip ahead to other sections meanwhile. With one job, repos run in config order.
+/// Fails once `opts.max_errors` repos have errors, leaving the rest unscanned.
+fn collect_all(
+    config: &Config,
+    opts: &CollectOptions,
+) -> Result<Vec<RepoResult>, Box<dyn std::error::Error>> {
+    struct Schedule<'a> {
+        pending: Vec<usize>,
+        running: HashMap<&'a str, usize>,
+        rows: Vec<Option<RepoResult>>,
+        done: usize,
+        errors: usize,
+    }
+
+    let entries: Vec<(&String, &String, &String)> = config
+        .sections
+        .iter()
+        .filter(|(section_name, _)| !opts.skip_sections.contains(*section_name))
+        .flat_map(|(section_name, section)| {
+            section
+                .repos
+                .iter()
+                .map(move |(repo_name, dir_str)| (section_name, repo_name, dir_str))
+        })
+        .collect();
+    let limit = |section: &str| {
+        config
+            .sections
+            .get(section)
+            .and_then(|s| s.jobs)
+            .map_or(usize::MAX, |n| n.max(1))
+    };
+    let schedule = Mutex::new(Schedule {
+        pending: (0..entries.len()).collect(),
+        running: HashMap::new(),
+        rows: entries.iter().map(|_| None).collect(),
+        done: 0,
+        errors: 0,
+    });
+    let freed = Condvar::new();
+    let lock = || schedule.lock().unwrap_or_else(PoisonError::into_inner);
+    thread::scope(|scope| {
+        for _ in 0..opts.jobs.clamp(1, entries.len().max(1)) {
+            scope.spawn(|| loop {
+                let mut state = lock();
+                let idx = loop {
+                    if state.pending.is_empty() {
+                        return;
+                    }
+                    let ready = state.pending.iter().position(|&i| {
+                        let section = entries[i].0.as_str();
+                        state.running.get(section).copied().unwrap_or(0) < limit(section)
+                    });
+                    if let Some(pos) = ready {
+                        break state.pending.remove(pos);
+                    }
+                    state = freed.wait(state).unwrap_or_else(PoisonError::into_inner);
+                };
+                let (section_name, repo_name, dir_str) = entries[idx];
+                *state.running.entry(section_name).or_default() += 1;
+                drop(state);
+
+                let started = Instant::now();
+                // A panic only loses this repo's row; it's reported as an error below.
+                let (row, timed_out) = with_git_options(opts.git_options(started), || {
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        collect_repo(section_name, repo_name, dir_str, opts)
+                    }))
+                    .ok()
+                });
+                let row = row.map(|row| {
+                    let row = if timed_out {
+                        RepoResult::new(repo_name, section_name, row.directory)
+                            .with_error(&opts.timeout_message(started))
+                    } else {
+                        row
+                    };
+                    RepoResult {
+                        scan_duration: started.elapsed(),
+                        ..row
+                    }
+                });
+
+                let mut state = lock();
+                state.done += 1;
+                let dir = row.as_ref().map_or(dir_str, |row| &row.directory);
+                opts.progress
+                    .show("checking", state.done, entries.len(), dir);
+                if row.as_ref().is_none_or(|row| row.local_error.is_some()) {
+                    state.errors += 1;
+                    // Most likely something systemic, like git missing or a drive gone.
+                    if opts.max_errors.is_some_and(|max| state.errors >= max) {
+                        state.pending.clear();
+                    }
+                }
+                state.rows[idx] = row;
+                if let Some(n) = state.running.get_mut(section_name.as_str()) {
+                    *n -= 1;
+                }
+                drop(state);
+                freed.notify_all();
+            });
+        }
+    });
+    let state = schedule
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some(max) = opts.max_errors.filter(|&max| state.errors >= max) {
+        return Err(format!(
+            "Too many errors ({} >= {max} threshold), aborting",
+            state.errors
+        )
+        .into());
+    }
+    Ok(entries
+        .into_iter()
+        .zip(state.rows)
+        .map(|((section_name, repo_name, dir_str), row)| {
+            row.unwrap_or_else(|| {
+                RepoResult::new(repo_name, section_name, dir_str.clone())
+                    .with_error("Failed to get git info")
+            })
+        })
+        .collect())
+}
+
+/// Gathers the live local state of one configured repo. Problems become an
+/// error row rather than a failure so every configured entry is reported.
+fn collect_repo(
+    section: &str,
+    repo_name: &str,
+    dir_str: &str,
+    opts: &CollectOptions,
+) -> RepoResult {
+    let resolved = match PathBuf::from(dir_str).canonicalize() {
+        Ok(p) => p,
+        Err(_) => {
+            return RepoResult::new(repo_name, section, dir_str.to_string())
+                .with_error("Not a valid directory")
+        }
+    };
+    let mut row = RepoResult::new(repo_name, section, resolved.to_string_lossy().into_owned());
+
+    let git_dir = resolved.join(".git");
+    let cached = opts.info_cache.get(&row.directory).filter(|entry| {
+        entry.check_upstream == opts.check_upstream
+            && (entry.diffstat || !opts.diffstat)
+            && entry.base_branch == opts.base_branch
+            && (opts.min_clean_streak.is_none() || entry.min_clean_streak == opts.min_clean_streak)
+            && info_signals(&git_dir, entry.info.upstream.as_deref()).as_ref()
+                == Some(&entry.signals)
+    });
+    let mut fresh_entry = None;
+    let info = if let Some(entry) = cached {
+        row.info_cached = true;
+        row.default_branch = entry.default_branch.clone();
+        row.ahead_of_base = entry.ahead_of_base;
+        row.recent_on_base = entry.recent_on_base;
+        entry.info.clone()
+    } else {
+        // Taken before reading, so a change made meanwhile invalidates the entry.
+        let signals = info_signals(&git_dir, None);
+        // Only a failed read pays for telling a non-repo apart from a broken one.
+        let Some(info) = get_local_info(
+            &row.directory,
+            opts.check_upstream,
+            !opts.brief,
+            opts.diffstat,
+        ) else {
+            if !is_git_repo(&resolved) {
+                return row.with_error("Not a Git repository");
+            }
+            return row.with_error("Failed to get git info");
+        };
+        let caching = opts.fresh_info.is_some() && !opts.brief;
+        if let Some(mut signals) = signals.filter(|_| caching) {
+            if let Some(upstream) = &info.upstream {
+                signals.push(mtime_nanos(&git_dir.join("refs/remotes").join(upstream)));
+            }
+            // Written once the base branch is known too, below.
+            fresh_entry = Some(InfoCacheEntry {
+                checked_at: now_secs(),
+                check_upstream: opts.check_upstream,
+                signals,
+                info: info.clone(),
+                diffstat: opts.diffstat,
+                base_branch: opts.base_branch.clone(),
+                default_branch: None,
+                ahead_of_base: None,
+                min_clean_streak: opts.min_clean_streak,
+                recent_on_base: false,
+            });
+        }
+        info
+    };
+    let mut row = RepoResult {
+        branch: Some(info.branch),
+        last_commit: Some(info.last_commit),
+        last_commit_at: info.last_commit_at,
+        clean: Some(info.clean),
+        conflicted: info.conflicted,
+        ahead: info.ahead,
+        upstream: info.upstream,
+        is_merge_commit: info.is_merge_commit,
+        last_fetch_at: info.last_fetch_at,
+        lfs_enabled: info.lfs_enabled,
+        bisect_state: info.bisect_state,
+        precommit_configured: info.precommit_configured,
+        stale_tracking_count: info.stale_tracking_count,
+        envrc_present: info.envrc_present,
+        has_contributing: info.has_contributing,
+        has_readme: info.has_readme,
+        diffstat: info.diffstat,
+        ..row
+    };
+
+    if opts.show_size {
+        row.size = disk_size(&row.directory, opts.size_scope);
+    }
+    if opts.icons {
+        row.language = detect_language(&row.directory);
+    }
+    if !row.info_cached && !opts.brief {
+        row.default_branch = default_branch(&row.directory, opts.base_branch.as_deref());
+        if let (Some(base), Some(branch)) = (&row.default_branch, &row.branch) {
+            row.ahead_of_base = ahead_of_base(&row.directory, base, branch);
+        }
+        if let (Some(base), Some(n)) = (&row.default_branch, opts.min_clean_streak) {
+            row.recent_on_base = recent_on_base(&row.directory, base, n);
+        }
+    }
+    if let (Some(fresh), Some(mut entry)) = (&opts.fresh_info, fresh_entry) {
+        entry.default_branch = row.default_branch.clone();
+        entry.ahead_of_base = row.ahead_of_base;
+        entry.recent_on_base = row.recent_on_base;
+        fresh
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(row.directory.clone(), entry);
+    }
+    // With nothing to push, a push can't need forcing.
+    if opts.check_force_push && !opts.offline && row.ahead.is_some_and(|n| n > 0) {
+        row.needs_force_push = needs_force_push(&row.directory);
+    }
+    if opts.show_contributors {
+        row.contributors = contributor_count(&row.directory);
+    }
+    if opts.remote_url {
+        let remote = row
+            .upstream
+            .as_deref()
+            .and_then(|upstream| upstream.split_once('/'))
+            .map_or("origin", |(remote, _)| remote);
+        row.remote_url = git_cmd(&row.directory, &["remote", "get-url", remote]);
+    }
+    if opts.security {
+        row.signature = head_signature(&row.directory);
+    }
+    if opts.show_lfs && row.lfs_enabled {
+        row.lfs_storage_bytes = lfs_storage_bytes(&row.directory);
+        row.has_lfsconfig = Path::new(&row.directory).join(".lfsconfig").is_file();
+    }
+    // Work in progress would skew the count, so dirty trees are left out.
+    if let Some(known) = opts
+        .todo_counts
+        .as_ref()
+        .filter(|_| row.clean == Some(true))
+    {
+        row.head = git_cmd(&row.directory, &["rev-parse", "HEAD"]);
+        row.todos = match (&row.head, known.get(&row.directory)) {
+            (Some(head), Some((seen, count))) if head == seen => Some(*count),
+            (Some(_), _) => todo_count(&row.directory),
+            (None, _) => None,
+        };
+    }
+    row
+}
+
+/// The branch work is meant to land on: `configured` (`--base-branch`), else
+/// what `origin/HEAD` points at, else `main` or `master` if one exists locally.
+fn default_branch(dir: &str, configured: Option<&str>) -> Option<String> {
+    if let Some(name) = configured {
+        return Some(name.to_string());
+    }
+    if let Some(remote_head) = git_cmd(
+        dir,
+        &[
+            "symbolic-ref",
+            "--quiet",
+            "--short",
+            "refs/remotes/origin/HEAD",
+        ],
+    ) {
+        return Some(
+            remote_head
+                .strip_prefix("origin/")
+                .unwrap_or(&remote_head)
+                .to_string(),
+        );
+    }
+    ["main", "master"]
+        .into_iter()
+        .find(|name| local_branch_exists(dir, name))
+        .map(str::to_string)
+}
+
+fn local_branch_exists(dir: &str, name: &str) -> bool {
+    git_cmd(
+        dir,
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("refs/heads/{name}"),
+        ],
+    )
+    .is_some()
+}
+
+/// Commits on HEAD that `base` doesn't have. `None` when `base` doesn't exist
+/// locally or HEAD is on it.
+fn ahead_of_base(dir: &str, base: &str, branch: &str) -> Option<u32> {
+    if branch == base || !local_branch_exists(dir, base) {
+        return None;
+    }
+    git_cmd(dir, &["rev-list", "--count", &format!("{base}..HEAD")])?
+        .parse()
+        .ok()
+}
+
+/// Whether the `n` newest commits across local branches are all on `base`: the
+/// same commits as its own `n` newest, so none were made on another branch since.
+fn recent_on_base(dir: &str, base: &str, n: u32) -> bool {
+    let newest = |rev: &str| git_cmd(dir, &["rev-list", &format!("--max-count={n}"), rev]);
+    newest("--branches").is_some_and(|all| newest(&format!("refs/heads/{base}")) == Some(all))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    JavaScript,
+    Go,
+    Python,
+    Ruby,
+    Java,
+    Elixir,
+    Php,
+    Swift,
+    Dart,
+    Nix,
+    Lua,
+    Shell,
+}
+
+impl Language {
+    fn from_marker(file_name: &str) -> Option<Self> {
+        match file_name {
+            "Cargo.toml" => Some(Language::Rust),
+            "package.json" => Some(Language::JavaScript),
+            "go.mod" => Some(Language::Go),
+            "pyproject.toml" | "setup.py" | "requirements.txt" => Some(Language::Python),
+            "Gemfile" => Some(Language::Ruby),
+            "pom.xml" | "build.gradle" | "build.gradle.kts" => Some(Language::Java),
+            "mix.exs" => Some(Language::Elixir),
+            "composer.json" => Some(Language::Php),
+            "Package.swift" => Some(Language::Swift),
+            "pubspec.yaml" => Some(Language::Dart),
+            "flake.nix" | "default.nix" => Some(Language::Nix),
+            _ => None,
+        }
+    }
+
+    fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "rs" => Some(Language::Rust),
+            "js" | "mjs" | "ts" | "tsx" | "jsx" => Some(Language::JavaScript),
+            "go" => Some(Language::Go),
+            "py" => Some(Language::Python),
+            "rb" => Some(Language::Ruby),
+            "java" | "kt" => Some(Language::Java),
+            "ex" | "exs" => Some(Language::Elixir),
+            "php" => Some(Language::Php),
+            "swift" => Some(Language::Swift),
+            "dart" => Some(Language::Dart),
+            "nix" => Some(Language::Nix),
+            "lua" => Some(Language::Lua),
+            "sh" | "bash" | "zsh" => Some(Language::Shell),
+            _ => None,
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Language::Rust => "\u{e7a8}",
+            Language::JavaScript => "\u{e718}",
+            Language::Go => "\u{e627}",
+            Language::Python => "\u{e73c}",
+            Language::Ruby => "\u{e739}",
+            Language::Java => "\u{e738}",
+            Language::Elixir => "\u{e62d}",
+            Language::Php => "\u{e73d}",
+            Language::Swift => "\u{e755}",
+            Language::Dart => "\u{e798}",
+            Language::Nix => "\u{f313}",
+            Language::Lua => "\u{e620}",
+            Language::Shell => "\u{f489}",
+        }
+    }
+}
+
+/// Generic git icon for repos whose language can't be guessed.
+const NERD_GIT_ICON: &str = "\u{f1d3}";
+
+/// Guesses a repo's language from its top-level listing only: a build manifest
+/// wins, otherwise the most common recognised file extension.
+fn detect_language(dir: &str) -> Option<Language> {
+    let entries: Vec<String> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+
+    if let Some(language) = entries.iter().find_map(|name| Language::from_marker(name)) {
+        return Some(language);
+    }
+
+    let mut counts: Vec<(Language, usize)> = Vec::new();
+    for language in entries
+        .iter()
+        .filter_map(|name| name.rsplit_once('.').map(|(_, ext)| ext))
+        .filter_map(Language::from_extension)
+    {
+        match counts.iter_mut().find(|(l, _)| *l == language) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((language, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(_, n)| n)
+        .map(|(language, _)| language)
+}
+
+/// Modification times of the files a commit, checkout, stage, fetch, push or
+/// bisect touches and of `origin/HEAD`, plus the upstream's ref when there is one.
+/// `None` when the repo has no plain `.git` directory (e.g. a linked worktree),
+/// which is never cached.
+/// Nothing here sees the work tree, so an unstaged edit waits out `cache_ttl`.
+fn info_signals(git_dir: &Path, upstream: Option<&str>) -> Option<Vec<u64>> {
+    if !git_dir.is_dir() {
+        return None;
+    }
+    let mut signals: Vec<u64> = [
+        "HEAD",
+        "index",
+        "refs/heads",
+        "packed-refs",
+        "FETCH_HEAD",
+        "BISECT_LOG",
+        "refs/remotes/origin/HEAD",
+    ]
+    .into_iter()
+    .map(|name| mtime_nanos(&git_dir.join(name)))
+    .collect();
+    if let Some(upstream) = upstream {
+        signals.push(mtime_nanos(&git_dir.join("refs/remotes").join(upstream)));
+    }
+    Some(signals)
+}
+
+/// 0 for a file that doesn't exist.
+fn mtime_nanos(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+/// How the git commands on one thread run; see `with_git_options`.
+#[derive(Debug, Clone, Copy, Default)]
+struct GitOptions {
+    /// When the repo this thread is working on runs out of time; see `run_bounded`.
+    /// `None` means no limit.
+    deadline: Option<Instant>,
+    /// `--load-envrc`; see `git`.
+    load_envrc: bool,
+}
+
+thread_local! {
+    static GIT_OPTIONS: std::cell::Cell<GitOptions> = const {
+        std::cell::Cell::new(GitOptions {
+            deadline: None,
+            load_envrc: false,
+        })
+    };
+    /// Set once a command is cut off by the deadline.
+    static TIMED_OUT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Runs `f` with every `git` and `run_bounded` command on this thread following
+/// `options`, and reports whether any of them hit the deadline.
+fn with_git_options<T>(options: GitOptions, f: impl FnOnce() -> T) -> (T, bool) {
+    GIT_OPTIONS.set(options);
+    TIMED_OUT.set(false);
+    let result = f();
+    GIT_OPTIONS.set(GitOptions::default());
+    (result, TIMED_OUT.take())
+}
+
+/// `Command::output` with stdin closed that gives up at this thread's deadline:
+/// the child is killed and reaped, and the error is `TimedOut`. Output is read
+/// on helper threads so a chatty child can't stall on a full pipe.
+fn run_bounded(cmd: &mut Command) -> std::io::Result<Output> {
+    #[cfg(test)]
+    tests::record_command(cmd);
+    cmd.stdin(Stdio::null());
+    let Some(deadline) = GIT_OPTIONS.get().deadline else {
+        return cmd.output();
+    };
+    let timed_out = || {
+        TIMED_OUT.set(true);
+        std::io::Error::from(std::io::ErrorKind::TimedOut)
+    };
+    if Instant::now() >= deadline {
+        return Err(timed_out());
+    }
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let (tx, rx) = mpsc::channel();
+    let pipes: [(bool, Option<Box<dyn Read + Send>>); 2] = [
+        (true, child.stdout.take().map(|p| Box::new(p) as _)),
+        (false, child.stderr.take().map(|p| Box::new(p) as _)),
+    ];
+    for (is_stdout, pipe) in pipes {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            let _ = tx.send((is_stdout, buf));
+        });
+    }
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    for _ in 0..2 {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(wait) {
+            Ok((true, buf)) => stdout = buf,
+            Ok((false, buf)) => stderr = buf,
+            Err(_) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(timed_out());
+            }
+        }
+    }
+    Ok(Output {
+        status: child.wait()?,
+        stdout,
+        stderr,
+    })
+}
+
+fn is_git_repo(dir: &Path) -> bool {
+    run_bounded(Command::new("git").args([
+        "-C",
+        dir.to_str().unwrap_or(""),
+        "rev-parse",
+        "--git-dir",
+    ]))
+    .map(|o| o.status.success())
+    .unwrap_or(false)
+}
+
+/// `git -C <dir>`, ready for arguments. With `--load-envrc`, a repo with an
+/// `.envrc` gets `direnv exec <dir> git -C <dir>` instead, so hooks and
+/// helpers see the environment it sets up.
+fn git(dir: &str) -> Command {
+    let load_envrc = GIT_OPTIONS.get().load_envrc;
+    let mut cmd = if load_envrc && Path::new(dir).join(".envrc").is_file() {
+        let mut cmd = Command::new("direnv");
+        cmd.args(["exec", dir, "git"]);
+        cmd
+    } else {
+        Command::new("git")
+    };
+    cmd.args(["-C", dir]);
+    cmd
+}
+
+fn git_cmd(dir: &str, args: &[&str]) -> Option<String> {
+    run_bounded(git(dir).args(args))
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+}
+
+/// Local state of a work tree, read without touching the network.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitInfo {
+    pub branch: String,
+    /// Subject of HEAD.
+    pub last_commit: String,
+    /// Committer time of HEAD, seconds since the epoch.
+    pub last_commit_at: Option<u64>,
+    pub clean: bool,
+    pub conflicted: bool,
+    /// Commits on HEAD not yet on the upstream; `None` without an upstream.
+    pub ahead: Option<u32>,
+    pub upstream: Option<String>,
+    /// HEAD has more than one parent.
+    pub is_merge_commit: bool,
+    /// When `FETCH_HEAD` was last written, seconds since the epoch; `None` if never fetched.
+    pub last_fetch_at: Option<u64>,
+    /// `.gitattributes` routes some paths through the LFS filter.
+    #[serde(default)]
+    pub lfs_enabled: bool,
+    /// A `git bisect` is under way in this work tree.
+    #[serde(default)]
+    pub bisect_state: Option<BisectState>,
+    /// Whether the `pre-commit` hook is installed; `None` without a `.pre-commit-config.yaml`.
+    #[serde(default)]
+    pub precommit_configured: Option<bool>,
+    /// Local branches whose upstream was deleted on the remote and pruned away.
+    #[serde(default)]
+    pub stale_tracking_count: u32,
+    /// The work tree has an `.envrc` for direnv.
+    #[serde(default)]
+    pub envrc_present: bool,
+    /// The work tree has contribution guidelines: `CONTRIBUTING.md` or `.rst`,
+    /// at the top or under `.github/`.
+    #[serde(default)]
+    pub has_contributing: bool,
+    /// The work tree has a README of any extension.
+    #[serde(default)]
+    pub has_readme: bool,
+    /// Lines and files changed since HEAD, staged and unstaged; `None` when clean
+    /// or not asked for.
+    #[serde(default)]
+    pub diffstat: Option<DiffStat>,
+}
+
+/// Progress of a `git bisect`, read from `BISECT_LOG`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BisectState {
+    /// Commits marked good, including those given to `git bisect start`.
+    pub good: u32,
+    /// Commits marked bad, including the one given to `git bisect start`.
+    pub bad: u32,
+    /// Commits marked since the start, skips included.
+    pub steps_done: u32,
+    /// `steps_done` plus git's estimate of the steps left; 0 until there's both a
+    /// good and a bad commit.
+    pub steps_estimated: u32,
+}
+
+/// Totals from `git diff --shortstat`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffStat {
+    pub files: u32,
+    pub insertions: u32,
+    pub deletions: u32,
+}
+
+impl std::fmt::Display for DiffStat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "+{} \u{2212}{} across {} {}",
+            self.insertions,
+            self.deletions,
+            self.files,
+            if self.files == 1 { "file" } else { "files" }
+        )
+    }
+}
+
+/// Reads a repo's branch, last commit, work tree state and upstream.
+/// `None` if `directory` isn't a readable Git work tree.
+pub fn get_git_info(directory: &Path) -> Option<GitInfo> {
+    get_local_info(directory.to_str()?, true, true, true)
+}
+
+/// Without `read_commit`, HEAD's subject, time and parents are left empty and
+/// `git log` isn't run; without `read_diffstat`, neither is `git diff`.
+fn get_local_info(
+    dir: &str,
+    check_upstream: bool,
+    read_commit: bool,
+    read_diffstat: bool,
+) -> Option<GitInfo> {
+    // FETCH_HEAD is rewritten by every fetch.
+    let combined = fetch_head_path(Path::new(dir))
+        .and_then(|fetch_head| Some((branch_status(dir)?, fetch_head)));
+    let (status, fetch_head) = match combined {
+        Some(found) => found,
+        None => legacy_branch_status(dir, check_upstream)?,
+    };
+    let last_fetch_at = std::fs::metadata(fetch_head)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    let log_line = if read_commit {
+        git_cmd(dir, &HEAD_LOG_ARGS)?
+    } else {
+        String::new()
+    };
+    let (last_commit_at, is_merge_commit, last_commit) = parse_head_log(&log_line);
+    let last_commit = last_commit.to_string();
+    let (ahead, upstream) = if check_upstream {
+        (status.ahead, status.upstream)
+    } else {
+        (None, None)
+    };
+    Some(GitInfo {
+        branch: status.branch,
+        last_commit,
+        last_commit_at,
+        clean: status.clean,
+        conflicted: status.conflicted,
+        ahead,
+        upstream,
+        is_merge_commit,
+        last_fetch_at,
+        lfs_enabled: uses_lfs(dir),
+        bisect_state: bisect_state(dir),
+        precommit_configured: precommit_configured(dir),
+        stale_tracking_count: if check_upstream && read_commit {
+            stale_tracking_count(dir)
+        } else {
+            0
+        },
+        envrc_present: Path::new(dir).join(".envrc").is_file(),
+        has_contributing: [
+            "CONTRIBUTING.md",
+            "CONTRIBUTING.rst",
+            ".github/CONTRIBUTING.md",
+        ]
+        .iter()
+        .any(|name| Path::new(dir).join(name).is_file()),
+        has_readme: has_readme(dir),
+        diffstat: if status.clean || !read_diffstat {
+            None
+        } else {
+            diffstat(dir)
+        },
+    })
+}
+
+/// Unstaged plus staged changes. A file with both counts twice, which is fine
+/// for a sense of size; `None` when only untracked files make the tree dirty.
+fn diffstat(dir: &str) -> Option<DiffStat> {
+    let unstaged = parse_shortstat(&git_cmd(dir, &["diff", "--shortstat"])?);
+    let staged = parse_shortstat(&git_cmd(dir, &["diff", "--cached", "--shortstat"])?);
+    let total = DiffStat {
+        files: unstaged.files + staged.files,
+        insertions: unstaged.insertions + staged.insertions,
+        deletions: unstaged.deletions + staged.deletions,
+    };
+    (total != DiffStat::default()).then_some(total)
+}
+
+/// Reads `3 files changed, 10 insertions(+), 2 deletions(-)`, where git leaves
+/// out whichever counts are zero and prints nothing at all for an empty diff.
+fn parse_shortstat(out: &str) -> DiffStat {
+    let mut stat = DiffStat::default();
+    for part in out.trim().split(',') {
+        let Some((count, label)) = part.trim().split_once(' ') else {
+            continue;
+        };
+        let Ok(count) = count.parse() else {
+            continue;
+        };
+        if label.starts_with("file") {
+            stat.files = count;
+        } else if label.starts_with("insertion") {
+            stat.insertions = count;
+        } else if label.starts_with("deletion") {
+            stat.deletions = count;
+        }
+    }
+    stat
+}
+
+/// `README`, `README.md`, `readme.rst` and so on, at the top of the work tree.
+fn has_readme(dir: &str) -> bool {
+    std::fs::read_dir(dir).is_ok_and(|entries| {
+        entries.flatten().any(|entry| {
+            let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+            (name == "readme" || name.starts_with("readme.")) && entry.path().is_file()
+        })
+    })
+}
+
+const HEAD_LOG_ARGS: [&str; 3] = ["log", "-1", "--pretty=%ct %P%x00%s"];
+
+/// Splits `HEAD_LOG_ARGS` output into HEAD's commit time, whether it's a
+/// merge, and its subject.
+fn parse_head_log(log_line: &str) -> (Option<u64>, bool, &str) {
+    let (meta, subject) = log_line.split_once('\0').unwrap_or((log_line, ""));
+    let (timestamp, parents) = meta.split_once(' ').unwrap_or((meta, ""));
+    (
+        timestamp.parse::<u64>().ok(),
+        parents.split_whitespace().count() > 1,
+        subject,
+    )
+}
+
+/// The branch and work tree half of `GitInfo`.
+struct BranchStatus {
+    /// `HEAD` when detached, as `rev-parse --abbrev-ref HEAD` prints it.
+    branch: String,
+    clean: bool,
+    conflicted: bool,
+    ahead: Option<u32>,
+    upstream: Option<String>,
+}
+
+/// Reads branch, upstream, ahead count and work tree state from a single
+/// `git status --porcelain=v2 --branch`, which needs git 2.11 or later. `None`
+/// if git can't produce it or the repo has no commits yet.
+fn branch_status(dir: &str) -> Option<BranchStatus> {
+    parse_branch_status(&git_cmd(dir, &BRANCH_STATUS_ARGS)?)
+}
+
+const BRANCH_STATUS_ARGS: [&str; 3] = ["status", "--porcelain=v2", "--branch"];
+
+fn parse_branch_status(output: &str) -> Option<BranchStatus> {
+    let mut status = BranchStatus {
+        branch: String::new(),
+        clean: true,
+        conflicted: false,
+        ahead: None,
+        upstream: None,
+    };
+    for line in output.lines() {
+        let Some(header) = line.strip_prefix("# ") else {
+            status.clean = false;
+            status.conflicted |= line.starts_with("u ");
+            continue;
+        };
+        match header.split_once(' ')? {
+            ("branch.oid", "(initial)") => return None,
+            ("branch.head", "(detached)") => status.branch = "HEAD".to_string(),
+            ("branch.head", head) => status.branch = head.to_string(),
+            ("branch.upstream", upstream) => status.upstream = Some(upstream.to_string()),
+            ("branch.ab", counts) => {
+                let ahead = counts.split_whitespace().next()?.strip_prefix('+')?;
+                status.ahead = Some(ahead.parse().ok()?);
+            }
+            _ => {}
+        }
+    }
+    // An upstream that no longer exists is still named, but without counts;
+    // `@{u}` doesn't resolve then either.
+    if status.ahead.is_none() {
+        status.upstream = None;
+    }
+    (!status.branch.is_empty()).then_some(status)
+}
+
+/// One call per field, for git older than 2.11 or layouts `fetch_head_path` can't follow.
+fn legacy_branch_status(dir: &str, check_upstream: bool) -> Option<(BranchStatus, PathBuf)> {
+    // `--git-path` also finds FETCH_HEAD in worktrees.
+    let rev_parse = git_cmd(
+        dir,
+        &[
+            "rev-parse",
+            "--git-path",
+            "FETCH_HEAD",
+            "--abbrev-ref",
+            "HEAD",
+        ],
+    )?;
+    let (fetch_head, branch) = rev_parse.split_once('\n')?;
+    let status_out = git_cmd(dir, &["status", "--porcelain"])?;
+    let (ahead, upstream) = if check_upstream {
+        let ahead = git_cmd(dir, &["rev-list", "--count", "@{u}..HEAD"])
+            .and_then(|s| s.parse::<u32>().ok());
+        let upstream = git_cmd(
+            dir,
+            &["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"],
+        );
+        (ahead, upstream)
+    } else {
+        (None, None)
+    };
+    let status = BranchStatus {
+        branch: branch.to_string(),
+        clean: status_out.is_empty(),
+        conflicted: status_out.lines().any(is_unmerged_status),
+        ahead,
+        upstream,
+    };
+    Some((status, Path::new(dir).join(fetch_head)))
+}
+
+/// The work tree's own git dir: `.git`, or the one a linked worktree's `.git`
+/// file points to.
+fn work_tree_git_dir(dir: &Path) -> Option<PathBuf> {
+    let dot_git = dir.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let pointer = std::fs::read_to_string(&dot_git).ok()?;
+    Some(dir.join(pointer.strip_prefix("gitdir:")?.trim()))
+}
+
+/// The git dir shared by all of a repo's worktrees, which a linked worktree
+/// names in its `.git` file and then its `commondir`.
+fn common_git_dir(dir: &Path) -> Option<PathBuf> {
+    let git_dir = work_tree_git_dir(dir)?;
+    Some(match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(relative) => git_dir.join(relative.trim()),
+        Err(_) => git_dir,
+    })
+}
+
+/// Where fetches write FETCH_HEAD without asking git: the common git dir.
+fn fetch_head_path(dir: &Path) -> Option<PathBuf> {
+    Some(common_git_dir(dir)?.join("FETCH_HEAD"))
+}
+
+/// Local branches `git branch -vv` would mark `: gone]`: their upstream was
+/// deleted on the remote, and a `fetch --prune` removed the tracking ref.
+fn stale_tracking_count(dir: &str) -> u32 {
+    git_cmd(
+        dir,
+        &["for-each-ref", "--format=%(upstream:track)", "refs/heads"],
+    )
+    .map_or(0, |out| {
+        out.lines().filter(|line| *line == "[gone]").count() as u32
+    })
+}
+
+/// `None` without a `.pre-commit-config.yaml`; otherwise whether `pre-commit
+/// install` has put an executable hook in place.
+fn precommit_configured(dir: &str) -> Option<bool> {
+    if !Path::new(dir).join(".pre-commit-config.yaml").is_file() {
+        return None;
+    }
+    let hook = common_git_dir(Path::new(dir))?.join("hooks/pre-commit");
+    let Ok(meta) = std::fs::metadata(hook) else {
+        return Some(false);
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    Some(meta.is_file())
+}
+
+/// `None` unless `BISECT_LOG` exists, so only a repo mid-bisect runs git here.
+/// Every mark is logged as a `# <term>: [<commit>] <subject>` comment, with the
+/// terms from `BISECT_TERMS` (`bad` and `good` unless the session renamed them).
+fn bisect_state(dir: &str) -> Option<BisectState> {
+    let git_dir = work_tree_git_dir(Path::new(dir))?;
+    let log = std::fs::read_to_string(git_dir.join("BISECT_LOG")).ok()?;
+    let terms = std::fs::read_to_string(git_dir.join("BISECT_TERMS")).unwrap_or_default();
+    let mut terms = terms.lines();
+    let bad_term = terms.next().unwrap_or("bad");
+    let good_term = terms.next().unwrap_or("good");
+    let mut state = BisectState {
+        good: 0,
+        bad: 0,
+        steps_done: 0,
+        steps_estimated: 0,
+    };
+    let mut started = false;
+    let mut found = false;
+    for line in log.lines() {
+        started |= line.starts_with("git bisect start");
+        found |= line.starts_with(&format!("# first {bad_term} commit"));
+        let Some((term, _)) = line.strip_prefix("# ").and_then(|l| l.split_once(": [")) else {
+            continue;
+        };
+        if term == bad_term {
+            state.bad += 1;
+        } else if term == good_term {
+            state.good += 1;
+        } else if term != "skip" {
+            continue;
+        }
+        if started {
+            state.steps_done += 1;
+        }
+    }
+    if found {
+        state.steps_estimated = state.steps_done;
+    } else if state.good > 0 && state.bad > 0 {
+        let vars = git_cmd(
+            dir,
+            &[
+                "rev-list",
+                "--bisect-vars",
+                &format!("refs/bisect/{bad_term}"),
+                "--not",
+                &format!("--glob=refs/bisect/{good_term}-*"),
+            ],
+        );
+        if let Some(left) = vars.as_deref().and_then(|vars| {
+            vars.lines()
+                .find_map(|line| line.strip_prefix("bisect_steps="))
+                .and_then(|n| n.parse::<u32>().ok())
+        }) {
+            // The commit checked out now is a step of its own.
+            state.steps_estimated = state.steps_done + left + 1;
+        }
+    }
+    Some(state)
+}
+
+/// Reads the top-level `.gitattributes` rather than asking git-lfs, which may
+/// not be installed.
+fn uses_lfs(dir: &str) -> bool {
+    std::fs::read_to_string(Path::new(dir).join(".gitattributes")).is_ok_and(|attributes| {
+        attributes
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .any(|line| line.split_whitespace().any(|attr| attr == "filter=lfs"))
+    })
+}
+
+/// Sums the sizes `git lfs ls-files --size` prints after each file, e.g. `(1.2 MB)`.
+/// `None` when git-lfs isn't installed or fails.
+fn lfs_storage_bytes(dir: &str) -> Option<u64> {
+    let output = run_bounded(git(dir).args(["lfs", "ls-files", "--size"]))
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (_, size) = line.trim_end().strip_suffix(')')?.rsplit_once('(')?;
+                DiskSize::parse(size).map(|size| size.bytes)
+            })
+            .sum(),
+    )
+}
+
+/// Splits a remote URL into its host and `owner/repo` path, without `.git`.
+/// Understands scp-like `git@host:owner/repo.git`, `ssh://`, `git://` and
+/// `http(s)://` URLs; credentials and ports are dropped. Local paths and
+/// `file://` URLs have no host, so are `None`.
+fn remote_host_path(remote: &str) -> Option<(&str, &str)> {
+    let remote = remote.trim();
+    let (authority, path) = match remote.split_once("://") {
+        Some((scheme, rest)) => {
+            let known = ["ssh", "git+ssh", "ssh+git", "git", "http", "https"];
+            if !known.contains(&scheme) {
+                return None;
+            }
+            rest.split_once('/')?
+        }
+        // scp-like syntax has a colon before any slash; otherwise it's a path.
+        None => {
+            let (authority, path) = remote.split_once(':')?;
+            // A single letter is a Windows drive, as in `C:/src/repo`.
+            if authority.contains('/') || authority.len() == 1 {
+                return None;
+            }
+            (authority, path)
+        }
+    };
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    (!host.is_empty() && !path.is_empty()).then_some((host, path))
+}
+
+/// The web page for a remote, e.g. `git@github.com:user/repo.git` becomes
+/// `https://github.com/user/repo`. Works the same for GitHub, GitLab,
+/// Bitbucket or any forge served from `https://<host>/`; see
+/// `Config::browser_url` for ones that aren't.
+pub fn ssh_to_https_url(remote: &str) -> Option<String> {
+    let (host, path) = remote_host_path(remote)?;
+    Some(format!("https://{host}/{path}"))
+}
+
+impl Config {
+    /// What loading the file raised (unknown section names, globs matching
+    /// nothing), left to the caller to show.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// `ssh_to_https_url`, except that hosts listed in `git_hosts` use their
+    /// `https_base`.
+    pub fn browser_url(&self, remote: &str) -> Option<String> {
+        let (host, path) = remote_host_path(remote)?;
+        match self
+            .git_hosts
+            .iter()
+            .find(|h| h.domain.eq_ignore_ascii_case(host))
+        {
+            Some(git_host) => Some(format!(
+                "{}/{path}",
+                git_host.https_base.trim_end_matches('/')
+            )),
+            None => ssh_to_https_url(remote),
+        }
+    }
+}
+
+/// What `jiancha browse` links to inside a repo's web page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BrowseTarget {
+    Repo,
+    Branch(String),
+    /// A full commit id.
+    Commit(String),
+}
+
+/// `page` (from `ssh_to_https_url`) deep-linked to a branch or commit, in the URL
+/// layout of GitHub, GitLab or Bitbucket, told apart by the page's host name.
+/// Other forges get the repo page itself.
+pub fn browse_url(page: &str, target: &BrowseTarget) -> String {
+    let page = page.trim_end_matches('/');
+    let host = page
+        .split_once("://")
+        .map_or(page, |(_, rest)| rest.split('/').next().unwrap_or(rest))
+        .to_ascii_lowercase();
+    let forge = ["github", "gitlab", "bitbucket"]
+        .into_iter()
+        .find(|name| host.contains(name));
+    // Branch names may contain URL syntax; `/` is left alone, as the forges expect.
+    let escape = |name: &str| {
+        name.replace('%', "%25")
+            .replace('#', "%23")
+            .replace('?', "%3F")
+    };
+    let suffix = match (forge, target) {
+        (_, BrowseTarget::Repo) | (None, _) => String::new(),
+        (Some("github"), BrowseTarget::Branch(branch)) => format!("/tree/{}", escape(branch)),
+        (Some("github"), BrowseTarget::Commit(id)) => format!("/commit/{id}"),
+        (Some("gitlab"), BrowseTarget::Branch(branch)) => format!("/-/tree/{}", escape(branch)),
+        (Some("gitlab"), BrowseTarget::Commit(id)) => format!("/-/commit/{id}"),
+        (Some(_), BrowseTarget::Branch(branch)) => format!("/src/{}", escape(branch)),
+        (Some(_), BrowseTarget::Commit(id)) => format!("/commits/{id}"),
+    };
+    format!("{page}{suffix}")
+}
+
+/// Asks the remote, via `git push --dry-run`, whether pushing the current branch
+/// would be rejected as a non-fast-forward (the histories diverged, usually after
+/// a local rebase). `None` if the dry run failed for some other reason.
+fn needs_force_push(dir: &str) -> Option<bool> {
+    let output = run_bounded(git(dir).args(["push", "--dry-run", "--porcelain"])).ok()?;
+    if output.status.success() {
+        return Some(false);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let rejected = |text: &str| {
+        text.lines()
+            .any(|line| line.contains("[rejected]") && line.contains("non-fast-forward"))
+    };
+    (rejected(&stdout) || rejected(&stderr)).then_some(true)
+}
+
+/// `%G?` is git's verdict on HEAD's signature and `%GF` the signing key's
+/// fingerprint. `U` (good, but the key isn't trusted) and `E` (the key is
+/// missing, so it can't be checked) both mean the key isn't in the local keyring.
+fn head_signature(dir: &str) -> Option<Signature> {
+    let output = git_cmd(dir, &["log", "-1", "--format=%G?%x00%GF"])?;
+    let (verdict, fingerprint) = output.split_once('\0')?;
+    Some(match verdict {
+        "N" => Signature::Unsigned,
+        "U" | "E" => Signature::UnknownKey,
+        "B" | "R" => Signature::Bad,
+        _ => Signature::Good {
+            fingerprint: fingerprint.to_string(),
+        },
+    })
+}
+
+/// One line per author in `git shortlog -s`. Empty repos have no HEAD to walk.
+fn contributor_count(dir: &str) -> Option<u32> {
+    let output = run_bounded(git(dir).args(["shortlog", "-s", "HEAD"]))
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count() as u32,
+    )
+}
+
+/// Sums `git grep -c` over tracked text files. git grep exits 1 when nothing matches.
+fn todo_count(dir: &str) -> Option<u32> {
+    let output =
+        run_bounded(git(dir).args(["grep", "-I", "-c", "-e", "TODO", "-e", "FIXME"])).ok()?;
+    match output.status.code() {
+        Some(0) => Some(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| line.rsplit_once(':')?.1.parse::<u32>().ok())
+                .sum(),
+        ),
+        Some(1) => Some(0),
+        _ => None,
+    }
+}
+
+fn is_unmerged_status(line: &str) -> bool {
+    matches!(
+        line.get(..2),
+        Some("DD" | "AU" | "UD" | "UA" | "DU" | "AA" | "UU")
+    )
+}
+
+impl DiskSize {
+    /// Parses `du -h` style sizes such as `0`, `512`, `4.0K`, `1.2M`, `3G`.
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let value: f64 = number.parse().ok()?;
+        let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KI" => 1 << 10,
+            "M" | "MB" | "MI" => 1 << 20,
+            "G" | "GB" | "GI" => 1 << 30,
+            "T" | "TB" | "TI" => 1 << 40,
+            "P" | "PB" | "PI" => 1 << 50,
+            _ => return None,
+        };
+        Some(DiskSize {
+            bytes: (value * multiplier as f64).round() as u64,
+        })
+    }
+}
+
+impl std::fmt::Display for DiskSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+        if self.bytes < 1024 {
+            return write!(f, "{}B", self.bytes);
+        }
+        let mut value = self.bytes as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        if value < 10.0 {
+            write!(f, "{:.1}{}", value, UNITS[unit])
+        } else {
+            write!(f, "{:.0}{}", value, UNITS[unit])
+        }
+    }
+}
+
+fn disk_size(dir: &str, scope: SizeScope) -> Option<DiskSize> {
+    let target = match scope {
+        SizeScope::Git => ".git",
+        SizeScope::Worktree => ".",
+    };
+    run_bounded(Command::new("du").args(["-sh", target]).current_dir(dir))
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .and_then(|s| s.split_whitespace().next().and_then(DiskSize::parse))
+}
+
+impl DateFormat {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "relative" => Some(DateFormat::Relative),
+            "iso" => Some(DateFormat::Iso),
+            "rfc" => Some(DateFormat::Rfc),
+            "locale" => Some(DateFormat::Locale),
+            _ => None,
+        }
+    }
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn format_relative(age_secs: u64) -> String {
+    const UNITS: [(u64, &str); 6] = [
+        (365 * 24 * 3600, "year"),
+        (30 * 24 * 3600, "month"),
+        (7 * 24 * 3600, "week"),
+        (24 * 3600, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+    ];
+    for (secs, unit) in UNITS {
+        let n = age_secs / secs;
+        if n >= 1 {
+            let plural = if n == 1 { "" } else { "s" };
+            return format!("{n} {unit}{plural} ago");
+        }
+    }
+    "just now".to_string()
+}
+
+fn format_locale_date(timestamp: u64) -> Option<String> {
+    // GNU date takes `-d @ts`, BSD date takes `-r ts`.
+    [
+        vec!["-d".to_string(), format!("@{timestamp}"), "+%x".to_string()],
+        vec!["-r".to_string(), timestamp.to_string(), "+%x".to_string()],
+    ]
+    .iter()
+    .find_map(|args| {
+        Command::new("date")
+            .args(args)
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .and_then(|o| String::from_utf8(o.stdout).ok())
+            .map(|s| s.trim().to_string())
+    })
+}
+
+/// Local time of day, e.g. `14:02:31`, from `date` as nothing here knows the
+/// time zone; UTC with a `Z` when `date` isn't available.
+fn clock_time(timestamp: u64) -> String {
+    Command::new("date")
+        .arg("+%H:%M:%S")
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_else(|| {
+            let secs = timestamp % 86_400;
+            format!(
+                "{:02}:{:02}:{:02}Z",
+                secs / 3600,
+                secs % 3600 / 60,
+                secs % 60
+            )
+        })
+}
+
+fn format_date(timestamp: u64, now: u64, fmt: DateFormat) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = (timestamp / 86_400) as i64;
+    let secs_of_day = timestamp % 86_400;
+    let (year, month, day) = civil_from_days(days);
+
+    match fmt {
+        DateFormat::Relative => format_relative(now.saturating_sub(timestamp)),
+        DateFormat::Iso => format!(
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+            secs_of_day / 3600,
+            secs_of_day % 3600 / 60,
+            secs_of_day % 60
+        ),
+        DateFormat::Rfc => format!(
+            "{}, {day:02} {} {year:04}",
+            WEEKDAYS[days.rem_euclid(7) as usize],
+            MONTHS[month as usize - 1]
+        ),
+        DateFormat::Locale => format_locale_date(timestamp)
+            .unwrap_or_else(|| format_date(timestamp, now, DateFormat::Iso)),
+    }
+}
+
+/// What one fetch did.
+struct FetchOutcome {
+    status: FetchStatus,
+    /// Ref changes from fetch's summary; only read with `update_all`.
+    updates: Vec<RefUpdate>,
+    /// The last `fatal:` or `error:` line of a failed fetch.
+    error: Option<String>,
+}
+
+impl FetchOutcome {
+    fn failed(error: Option<String>) -> Self {
+        FetchOutcome {
+            status: FetchStatus::Error,
+            updates: Vec::new(),
+            error,
+        }
+    }
+}
+
+/// The last `fatal:` or `error:` line git wrote: why a command failed.
+fn last_git_error(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .rev()
+        .find(|line| line.starts_with("fatal:") || line.starts_with("error:"))
+        .map(str::to_string)
+}
+
+/// `git fetch --quiet` from the upstream's remote, or with `update_all` (the
+/// `fetch` subcommand) `git fetch --all --prune`, reading its ref summary.
+fn run_git_fetch(dir: &str, update_all: bool) -> FetchOutcome {
+    let mut cmd = git(dir);
+    cmd.arg("fetch");
+    if update_all {
+        // The summary is only parsed in English.
+        cmd.args(["--all", "--prune"]).env("LC_ALL", "C");
+    } else {
+        cmd.arg("--quiet");
+    }
+    let output = match run_bounded(&mut cmd) {
+        Ok(output) => output,
+        Err(e) => return FetchOutcome::failed(Some(e.to_string())),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return FetchOutcome::failed(last_git_error(&stderr));
+    }
+
+    let status = match git_cmd(dir, &["rev-list", "--count", "HEAD..@{u}"]) {
+        Some(s) => match s.parse::<u32>() {
+            Ok(0) => FetchStatus::UpToDate,
+            Ok(n) => FetchStatus::Behind(n),
+            Err(_) => FetchStatus::Error,
+        },
+        None => FetchStatus::UpToDate,
+    };
+    FetchOutcome {
+        status,
+        updates: stderr.lines().filter_map(parse_ref_update).collect(),
+        error: None,
+    }
+}
+
+/// What `jiancha pull` did to a freshly fetched repo, for the Action column.
+/// It only ever fast-forwards a clean branch onto its upstream; anything else
+/// is `SKIPPED` with the reason.
+fn pull_repo(repo: &RepoResult, dry_run: bool) -> String {
+    let dir = repo.directory.as_str();
+    if repo.local_error.is_some() {
+        return "SKIPPED: error".into();
+    }
+    let behind = match repo.fetch_status {
+        FetchStatus::Behind(n) => n,
+        FetchStatus::Error => return "SKIPPED: fetch failed".into(),
+        // Nothing to compare against, so "UP TO DATE" would be a guess.
+        FetchStatus::Pending | FetchStatus::Skipped => return "SKIPPED: not fetched".into(),
+        FetchStatus::UpToDate => 0,
+    };
+    // Checked again here rather than trusting the scan, which may be cached.
+    if git_cmd(dir, &["symbolic-ref", "--quiet", "HEAD"]).is_none() {
+        return "SKIPPED: detached".into();
+    }
+    if repo.upstream.is_none() {
+        return "SKIPPED: no upstream".into();
+    }
+    match git_cmd(dir, &["status", "--porcelain"]) {
+        Some(status) if status.is_empty() => {}
+        Some(_) => return "SKIPPED: dirty".into(),
+        None => return "SKIPPED: error".into(),
+    }
+    if behind == 0 {
+        return "UP TO DATE".into();
+    }
+    let fast_forward = run_bounded(git(dir).args(["merge-base", "--is-ancestor", "HEAD", "@{u}"]))
+        .is_ok_and(|o| o.status.success());
+    if !fast_forward {
+        return "SKIPPED: diverged".into();
+    }
+    if dry_run {
+        return format!("WOULD PULL {behind}");
+    }
+    let old = git_cmd(dir, &["rev-parse", "--short", "HEAD"]).unwrap_or_default();
+    let merged = run_bounded(git(dir).args(["merge", "--ff-only", "--quiet", "@{u}"]))
+        .is_ok_and(|o| o.status.success());
+    if !merged {
+        return "FAILED".into();
+    }
+    let new = git_cmd(dir, &["rev-parse", "--short", "HEAD"]).unwrap_or_default();
+    format!("PULLED {behind} {old}\u{2192}{new}")
+}
+
+/// Why `jiancha push` leaves a repo alone, or how many commits it would push;
+/// `None` commits for a branch with no upstream yet (`--set-upstream`).
+fn push_plan(repo: &RepoResult, set_upstream: bool) -> Result<Option<u32>, &'static str> {
+    let dir = repo.directory.as_str();
+    if repo.local_error.is_some() {
+        return Err("error");
+    }
+    // Checked again here rather than trusting the scan, which may be cached.
+    if git_cmd(dir, &["symbolic-ref", "--quiet", "HEAD"]).is_none() {
+        return Err("detached");
+    }
+    match git_cmd(dir, &["status", "--porcelain"]) {
+        Some(status) if status.is_empty() => {}
+        Some(_) => return Err("dirty"),
+        None => return Err("error"),
+    }
+    if repo.upstream.is_none() {
+        return if set_upstream {
+            Ok(None)
+        } else {
+            Err("no upstream")
+        };
+    }
+    git_cmd(dir, &["rev-list", "--count", "@{u}..HEAD"])
+        .and_then(|n| n.parse().ok())
+        .map(Some)
+        .ok_or("error")
+}
+
+/// Runs `git push`, or `git push -u origin <branch>` for a new branch, and
+/// sums the outcome up for the Action column. Never forced.
+fn push_repo(repo: &RepoResult, ahead: Option<u32>) -> String {
+    let mut cmd = git(&repo.directory);
+    cmd.args(["push", "--quiet"]);
+    if ahead.is_none() {
+        cmd.args(["-u", "origin", repo.branch.as_deref().unwrap_or("HEAD")]);
+    }
+    // Fail instead of waiting for a password nobody will type; English for matching.
+    cmd.env("GIT_TERMINAL_PROMPT", "0").env("LC_ALL", "C");
+    let output = match run_bounded(&mut cmd) {
+        Ok(output) => output,
+        Err(_) => return "FAILED".into(),
+    };
+    if output.status.success() {
+        return match ahead {
+            Some(n) => format!("PUSHED {n}"),
+            None => "PUSHED -u origin".into(),
+        };
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let auth = [
+        "Authentication failed",
+        "Permission denied",
+        "could not read Username",
+        "terminal prompts disabled",
+        "returned error: 403",
+    ];
+    if stderr.contains("[rejected]") || stderr.contains("non-fast-forward") {
+        "REJECTED".into()
+    } else if auth.iter().any(|text| stderr.contains(text)) {
+        "AUTH FAILED".into()
+    } else {
+        "FAILED".into()
+    }
+}
+
+/// `jiancha push`: fills in every repo's Action, asking first with `--confirm`,
+/// and re-reads the repos it pushed so their rows are current.
+fn push_repos(
+    repos: &mut [RepoResult],
+    opts: &PushOpts,
+    collect_opts: &CollectOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut candidates = Vec::new();
+    for (idx, repo) in repos.iter_mut().enumerate() {
+        repo.action = Some(match push_plan(repo, opts.set_upstream) {
+            Err(reason) => format!("SKIPPED: {reason}"),
+            Ok(Some(0)) => "UP TO DATE".into(),
+            Ok(ahead) => {
+                candidates.push((idx, ahead));
+                match ahead {
+                    Some(n) => format!("WOULD PUSH {n}"),
+                    None => "WOULD PUSH -u origin".into(),
+                }
+            }
+        });
+    }
+    if opts.dry_run || candidates.is_empty() {
+        return Ok(());
+    }
+    if opts.confirm {
+        if !std::io::stdin().is_terminal() {
+            return Err("--confirm asks before pushing, so stdin must be a terminal".into());
+        }
+        for &(idx, ahead) in &candidates {
+            let repo = &repos[idx];
+            let what = match ahead {
+                Some(n) => format!(
+                    "{n} commit(s) to {}",
+                    repo.upstream.as_deref().unwrap_or("?")
+                ),
+                None => "new branch to origin".into(),
+            };
+            let branch = repo.branch.as_deref().unwrap_or("HEAD");
+            eprintln!("  {}/{} {branch}: {what}", repo.section, repo.repo_key);
+        }
+        eprint!("Push these {} repos? [y/N] ", candidates.len());
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            eprintln!("Nothing pushed.");
+            for &(idx, _) in &candidates {
+                repos[idx].action = Some("SKIPPED: declined".into());
+            }
+            return Ok(());
+        }
+    }
+    for (done, &(idx, ahead)) in candidates.iter().enumerate() {
+        collect_opts
+            .progress
+            .show("pushing", done + 1, candidates.len(), &repos[idx].directory);
+        let started = Instant::now();
+        let action = push_repo(&repos[idx], ahead);
+        if action.starts_with("PUSHED") {
+            // Re-read so the row no longer shows unpushed commits.
+            let repo = &repos[idx];
+            let dir = repo.directory.clone();
+            let row = collect_repo(&repo.section, &repo.repo_key, &dir, collect_opts);
+            repos[idx] = RepoResult {
+                scan_duration: repo.scan_duration,
+                fetch_status: repo.fetch_status.clone(),
+                clean_streak: repo.clean_streak,
+                ..row
+            };
+        }
+        repos[idx].scan_duration += started.elapsed();
+        repos[idx].action = Some(action);
+    }
+    collect_opts.progress.clear();
+    Ok(())
+}
+
+/// Why `jiancha gc` leaves a repo alone: git would refuse, or the repo is
+/// in the middle of something that repacking shouldn't race with.
+fn gc_skip_reason(repo: &RepoResult) -> Option<&'static str> {
+    if repo.local_error.is_some() {
+        return Some("error");
+    }
+    let Some(git_dir) = work_tree_git_dir(Path::new(&repo.directory)) else {
+        return Some("error");
+    };
+    if git_dir.join("rebase-merge").is_dir() || git_dir.join("rebase-apply").is_dir() {
+        Some("rebase in progress")
+    } else if git_dir.join("MERGE_HEAD").is_file() {
+        Some("merge in progress")
+    } else if git_dir.join("index.lock").exists() {
+        Some("index.lock held")
+    } else {
+        None
+    }
+}
+
+/// Everything git keeps for the repo, all worktrees' shared objects included.
+fn git_dir_size(dir: &str) -> Option<DiskSize> {
+    let git_dir = common_git_dir(Path::new(dir))?;
+    run_bounded(Command::new("du").arg("-sk").arg(git_dir))
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .and_then(|s| s.split_whitespace().next()?.parse::<u64>().ok())
+        .map(|kib| DiskSize { bytes: kib * 1024 })
+}
+
+/// `git maintenance run`, or `git gc --auto` on git older than 2.29. Neither is
+/// bounded by `--timeout`: repacking a big repo legitimately takes a while.
+fn gc_repo(dir: &str) -> String {
+    let before = git_dir_size(dir);
+    let started = Instant::now();
+    let mut output = git(dir)
+        .args(["maintenance", "run", "--quiet"])
+        .env("LC_ALL", "C")
+        .output();
+    if output.as_ref().is_ok_and(|o| {
+        String::from_utf8_lossy(&o.stderr).contains("'maintenance' is not a git command")
+    }) {
+        output = git(dir).args(["gc", "--auto", "--quiet"]).output();
+    }
+    if !output.is_ok_and(|o| o.status.success()) {
+        return "FAILED".into();
+    }
+    let took = started.elapsed().as_secs_f64();
+    match (before, git_dir_size(dir)) {
+        (Some(before), Some(after)) => format!("GC {before}\u{2192}{after} {took:.1}s"),
+        _ => format!("GC {took:.1}s"),
+    }
+}
+
+/// `jiancha gc`: fills in every repo's Action, running maintenance in up to
+/// `--jobs` repos at once.
+fn gc_repos(repos: &mut [RepoResult], dry_run: bool, collect_opts: &CollectOptions) {
+    let mut pending = Vec::new();
+    for (idx, repo) in repos.iter_mut().enumerate() {
+        repo.action = Some(match gc_skip_reason(repo) {
+            Some(reason) => format!("SKIPPED: {reason}"),
+            None => {
+                pending.push(idx);
+                match git_dir_size(&repo.directory) {
+                    Some(size) => format!("WOULD GC {size}"),
+                    None => "WOULD GC".into(),
+                }
+            }
+        });
+    }
+    if dry_run || pending.is_empty() {
+        return;
+    }
+    let total = pending.len();
+    let queue = Mutex::new(pending);
+    let done = Mutex::new(Vec::new());
+    let next = || queue.lock().unwrap_or_else(PoisonError::into_inner).pop();
+    let dirs: Vec<&str> = repos.iter().map(|repo| repo.directory.as_str()).collect();
+    thread::scope(|scope| {
+        for _ in 0..collect_opts.jobs.clamp(1, total) {
+            scope.spawn(|| {
+                while let Some(idx) = next() {
+                    let started = Instant::now();
+                    // No deadline: repacking a big repo can take minutes.
+                    let git_options = GitOptions {
+                        load_envrc: collect_opts.load_envrc,
+                        ..GitOptions::default()
+                    };
+                    let (action, _) = with_git_options(git_options, || gc_repo(dirs[idx]));
+                    let mut done = done.lock().unwrap_or_else(PoisonError::into_inner);
+                    done.push((idx, action, started.elapsed()));
+                    collect_opts
+                        .progress
+                        .show("collecting garbage", done.len(), total, dirs[idx]);
+                }
+            });
+        }
+    });
+    collect_opts.progress.clear();
+    for (idx, action, elapsed) in done.into_inner().unwrap_or_else(PoisonError::into_inner) {
+        repos[idx].scan_duration += elapsed;
+        repos[idx].action = Some(action);
+    }
+}
+
+/// Runs `exec`'s command in one repo, with `{path}` and `{name}` filled in.
+/// Streams straight to the terminal when `capture` is off.
+fn exec_repo(repo: &RepoResult, template: &[String], capture: bool) -> std::io::Result<Output> {
+    let argv: Vec<String> = template
+        .iter()
+        .map(|arg| {
+            arg.replace("{path}", &repo.directory)
+                .replace("{name}", &repo.repo_key)
+        })
+        .collect();
+    let mut cmd = Command::new(&argv[0]);
+    cmd.args(&argv[1..]).current_dir(&repo.directory);
+    if capture {
+        cmd.stdin(Stdio::null()).output()
+    } else {
+        cmd.status().map(|status| Output {
+            status,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+}
+
+/// `jiancha exec`: runs the command in every repo, each behind a `==> section/repo`
+/// line, and fills in the Action column with how it exited. One job at a time
+/// streams; more capture each repo's output and print it whole, so it can't
+/// interleave. Returns the worst exit status, 127 when the command can't be run.
+fn exec_repos(
+    repos: &mut [RepoResult],
+    opts: &ExecOpts,
+    jobs: usize,
+    render: &RenderOptions,
+) -> i32 {
+    let mut pending = Vec::new();
+    for (idx, repo) in repos.iter_mut().enumerate() {
+        if repo.local_error.is_some() {
+            repo.action = Some("SKIPPED: error".into());
+        } else {
+            pending.push(idx);
+        }
+    }
+    // Popped from the end, so reversed to go in table order.
+    pending.reverse();
+    let jobs = jobs.clamp(1, pending.len().max(1));
+    let capture = jobs > 1;
+    let queue = Mutex::new(pending);
+    let done = Mutex::new(Vec::new());
+    let failed = AtomicBool::new(false);
+    let next = || {
+        if opts.fail_fast && failed.load(Ordering::Relaxed) {
+            return None;
+        }
+        queue.lock().unwrap_or_else(PoisonError::into_inner).pop()
+    };
+    let shared: &[RepoResult] = repos;
+    let header = |repo: &RepoResult| {
+        let line = format!("==> {}/{}", repo.section, repo.repo_key);
+        if render.color {
+            render.theme.section_line(&line)
+        } else {
+            line
+        }
+    };
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while let Some(idx) = next() {
+                    let repo = &shared[idx];
+                    if !capture {
+                        println!("{}", header(repo));
+                    }
+                    let started = Instant::now();
+                    let result = exec_repo(repo, &opts.command, capture);
+                    let elapsed = started.elapsed();
+                    // The lock also keeps one repo's output together.
+                    let mut done = done.lock().unwrap_or_else(PoisonError::into_inner);
+                    if let (Ok(output), true) = (&result, capture) {
+                        println!("{}", header(repo));
+                        let _ = std::io::stdout().write_all(&output.stdout);
+                        let _ = std::io::stderr().write_all(&output.stderr);
+                    }
+                    let (action, code) = match result {
+                        Ok(output) => match output.status.code() {
+                            Some(0) => ("EXIT 0".to_string(), 0),
+                            Some(code) => (format!("EXIT {code}"), code),
+                            None => ("KILLED".to_string(), EXIT_ERROR),
+                        },
+                        Err(e) => {
+                            eprintln!(
+                                "{}/{}: {}: {e}",
+                                repo.section, repo.repo_key, opts.command[0]
+                            );
+                            ("FAILED".to_string(), EXIT_NOT_RUN)
+                        }
+                    };
+                    if code != 0 {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    done.push((idx, action, code, elapsed));
+                }
+            });
+        }
+    });
+    let mut worst = 0;
+    for (idx, action, code, elapsed) in done.into_inner().unwrap_or_else(PoisonError::into_inner) {
+        repos[idx].scan_duration += elapsed;
+        repos[idx].action = Some(action);
+        worst = worst.max(code);
+    }
+    for idx in queue.into_inner().unwrap_or_else(PoisonError::into_inner) {
+        repos[idx].action = Some("SKIPPED: fail-fast".into());
+    }
+    worst
+}
+
+/// Reads one line of fetch's ref summary: ` <flag> <summary> <from> -> <to>`,
+/// e.g. `   1a2b3c4..5d6e7f8  main -> origin/main`. Other lines, such as
+/// `From ...` or rejected refs, are `None`.
+fn parse_ref_update(line: &str) -> Option<RefUpdate> {
+    let mut chars = line.chars();
+    if chars.next()? != ' ' {
+        return None;
+    }
+    let flag = chars.next()?;
+    let rest = chars.as_str().strip_prefix(' ')?.trim_start();
+    let (summary, rest) = match rest.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']')?,
+        None => rest.split_once(char::is_whitespace)?,
+    };
+    let (_, to) = rest.split_once("-> ")?;
+    let name = to.split_whitespace().next()?.to_string();
+    match flag {
+        '*' => Some(RefUpdate::New(name)),
+        '-' => Some(RefUpdate::Deleted(name)),
+        ' ' | '+' => {
+            // Forced updates separate the hashes with `...`.
+            let (old, new) = summary.split_once("..")?;
+            Some(RefUpdate::Updated {
+                name,
+                old: old.to_string(),
+                new: new.trim_start_matches('.').to_string(),
+                forced: flag == '+',
+            })
+        }
+        _ => None,
+    }
+}
+
+fn config_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let config_dir = if let Ok(xdg) = std::env::var("XDG_CONFIG_HOME") {
+        PathBuf::from(xdg)
+    } else if let Ok(home) = std::env::var("HOME") {
+        PathBuf::from(home).join(".config")
+    } else {
+        return Err("HOME not set".into());
+    };
+    Ok(config_dir.join("jiancha"))
+}
+
+fn profiles_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(config_dir()?.join("profiles"))
+}
+
+fn valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn config_path(profile: Option<&str>) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match profile {
+        Some(name) => {
+            if !valid_profile_name(name) {
+                return Err(format!("Invalid profile name: {name:?}").into());
+            }
+            Ok(profiles_dir()?.join(format!("{name}.toml")))
+        }
+        None => Ok(config_dir()?.join("config.toml")),
+    }
+}
+
+/// Resolves the active profile: `--profile` wins over `$JIANCHA_PROFILE`.
+fn active_profile(args: &Args) -> Option<String> {
+    args.profile.clone().or_else(env_profile)
+}
+
+/// `$JIANCHA_PROFILE`, or `$JIAN_CHA_PROFILE` (the project's name spelled out)
+/// when that's the one set.
+fn env_profile() -> Option<String> {
+    ["JIANCHA_PROFILE", "JIAN_CHA_PROFILE"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|p| !p.is_empty())
+}
+
+/// Why a config file couldn't be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config location couldn't be worked out (no `$HOME`, bad profile name).
+    Path(String),
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse(Box<toml::de::Error>),
+    /// A glob matched nothing under `--strict`.
+    NoMatch(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Path(message) | ConfigError::NoMatch(message) => f.write_str(message),
+            ConfigError::Read { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            ConfigError::Parse(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse(source) => Some(source.as_ref()),
+            ConfigError::Path(_) | ConfigError::NoMatch(_) => None,
+        }
+    }
+}
+
+/// Loads the active config: the profile named by `$JIANCHA_PROFILE`, else `config.toml`.
+pub fn load_config() -> Result<Config, ConfigError> {
+    load_profile_config(env_profile().as_deref(), false)
+}
+
+fn load_profile_config(profile: Option<&str>, strict: bool) -> Result<Config, ConfigError> {
+    let path = config_path(profile).map_err(|e| ConfigError::Path(e.to_string()))?;
+    load_config_file(path, strict)
+}
+
+fn load_config_file(path: PathBuf, strict: bool) -> Result<Config, ConfigError> {
+    let contents = std::fs::read_to_string(&path).map_err(|source| ConfigError::Read {
+        path: path.clone(),
+        source,
+    })?;
+    parse_config(&contents, strict)
+}
+
+/// A config file's contents, with disabled sections dropped and globs expanded.
+fn parse_config(contents: &str, strict: bool) -> Result<Config, ConfigError> {
+    let mut config: Config =
+        toml::from_str(contents).map_err(|e| ConfigError::Parse(Box::new(e)))?;
+    let mut warnings = Vec::new();
+    for name in &config.ignore_sections {
+        if !config.sections.contains_key(name) {
+            warnings.push(format!("ignore_sections lists unknown section {name:?}"));
+        }
+    }
+    for name in &config.display_order {
+        if !config.sections.contains_key(name) {
+            warnings.push(format!("display_order lists unknown section {name:?}"));
+        }
+    }
+    let ignored = std::mem::take(&mut config.ignore_sections);
+    config.disabled_sections = config
+        .sections
+        .iter()
+        .filter(|(name, section)| !section.enabled.unwrap_or(true) || ignored.contains(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    config
+        .sections
+        .retain(|name, _| !config.disabled_sections.contains(name));
+    // Stable, so unlisted sections keep their place relative to each other.
+    let order = &config.display_order;
+    config.sections.sort_by_cached_key(|name, _| {
+        order
+            .iter()
+            .position(|listed| listed == name)
+            .unwrap_or(order.len())
+    });
+    for (section_name, section) in config.sections.iter_mut() {
+        let mut repos = IndexMap::new();
+        for (key, raw) in &section.repos {
+            if !is_glob(raw) {
+                let (_, path) = expand_config_value(raw).remove(0);
+                repos.insert(key.clone(), path.to_string_lossy().into_owned());
+                continue;
+            }
+            let matches = expand_config_value(raw);
+            if matches.is_empty() {
+                let message = format!("{section_name}.{key} = {raw:?} matches no directories");
+                if strict {
+                    return Err(ConfigError::NoMatch(message));
+                }
+                warnings.push(message);
+            }
+            for (name, path) in matches {
+                let path_str = path.to_string_lossy().into_owned();
+                if repos.values().any(|existing| *existing == path_str) {
+                    continue;
+                }
+                // An explicit entry or an earlier glob keeps the name.
+                if repos.contains_key(&name) || section.repos.contains_key(&name) {
+                    warnings.push(format!(
+                        "{section_name}.{key}: skipping {}, {name:?} is already taken",
+                        path.display()
+                    ));
+                    continue;
+                }
+                repos.insert(name, path_str);
+            }
+        }
+        section.repos = repos;
+    }
+    config.warnings = warnings;
+    Ok(config)
+}
+
+/// Section holding the directories passed on the command line.
+const ADHOC_SECTION: &str = "args";
+
+/// A config made of just the directories given on the command line, e.g.
+/// `jiancha ~/src/a ../b`, with default settings and theme. Relative paths are
+/// taken from the current directory; repos are named after their basename, or
+/// their full path when two basenames clash.
+fn adhoc_config(dirs: &[String]) -> Result<Config, Box<dyn std::error::Error>> {
+    let cwd = std::env::current_dir()?;
+    let mut section = Section::default();
+    for dir in dirs {
+        let joined = cwd.join(dir);
+        let path = std::fs::canonicalize(&joined).unwrap_or(joined);
+        let path_str = path.to_string_lossy().into_owned();
+        if section.repos.values().any(|existing| *existing == path_str) {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| !section.repos.contains_key(name))
+            .unwrap_or_else(|| path_str.clone());
+        section.repos.insert(name, path_str);
+    }
+    Ok(Config {
+        settings: Settings::default(),
+        theme: ThemeConfig::default(),
+        ignore_sections: Vec::new(),
+        display_order: Vec::new(),
+        git_hosts: Vec::new(),
+        sections: IndexMap::from([(ADHOC_SECTION.to_string(), section)]),
+        disabled_sections: Vec::new(),
+        warnings: Vec::new(),
+    })
+}
+
+/// The config for `jiancha status <dir>`: when a configured repo lives at `dir`,
+/// just that repo, keeping its name, section and the config's settings; otherwise
+/// the same as `adhoc_config`. A missing default config also means ad hoc, but
+/// one asked for with `--config` or a profile has to be readable.
+fn single_repo_config(
+    args: &Args,
+    profile: Option<&str>,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    let loaded = match &args.config {
+        Some(path) => load_config_file(path.clone(), args.strict),
+        None => load_profile_config(profile, args.strict),
+    };
+    let mut config = match loaded {
+        Ok(config) => show_warnings(config),
+        Err(ConfigError::Read { .. }) if args.config.is_none() && profile.is_none() => {
+            return adhoc_config(&args.dirs)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let canonical = |dir: &str| std::fs::canonicalize(dir).ok();
+    let Some(target) = canonical(&args.dirs[0]) else {
+        return adhoc_config(&args.dirs);
+    };
+    let found = config.sections.iter().find_map(|(section, entries)| {
+        entries
+            .repos
+            .iter()
+            .find(|(_, path)| canonical(path).as_ref() == Some(&target))
+            .map(|(repo, _)| (section.clone(), repo.clone()))
+    });
+    let Some((section, repo)) = found else {
+        return adhoc_config(&args.dirs);
+    };
+    config.sections.retain(|name, _| *name == section);
+    if let Some(entries) = config.sections.get_mut(&section) {
+        entries.repos.retain(|name, _| *name == repo);
+    }
+    Ok(config)
+}
+
+/// Exit statuses `run` returns; `jiancha man` describes each under EXIT STATUS.
+pub const EXIT_OK: i32 = 0;
+/// An error, `--max-errors` reached, or an `::error::` annotation from `--format github`.
+pub const EXIT_ERROR: i32 = 1;
+/// `path` was given a name that fits more than one repo.
+pub const EXIT_AMBIGUOUS: i32 = 2;
+/// `status <dir>`: the work tree has uncommitted changes.
+pub const EXIT_DIRTY: i32 = 3;
+/// `status <dir>`: the branch is ahead of or behind its upstream.
+pub const EXIT_OUT_OF_SYNC: i32 = 4;
+/// `exec`: the command couldn't be run in some repo.
+pub const EXIT_NOT_RUN: i32 = 127;
+
+/// `jiancha status <dir>` exits 0 when the repo is clean and in sync, 1 when it
+/// can't be read, 3 with uncommitted changes, and 4 when it's ahead of or behind
+/// its upstream.
+fn single_repo_exit(repo: &RepoResult) -> i32 {
+    if repo.local_error.is_some() || repo.clean.is_none() {
+        EXIT_ERROR
+    } else if repo.clean == Some(false) || repo.conflicted {
+        EXIT_DIRTY
+    } else if repo.ahead.is_some_and(|n| n > 0)
+        || matches!(repo.fetch_status, FetchStatus::Behind(n) if n > 0)
+    {
+        EXIT_OUT_OF_SYNC
+    } else {
+        EXIT_OK
+    }
+}
+
+/// The one place human-readable warnings are written. They go to stderr with a
+/// `warning:` prefix so stdout only ever carries the report itself.
+fn warn(message: impl std::fmt::Display) {
+    eprintln!("warning: {message}");
+}
+
+/// Writes a loaded config's warnings and hands back the config.
+fn show_warnings(config: Config) -> Config {
+    config.warnings.iter().for_each(warn);
+    config
+}
+
+fn is_glob(raw: &str) -> bool {
+    raw.contains(['*', '?', '['])
+}
+
+/// Expands a leading `~` and, for glob values such as `~/projects/work-*`, every
+/// matching directory, named after its basename. A plain path always yields itself.
+/// Supports `*`, `?` and `[...]` classes within a component; hidden entries only
+/// match a component that itself starts with `.`.
+fn expand_config_value(raw: &str) -> Vec<(String, PathBuf)> {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let expanded = match raw.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{home}{rest}"),
+        _ => raw.to_string(),
+    };
+    let name_of = |path: &Path| {
+        path.file_name().map_or_else(
+            || path.display().to_string(),
+            |n| n.to_string_lossy().into_owned(),
+        )
+    };
+
+    if !is_glob(&expanded) {
+        let path = PathBuf::from(expanded);
+        return vec![(name_of(&path), path)];
+    }
+
+    let mut candidates = vec![if expanded.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        PathBuf::new()
+    }];
+    for component in expanded.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        if !is_glob(component) {
+            for path in &mut candidates {
+                path.push(component);
+            }
+            continue;
+        }
+        let pattern: Vec<char> = component.chars().collect();
+        let mut next = Vec::new();
+        for dir in &candidates {
+            let listing = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            let Ok(entries) = std::fs::read_dir(listing) else {
+                continue;
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                let name: Vec<char> = name.chars().collect();
+                if glob_match(&pattern, &name) {
+                    next.push(dir.join(entry.file_name()));
+                }
+            }
+        }
+        candidates = next;
+    }
+
+    let mut matches: Vec<PathBuf> = candidates.into_iter().filter(|p| p.is_dir()).collect();
+    matches.sort();
+    matches.into_iter().map(|p| (name_of(&p), p)).collect()
+}
+
+/// Shell-style wildcard match of one path component.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| glob_match(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some(('[', rest)) => {
+            let Some(close) = rest.iter().skip(1).position(|&c| c == ']').map(|i| i + 1) else {
+                return name.first() == Some(&'[') && glob_match(rest, &name[1..]);
+            };
+            let Some((&c, name_rest)) = name.split_first() else {
+                return false;
+            };
+            let (negated, class) = match rest[..close].split_first() {
+                Some(('!' | '^', class)) => (true, class),
+                _ => (false, &rest[..close]),
+            };
+            let mut hit = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    hit |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    hit |= class[i] == c;
+                    i += 1;
+                }
+            }
+            hit != negated && glob_match(&rest[close + 1..], name_rest)
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && glob_match(rest, &name[1..]),
+    }
+}
+
+fn parse_color(spec: &str) -> Option<Color> {
+    if let Some(hex) = spec.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+
+    let color = match spec.to_ascii_lowercase().replace(['-', ' '], "_").as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "dark_red" => Color::DarkRed,
+        "green" => Color::Green,
+        "dark_green" => Color::DarkGreen,
+        "yellow" => Color::Yellow,
+        "dark_yellow" => Color::DarkYellow,
+        "blue" => Color::Blue,
+        "dark_blue" => Color::DarkBlue,
+        "magenta" => Color::Magenta,
+        "dark_magenta" => Color::DarkMagenta,
+        "cyan" => Color::Cyan,
+        "dark_cyan" => Color::DarkCyan,
+        "white" => Color::White,
+        "grey" | "gray" => Color::Grey,
+        "dark_grey" | "dark_gray" => Color::DarkGrey,
+        "orange" => Color::Rgb {
+            r: 255,
+            g: 165,
+            b: 0,
+        },
+        "dark_orange" | "darkorange" => Color::Rgb {
+            r: 255,
+            g: 140,
+            b: 0,
+        },
+        _ => return None,
+    };
+    Some(color)
+}
+
+fn resolve_symbol(
+    name: &str,
+    spec: &Option<SymbolSpec>,
+    glyph: &str,
+    color: Color,
+    mode: ColorMode,
+) -> Result<Symbol, Box<dyn std::error::Error>> {
+    let Some(spec) = spec else {
+        return Ok(Symbol {
+            glyph: glyph.to_string(),
+            color,
+        });
+    };
+
+    let color = match &spec.color {
+        Some(c) => mode.adapt(
+            parse_color(c)
+                .ok_or_else(|| format!("Invalid color for settings.symbols.{name}: {c:?}"))?,
+        ),
+        None => color,
+    };
+    Ok(Symbol {
+        glyph: spec.glyph.clone().unwrap_or_else(|| glyph.to_string()),
+        color,
+    })
+}
+
+/// Default glyph for every symbol; user overrides in `[settings.symbols]` win.
+struct GlyphSet {
+    clean: &'static str,
+    unpushed: &'static str,
+    no_upstream: &'static str,
+    dirty: &'static str,
+    conflict: &'static str,
+    error: &'static str,
+    up_to_date: &'static str,
+    behind: &'static str,
+    fetch_error: &'static str,
+    pending: &'static str,
+    skipped: &'static str,
+    /// Marks the middle of an elided path.
+    ellipsis: &'static str,
+    /// Repeated to draw the rule around section titles.
+    rule: &'static str,
+    /// Prefixes the message of a merge commit.
+    merge: &'static str,
+    /// Follows the status when a push would need `--force-with-lease`.
+    force_push: &'static str,
+    /// Prefixes the branch while a `git bisect` is under way.
+    bisect: &'static str,
+    /// Follows the status of a repo on a `--min-clean-streak` streak.
+    streak: &'static str,
+}
+
+const PLAIN_GLYPHS: GlyphSet = GlyphSet {
+    clean: "✓",
+    unpushed: "↑",
+    no_upstream: "⚠",
+    dirty: "✗",
+    conflict: "✗",
+    error: "?",
+    up_to_date: "✓",
+    behind: "↓",
+    fetch_error: "err",
+    pending: "...",
+    skipped: "—",
+    ellipsis: "…",
+    rule: "═",
+    merge: "⋈",
+    force_push: "⚡",
+    bisect: "⚡",
+    streak: "🏆",
+};
+
+/// For terminals and logs that can't show UTF-8 (`--ascii`).
+const ASCII_GLYPHS: GlyphSet = GlyphSet {
+    clean: "OK",
+    unpushed: "^",
+    no_upstream: "!",
+    dirty: "X",
+    conflict: "X",
+    error: "?",
+    up_to_date: "OK",
+    behind: "v",
+    fetch_error: "err",
+    pending: "...",
+    skipped: "-",
+    ellipsis: "...",
+    rule: "=",
+    merge: "M",
+    force_push: "+",
+    bisect: "~",
+    streak: "*",
+};
+
+/// Nerd Font code points (Font Awesome and Devicons ranges).
+const NERD_GLYPHS: GlyphSet = GlyphSet {
+    clean: "\u{f00c}",
+    unpushed: "\u{f062}",
+    no_upstream: "\u{f127}",
+    dirty: "\u{f044}",
+    conflict: "\u{e727}",
+    error: "\u{f059}",
+    up_to_date: "\u{f00c}",
+    behind: "\u{f063}",
+    fetch_error: "\u{f071}",
+    pending: "\u{f110}",
+    skipped: "\u{f05e}",
+    ellipsis: "…",
+    rule: "═",
+    merge: "⋈",
+    force_push: "\u{f0e7}",
+    bisect: "\u{f0e7}",
+    streak: "\u{f091}",
+};
+
+const NERD_BRANCH_ICON: &str = "\u{e0a0}";
+
+impl Symbols {
+    fn from_config(
+        config: &SymbolsConfig,
+        theme: &Theme,
+        glyphs: &GlyphSet,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let m = theme.mode;
+        let g = glyphs;
+        Ok(Symbols {
+            clean: resolve_symbol("clean", &config.clean, g.clean, theme.clean, m)?,
+            unpushed: resolve_symbol("unpushed", &config.unpushed, g.unpushed, theme.unpushed, m)?,
+            no_upstream: resolve_symbol(
+                "no_upstream",
+                &config.no_upstream,
+                g.no_upstream,
+                theme.no_upstream,
+                m,
+            )?,
+            dirty: resolve_symbol("dirty", &config.dirty, g.dirty, theme.dirty, m)?,
+            conflict: resolve_symbol("conflict", &config.conflict, g.conflict, theme.conflict, m)?,
+            error: resolve_symbol("error", &config.error, g.error, theme.error, m)?,
+            up_to_date: resolve_symbol(
+                "up_to_date",
+                &config.up_to_date,
+                g.up_to_date,
+                theme.up_to_date,
+                m,
+            )?,
+            behind: resolve_symbol("behind", &config.behind, g.behind, theme.behind, m)?,
+            fetch_error: resolve_symbol(
+                "fetch_error",
+                &config.fetch_error,
+                g.fetch_error,
+                theme.fetch_error,
+                m,
+            )?,
+            pending: resolve_symbol("pending", &config.pending, g.pending, theme.pending, m)?,
+            skipped: resolve_symbol("skipped", &config.skipped, g.skipped, theme.skipped, m)?,
+        })
+    }
+
+    fn for_state(&self, state: RepoState) -> &Symbol {
+        match state {
+            RepoState::Clean => &self.clean,
+            RepoState::Unpushed => &self.unpushed,
+            RepoState::NoUpstream => &self.no_upstream,
+            RepoState::Dirty => &self.dirty,
+            RepoState::Conflict => &self.conflict,
+            RepoState::Error => &self.error,
+        }
+    }
+}
+
+impl ColorMode {
+    fn detect() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        match std::env::var("COLORTERM").as_deref() {
+            Ok("truecolor") | Ok("24bit") => ColorMode::TrueColor,
+            // The Linux console and other terminals known to stop at 16 colors.
+            _ if term.ends_with("-16color")
+                || ["linux", "ansi", "vt100", "vt220", "cons25", "xterm-color"]
+                    .contains(&term.as_str()) =>
+            {
+                ColorMode::Ansi16
+            }
+            _ => ColorMode::Ansi256,
+        }
+    }
+
+    fn adapt(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorMode::Ansi256, Color::Rgb { r, g, b }) => {
+                Color::AnsiValue(rgb_to_ansi256(r, g, b))
+            }
+            (ColorMode::Ansi16, Color::Rgb { r, g, b }) => nearest_basic_color((r, g, b)),
+            (ColorMode::Ansi16, Color::AnsiValue(n)) => nearest_basic_color(ansi256_to_rgb(n)),
+            _ => color,
+        }
+    }
+}
+
+/// Nearest xterm-256 index for an RGB triple, picking between the 6x6x6 color
+/// cube and the 24-step grayscale ramp.
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    const CUBE_STEPS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let cube_index = |v: u8| -> usize {
+        CUBE_STEPS
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, &step)| (step as i32 - v as i32).abs())
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| -> i32 {
+        let dr = r as i32 - r2 as i32;
+        let dg = g as i32 - g2 as i32;
+        let db = b as i32 - b2 as i32;
+        dr * dr + dg * dg + db * db
+    };
+
+    let (ri, gi, bi) = (cube_index(r), cube_index(g), cube_index(b));
+    let cube = (CUBE_STEPS[ri], CUBE_STEPS[gi], CUBE_STEPS[bi]);
+    let cube_code = 16 + 36 * ri + 6 * gi + bi;
+
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_index = (avg.saturating_sub(8) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray_index;
+    let gray = (gray_level, gray_level, gray_level);
+
+    if distance(gray) < distance(cube) {
+        232 + gray_index
+    } else {
+        cube_code as u8
+    }
+}
+
+/// The 16 basic colors in palette order, with the RGB values xterm gives them.
+const BASIC_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// The basic color closest to `rgb`, for terminals with only 16 colors.
+fn nearest_basic_color((r, g, b): (u8, u8, u8)) -> Color {
+    let distance = |(r2, g2, b2): (u8, u8, u8)| -> i32 {
+        let dr = r as i32 - r2 as i32;
+        let dg = g as i32 - g2 as i32;
+        let db = b as i32 - b2 as i32;
+        dr * dr + dg * dg + db * db
+    };
+    BASIC_COLORS
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb))
+        .map_or(Color::Reset, |&(color, _)| color)
+}
+
+/// The RGB value of an xterm-256 index: the basic colors, the 6x6x6 cube, then
+/// the grayscale ramp.
+fn ansi256_to_rgb(n: u8) -> (u8, u8, u8) {
+    const CUBE_STEPS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match n {
+        0..=15 => BASIC_COLORS[n as usize].1,
+        16..=231 => {
+            let i = (n - 16) as usize;
+            (CUBE_STEPS[i / 36], CUBE_STEPS[i / 6 % 6], CUBE_STEPS[i % 6])
+        }
+        _ => {
+            let level = 8 + 10 * (n - 232);
+            (level, level, level)
+        }
+    }
+}
+
+/// SGR parameters selecting `color` as the foreground, or `None` for `Color::Reset`.
+fn fg_sgr(color: Color) -> Option<String> {
+    color_sgr(color, false)
+}
+
+/// Like `fg_sgr`, for the background.
+fn bg_sgr(color: Color) -> Option<String> {
+    color_sgr(color, true)
+}
+
+/// The basic colors get the classic 30–37 and 90–97 codes (40–47 and 100–107 for
+/// the background), which every color terminal reads; the rest use the 256-color
+/// or 24-bit forms.
+fn color_sgr(color: Color, background: bool) -> Option<String> {
+    let (basic, bright, extended) = if background {
+        (40, 100, 48)
+    } else {
+        (30, 90, 38)
+    };
+    let index = match color {
+        Color::Reset => return None,
+        Color::Rgb { r, g, b } => return Some(format!("{extended};2;{r};{g};{b}")),
+        Color::AnsiValue(n) => return Some(format!("{extended};5;{n}")),
+        Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15,
+    };
+    Some(match index {
+        0..=7 => format!("{}", basic + index),
+        _ => format!("{}", bright + index - 8),
+    })
+}
+
+/// The escape sequence that starts a section header, or nothing for a plain one.
+fn section_header_ansi(style: &SectionHeaderStyle) -> String {
+    let mut params: Vec<String> = [
+        (style.bold, "1"),
+        (style.italic, "3"),
+        (style.underline, "4"),
+    ]
+    .into_iter()
+    .filter(|&(on, _)| on)
+    .map(|(_, code)| code.to_string())
+    .collect();
+    params.extend(fg_sgr(style.fg));
+    params.extend(bg_sgr(style.bg));
+    if params.is_empty() {
+        return String::new();
+    }
+    format!("\x1b[{}m", params.join(";"))
+}
+
+/// Removes SGR sequences (`ESC [ <digits and ;> m`) for `--ansi-strip`; other
+/// escapes are left alone.
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("\x1b[") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let params = after
+            .find(|c: char| !c.is_ascii_digit() && c != ';')
+            .unwrap_or(after.len());
+        if after[params..].starts_with('m') {
+            rest = &after[params + 1..];
+        } else {
+            out.push_str("\x1b[");
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Theme {
+    fn by_name(name: &str, mode: ColorMode) -> Result<Self, Box<dyn std::error::Error>> {
+        let rgb = |r, g, b| Color::Rgb { r, g, b };
+        let theme = match name {
+            "default" => Theme {
+                mode,
+                section_header: SectionHeaderStyle::bold(rgb(255, 140, 0)),
+                repo_header: Color::Cyan,
+                branch_header: Color::Magenta,
+                status_header: rgb(119, 136, 153),
+                last_commit_header: rgb(184, 134, 11),
+                remote_header: rgb(100, 200, 100),
+                error_header: Color::Red,
+                clean: Color::Green,
+                unpushed: Color::Yellow,
+                no_upstream: Color::Yellow,
+                dirty: Color::Red,
+                conflict: Color::Red,
+                error: Color::Yellow,
+                up_to_date: Color::Green,
+                behind: Color::Yellow,
+                fetch_error: Color::Red,
+                pending: Color::Grey,
+                skipped: Color::DarkGrey,
+                extra_header: Color::Blue,
+                warning: Color::Yellow,
+                error_row: Color::Red,
+                feature_branch: Color::Yellow,
+                detached_head: Color::Magenta,
+                unsigned: Color::Red,
+                unknown_key: rgb(255, 165, 0),
+                stale_branches: rgb(255, 165, 0),
+                commit_age: [
+                    Color::Reset,
+                    rgb(178, 178, 178),
+                    rgb(138, 138, 138),
+                    rgb(108, 108, 108),
+                    rgb(175, 95, 95),
+                ],
+            },
+            "mono" => Theme {
+                mode,
+                section_header: SectionHeaderStyle::bold(Color::Reset),
+                repo_header: Color::Reset,
+                branch_header: Color::Reset,
+                status_header: Color::Reset,
+                last_commit_header: Color::Reset,
+                remote_header: Color::Reset,
+                error_header: Color::Reset,
+                clean: Color::Reset,
+                unpushed: Color::Reset,
+                no_upstream: Color::Reset,
+                dirty: Color::Reset,
+                conflict: Color::Reset,
+                error: Color::Reset,
+                up_to_date: Color::Reset,
+                behind: Color::Reset,
+                fetch_error: Color::Reset,
+                pending: Color::Reset,
+                skipped: Color::Reset,
+                extra_header: Color::Reset,
+                warning: Color::Reset,
+                error_row: Color::Reset,
+                feature_branch: Color::Reset,
+                detached_head: Color::Reset,
+                unsigned: Color::Reset,
+                unknown_key: Color::Reset,
+                stale_branches: Color::Reset,
+                commit_age: [Color::Reset; 5],
+            },
+            "solarized" => Theme {
+                mode,
+                section_header: SectionHeaderStyle::bold(rgb(203, 75, 22)),
+                repo_header: rgb(38, 139, 210),
+                branch_header: rgb(108, 113, 196),
+                status_header: rgb(147, 161, 161),
+                last_commit_header: rgb(181, 137, 0),
+                remote_header: rgb(42, 161, 152),
+                error_header: rgb(220, 50, 47),
+                clean: rgb(133, 153, 0),
+                unpushed: rgb(181, 137, 0),
+                no_upstream: rgb(203, 75, 22),
+                dirty: rgb(220, 50, 47),
+                conflict: rgb(211, 54, 130),
+                error: rgb(181, 137, 0),
+                up_to_date: rgb(133, 153, 0),
+                behind: rgb(181, 137, 0),
+                fetch_error: rgb(220, 50, 47),
+                pending: rgb(88, 110, 117),
+                skipped: rgb(88, 110, 117),
+                extra_header: rgb(101, 123, 131),
+                warning: rgb(181, 137, 0),
+                error_row: rgb(220, 50, 47),
+                feature_branch: rgb(181, 137, 0),
+                detached_head: rgb(211, 54, 130),
+                unsigned: rgb(220, 50, 47),
+                unknown_key: rgb(203, 75, 22),
+                stale_branches: rgb(203, 75, 22),
+                commit_age: [
+                    Color::Reset,
+                    rgb(147, 161, 161),
+                    rgb(131, 148, 150),
+                    rgb(88, 110, 117),
+                    rgb(203, 75, 22),
+                ],
+            },
+            _ => {
+                return Err(format!(
+                    "Unknown theme {name:?} (expected one of: {})",
+                    THEME_NAMES.join(", ")
+                )
+                .into())
+            }
+        };
+        Ok(theme.adapted())
+    }
+
+    fn adapted(self) -> Self {
+        let m = self.mode;
+        Theme {
+            mode: m,
+            section_header: SectionHeaderStyle {
+                fg: m.adapt(self.section_header.fg),
+                bg: m.adapt(self.section_header.bg),
+                ..self.section_header
+            },
+            repo_header: m.adapt(self.repo_header),
+            branch_header: m.adapt(self.branch_header),
+            status_header: m.adapt(self.status_header),
+            last_commit_header: m.adapt(self.last_commit_header),
+            remote_header: m.adapt(self.remote_header),
+            error_header: m.adapt(self.error_header),
+            clean: m.adapt(self.clean),
+            unpushed: m.adapt(self.unpushed),
+            no_upstream: m.adapt(self.no_upstream),
+            dirty: m.adapt(self.dirty),
+            conflict: m.adapt(self.conflict),
+            error: m.adapt(self.error),
+            up_to_date: m.adapt(self.up_to_date),
+            behind: m.adapt(self.behind),
+            fetch_error: m.adapt(self.fetch_error),
+            pending: m.adapt(self.pending),
+            skipped: m.adapt(self.skipped),
+            extra_header: m.adapt(self.extra_header),
+            warning: m.adapt(self.warning),
+            error_row: m.adapt(self.error_row),
+            feature_branch: m.adapt(self.feature_branch),
+            detached_head: m.adapt(self.detached_head),
+            unsigned: m.adapt(self.unsigned),
+            unknown_key: m.adapt(self.unknown_key),
+            stale_branches: m.adapt(self.stale_branches),
+            commit_age: self.commit_age.map(|c| m.adapt(c)),
+        }
+    }
+
+    /// A line in the section header style, used for the rule and the title.
+    fn section_line(&self, text: &str) -> String {
+        match section_header_ansi(&self.section_header) {
+            sgr if sgr.is_empty() => text.to_string(),
+            sgr => format!("{sgr}{text}\x1b[0m"),
+        }
+    }
+
+    /// Tints a whole cell for rows in an error or conflict state; themes without
+    /// an error row color dim the text instead.
+    fn highlight_error(&self, cell: Cell) -> Cell {
+        match self.error_row {
+            Color::Reset => cell.add_attribute(Attribute::Dim),
+            color => cell.fg(color),
+        }
+    }
+
+    fn paint(&self, cell: Cell, color: Color) -> Cell {
+        match color {
+            Color::Reset => cell,
+            color => cell.fg(color),
+        }
+    }
+}
+
+impl RepoState {
+    /// Matches the `[settings.symbols]` key for the state.
+    fn name(self) -> &'static str {
+        match self {
+            RepoState::Clean => "clean",
+            RepoState::Unpushed => "unpushed",
+            RepoState::NoUpstream => "no_upstream",
+            RepoState::Dirty => "dirty",
+            RepoState::Conflict => "conflict",
+            RepoState::Error => "error",
+        }
+    }
+}
+
+fn repo_state(repo: &RepoResult) -> RepoState {
+    if repo.local_error.is_some() {
+        return RepoState::Error;
+    }
+    if repo.conflicted {
+        return RepoState::Conflict;
+    }
+    let upstream_skipped = matches!(repo.fetch_status, FetchStatus::Skipped);
+    match (repo.clean.unwrap_or(false), repo.ahead.map(|n| n > 0)) {
+        (true, Some(false)) => RepoState::Clean,
+        (true, None) if upstream_skipped => RepoState::Clean,
+        (true, Some(true)) => RepoState::Unpushed,
+        (true, None) => RepoState::NoUpstream,
+        _ => RepoState::Dirty,
+    }
+}
+
+fn take_value(
+    flag: &str,
+    inline: Option<String>,
+    argv: &mut impl Iterator<Item = String>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    inline
+        .or_else(|| argv.next())
+        .ok_or_else(|| format!("{flag} requires a value").into())
+}
+
+/// First words that pick a subcommand rather than name a directory.
+const SUBCOMMANDS: [&str; 18] = [
+    "status",
+    "fetch",
+    "pull",
+    "push",
+    "gc",
+    "exec",
+    "list",
+    "path",
+    "open",
+    "browse",
+    "clone",
+    "check-stale",
+    "config",
+    "profiles",
+    "diagnose",
+    "completions",
+    "man",
+    "__complete",
+];
+
+/// A command-line flag. `parse_args` resolves aliases and rejects unknown flags
+/// through `FLAGS`, and `--help`, the completion scripts and `jiancha man` list them
+/// from it, so the four can't drift apart.
+struct Flag {
+    name: &'static str,
+    /// Other spellings, dashes included: `-j` for `--jobs`, `--refresh` for `--fresh`.
+    aliases: &'static [&'static str],
+    /// The value as `--help` shows it, e.g. ` <n>`; empty for a switch, or when
+    /// `completion` lists the words.
+    value: &'static str,
+    completion: Completion,
+    /// May be given more than once.
+    repeats: bool,
+    /// Only read by a subcommand, whose line in `--help` shows it.
+    subcommand: bool,
+}
+
+impl Flag {
+    const fn switch(name: &'static str) -> Flag {
+        Flag {
+            name,
+            aliases: &[],
+            value: "",
+            completion: Completion::Nothing,
+            repeats: false,
+            subcommand: false,
+        }
+    }
+
+    const fn takes(name: &'static str, value: &'static str, completion: Completion) -> Flag {
+        Flag {
+            value,
+            completion,
+            ..Flag::switch(name)
+        }
+    }
+
+    const fn words(name: &'static str, words: &'static str) -> Flag {
+        Flag::takes(name, "", Completion::Words(words))
+    }
+
+    const fn alias(self, aliases: &'static [&'static str]) -> Flag {
+        Flag { aliases, ..self }
+    }
+
+    const fn repeats(self) -> Flag {
+        Flag {
+            repeats: true,
+            ..self
+        }
+    }
+
+    const fn for_subcommand(self) -> Flag {
+        Flag {
+            subcommand: true,
+            ..self
+        }
+    }
+
+    /// Every way to write the flag: short aliases, the name, then long aliases.
+    fn spellings(&self) -> Vec<String> {
+        let (short, long): (Vec<&str>, Vec<&str>) = self
+            .aliases
+            .iter()
+            .partition(|alias| !alias.starts_with("--"));
+        short
+            .into_iter()
+            .map(str::to_string)
+            .chain(std::iter::once(format!("--{}", self.name)))
+            .chain(long.into_iter().map(str::to_string))
+            .collect()
+    }
+
+    /// The value after the flag as `--help` shows it.
+    fn value_text(&self) -> String {
+        match (self.value, &self.completion) {
+            ("", Completion::Words(words)) => format!(" {}", words.replace(' ', "|")),
+            (value, _) => value.to_string(),
+        }
+    }
+
+    /// The flag's entry in `--help`, e.g. `[-j|--jobs <n>]`.
+    fn usage(&self) -> String {
+        format!(
+            "[{}{}]{}",
+            self.spellings().join("|"),
+            self.value_text(),
+            if self.repeats { "..." } else { "" }
+        )
+    }
+}
+
+/// Every flag, in the order `--help` lists them.
+const FLAGS: &[Flag] = &[
+    Flag::switch("fresh").alias(&["--refresh"]),
+    Flag::switch("offline"),
+    Flag::takes("profile", " <name>", Completion::Names("profiles")),
+    Flag::takes(
+        "theme",
+        " <name>",
+        Completion::Words("default mono solarized"),
+    ),
+    Flag::takes("config", " <path>", Completion::Files),
+    Flag::switch("dirty"),
+    Flag::switch("filter-stale-branches"),
+    Flag::switch("no-color"),
+    Flag::switch("ansi-strip"),
+    Flag::switch("version").alias(&["-V"]),
+    Flag::switch("help").alias(&["-h"]),
+    Flag::switch("update-self"),
+    Flag::switch("no-pager"),
+    Flag::switch("no-fetch-check"),
+    Flag::switch("paths"),
+    Flag::switch("show-size"),
+    Flag::takes("size-threshold", " <size>", Completion::Anything),
+    Flag::switch("show-fetch-time"),
+    Flag::switch("numbers"),
+    Flag::switch("show-contributors"),
+    Flag::switch("show-lfs"),
+    Flag::switch("show-hooks"),
+    Flag::switch("show-envrc"),
+    Flag::switch("load-envrc"),
+    Flag::switch("show-contributing"),
+    Flag::switch("show-diffstat"),
+    Flag::switch("security"),
+    Flag::switch("timings"),
+    Flag::switch("deep"),
+    Flag::takes("min-clean-streak", " <n>", Completion::Anything),
+    Flag::takes("jobs", " <n>", Completion::Anything).alias(&["-j"]),
+    Flag::takes("max-errors", " <n>", Completion::Anything),
+    Flag::switch("quiet").alias(&["-q"]),
+    Flag::switch("no-cache"),
+    Flag::takes("cache-ttl", " <secs>", Completion::Anything),
+    Flag::takes("timeout", " <secs>", Completion::Anything),
+    Flag::switch("machine-json"),
+    Flag::switch("stream"),
+    // The interval is optional, so completion offers nothing after it.
+    Flag::takes("watch", " [<secs>]", Completion::Nothing),
+    Flag::words("group-by", "section status none"),
+    Flag::words("date-format", "relative iso rfc locale"),
+    Flag::words("format-number", "plain locale"),
+    Flag::switch("icons"),
+    Flag::switch("ascii"),
+    Flag::switch("wrap"),
+    Flag::switch("check-force-push"),
+    Flag::takes("columns", " <name,...>", Completion::Anything),
+    Flag::takes("column-alias", " <column>=<header>", Completion::Anything).repeats(),
+    Flag::takes("base-branch", " <name>", Completion::Anything),
+    Flag::takes("remote-filter", " <text>", Completion::Anything).repeats(),
+    Flag::words("color", "auto always never"),
+    Flag::takes("output", " <path>", Completion::Files).alias(&["-o"]),
+    Flag::switch("diff"),
+    Flag::takes("section", " <name>", Completion::Names("sections")),
+    Flag::words(
+        "format",
+        "table prometheus github porcelain compact tsv yaml",
+    ),
+    Flag::switch("no-header"),
+    Flag::switch("strict"),
+    Flag::takes("oneline", "[=counts]", Completion::Nothing),
+    Flag::switch("porcelain"),
+    Flag::switch("compact"),
+    Flag::switch("dry-run").for_subcommand(),
+    Flag::switch("set-upstream").for_subcommand(),
+    Flag::switch("confirm").for_subcommand(),
+    Flag::switch("fail-fast").for_subcommand(),
+    Flag::switch("json").for_subcommand(),
+    Flag::switch("relative-paths").for_subcommand(),
+    Flag::switch("all").for_subcommand(),
+    Flag::switch("shell").for_subcommand(),
+    Flag::switch("branch").for_subcommand(),
+    Flag::switch("commit").for_subcommand(),
+    Flag::takes("days", " <n>", Completion::Anything).for_subcommand(),
+    Flag::switch("github-archive").for_subcommand(),
+];
+
+/// The flag `arg` spells, by its name or an alias.
+fn find_flag(arg: &str) -> Option<&'static Flag> {
+    FLAGS
+        .iter()
+        .find(|flag| arg.strip_prefix("--") == Some(flag.name) || flag.aliases.contains(&arg))
+}
+
+/// The subcommand lines of `--help`, after the top-level options.
+const SUBCOMMAND_USAGE: [&str; 18] = [
+    "jiancha status <dir>",
+    "jiancha fetch [--dry-run] [<dir>...]",
+    "jiancha pull [--dry-run] [<dir>...]",
+    "jiancha push [--dry-run] [--set-upstream] [--confirm] [<dir>...]",
+    "jiancha gc [--dry-run] [<dir>...]",
+    "jiancha exec [--fail-fast] [<dir>...] -- <command>...",
+    "jiancha list [--json] [--paths] [--relative-paths]",
+    "jiancha path <number|name> | --all",
+    "jiancha open [--shell] <number|name>",
+    "jiancha browse [--branch|--commit] <number|name>",
+    "jiancha clone [--dry-run] [<name>]",
+    "jiancha check-stale [--days <n>] [--json] [--github-archive]",
+    "jiancha config diff",
+    "jiancha profiles list",
+    "jiancha profiles create <name>",
+    "jiancha diagnose [--json] <dir>",
+    "jiancha completions bash|zsh|fish|powershell",
+    "jiancha man",
+];
+
+/// `--help`, and the SYNOPSIS of `jiancha man`: the top-level flags from `FLAGS`,
+/// wrapped to 100 columns, then a line per subcommand.
+fn usage() -> String {
+    const FIRST: &str = "Usage: jiancha";
+    let indent = " ".repeat(FIRST.len());
+    let mut lines = vec![FIRST.to_string()];
+    let entries = FLAGS
+        .iter()
+        .filter(|flag| !flag.subcommand)
+        .map(Flag::usage)
+        .chain(std::iter::once("[<dir>...]".to_string()));
+    for entry in entries {
+        let line = lines.last_mut().expect("starts with a line");
+        if line.len() > indent.len() && line.len() + 1 + entry.len() > 100 {
+            lines.push(format!("{indent} {entry}"));
+        } else {
+            line.push(' ');
+            line.push_str(&entry);
+        }
+    }
+    for subcommand in SUBCOMMAND_USAGE {
+        lines.push(format!("       {subcommand}"));
+    }
+    lines.join("\n")
+}
+
+/// Printed after `usage()` by `--help`; `jiancha man` has the full story.
+const HELP_NOTES: &str =
+    "Exit status: 0 on success, 1 on an error or when --format github writes an\n\
+                          ::error:: annotation. See jiancha man for the rest.\n\
+                          --watch runs until Ctrl-C; a cycle interrupted midway is discarded, and the\n\
+                          cache and snapshot keep what the last completed cycle wrote.\n\
+                          Local state is cached for --cache-ttl seconds (default 60): edits to files\n\
+                          that aren't staged show up once it expires, or at once with --cache-ttl 0.";
+
+/// What the command line asks for: a run, or only the usage or version text.
+enum Invocation {
+    Run(Box<Args>),
+    Help,
+    Version,
+}
+
+fn parse_args(
+    argv: impl IntoIterator<Item = String>,
+) -> Result<Invocation, Box<dyn std::error::Error>> {
+    let mut args = Args {
+        fresh: false,
+        offline: false,
+        profile: None,
+        config: None,
+        dirty: false,
+        filter_stale_branches: false,
+        theme: None,
+        no_pager: false,
+        no_header: false,
+        no_fetch_check: false,
+        paths: false,
+        show_size: false,
+        size_threshold: None,
+        group_by: GroupBy::Section,
+        date_format: None,
+        number_format: None,
+        icons: false,
+        ascii: false,
+        strict: false,
+        show_fetch_time: false,
+        show_contributors: false,
+        show_lfs: false,
+        show_hooks: false,
+        show_envrc: false,
+        load_envrc: false,
+        show_contributing: false,
+        show_diffstat: false,
+        security: false,
+        timings: false,
+        deep: false,
+        quiet: false,
+        no_cache: false,
+        relative_paths: false,
+        machine_json: false,
+        stream: false,
+        cache_ttl: None,
+        timeout: None,
+        jobs: None,
+        max_errors: None,
+        min_clean_streak: None,
+        numbers: false,
+        columns: None,
+        column_aliases: Vec::new(),
+        wrap: false,
+        check_force_push: false,
+        base_branch: None,
+        remote_filters: Vec::new(),
+        color: ColorChoice::Auto,
+        ansi_strip: false,
+        output: None,
+        diff: false,
+        section: None,
+        watch: None,
+        format: OutputFormat::Table,
+        dirs: Vec::new(),
+        single: false,
+        command: Subcommand::Status,
+    };
+    let mut positionals = Vec::new();
+    let mut dry_run = false;
+    let mut json = false;
+    let mut days = None;
+    let mut github_archive = false;
+    let mut set_upstream = false;
+    let mut confirm = false;
+    let mut shell = false;
+    let mut browse = BrowseKind::Repo;
+    let mut fail_fast = false;
+    let mut all = false;
+    let mut update_self = false;
+    let mut exec_command = None;
+
+    let mut argv = argv.into_iter().peekable();
+    while let Some(arg) = argv.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        // Aliases become the flag's name, so the arms below only match names.
+        let flag = match find_flag(&flag) {
+            Some(known) => format!("--{}", known.name),
+            None if flag.starts_with('-') && flag != "--" => {
+                return Err(format!("Unknown argument: {arg} (see jiancha --help)").into())
+            }
+            None => flag,
+        };
+        match flag.as_str() {
+            "--fresh" => args.fresh = true,
+            "--dry-run" => dry_run = true,
+            "--fail-fast" => fail_fast = true,
+            "--all" => all = true,
+            "--update-self" => update_self = true,
+            // Everything after it is the command for `exec`, flags included.
+            "--" => {
+                exec_command = Some(argv.by_ref().collect::<Vec<_>>());
+                break;
+            }
+            "--json" => json = true,
+            "--days" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                days = Some(value.parse::<u64>().map_err(|_| {
+                    format!("Invalid value for --days: {value} (expected a number of days)")
+                })?);
+            }
+            "--github-archive" => github_archive = true,
+            "--set-upstream" => set_upstream = true,
+            "--confirm" => confirm = true,
+            "--shell" => shell = true,
+            "--branch" | "--commit" if browse != BrowseKind::Repo => {
+                return Err("--branch and --commit cannot be used together".into())
+            }
+            "--branch" => browse = BrowseKind::Branch,
+            "--commit" => browse = BrowseKind::Commit,
+            "--section" => args.section = Some(take_value(&flag, inline, &mut argv)?),
+            "--offline" => args.offline = true,
+            "--profile" => args.profile = Some(take_value(&flag, inline, &mut argv)?),
+            "--config" => args.config = Some(PathBuf::from(take_value(&flag, inline, &mut argv)?)),
+            "--dirty" => args.dirty = true,
+            "--filter-stale-branches" => args.filter_stale_branches = true,
+            "--no-color" => args.color = ColorChoice::Never,
+            "--ansi-strip" => args.ansi_strip = true,
+            "--version" => return Ok(Invocation::Version),
+            "--theme" => args.theme = Some(take_value(&flag, inline, &mut argv)?),
+            "--no-pager" => args.no_pager = true,
+            "--no-header" => args.no_header = true,
+            "--no-fetch-check" => args.no_fetch_check = true,
+            "--paths" => args.paths = true,
+            "--show-size" => args.show_size = true,
+            "--show-fetch-time" => args.show_fetch_time = true,
+            "--numbers" => args.numbers = true,
+            "--show-contributors" => args.show_contributors = true,
+            "--show-lfs" => args.show_lfs = true,
+            "--show-hooks" => args.show_hooks = true,
+            "--show-envrc" => args.show_envrc = true,
+            "--load-envrc" => args.load_envrc = true,
+            "--show-contributing" => args.show_contributing = true,
+            "--show-diffstat" => args.show_diffstat = true,
+            "--security" => args.security = true,
+            "--timings" => args.timings = true,
+            "--deep" => args.deep = true,
+            "--quiet" => args.quiet = true,
+            "--no-cache" => args.no_cache = true,
+            "--relative-paths" => args.relative_paths = true,
+            "--machine-json" => args.machine_json = true,
+            "--stream" => args.stream = true,
+            "--cache-ttl" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.cache_ttl = Some(value.parse::<u64>().map_err(|_| {
+                    format!("Invalid value for --cache-ttl: {value} (expected seconds)")
+                })?);
+            }
+            "--timeout" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.timeout = Some(value.parse::<u64>().map_err(|_| {
+                    format!("Invalid value for --timeout: {value} (expected seconds)")
+                })?);
+            }
+            "--watch" => {
+                // The interval is optional, so only a number right after the flag is one.
+                let value = inline.or_else(|| argv.next_if(|next| next.parse::<u64>().is_ok()));
+                let secs = match value {
+                    Some(value) => value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|&secs| secs > 0)
+                        .ok_or_else(|| {
+                            format!("Invalid value for --watch: {value} (expected seconds)")
+                        })?,
+                    None => DEFAULT_WATCH_SECS,
+                };
+                args.watch = Some(Duration::from_secs(secs));
+            }
+            "--icons" => args.icons = true,
+            "--ascii" => args.ascii = true,
+            "--strict" => args.strict = true,
+            "--wrap" => args.wrap = true,
+            "--columns" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.columns = Some(value.split(',').map(str::to_string).collect());
+            }
+            "--column-alias" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                let (column, alias) = value
+                    .split_once('=')
+                    .filter(|(column, alias)| !column.is_empty() && !alias.is_empty())
+                    .ok_or_else(|| {
+                        format!("Invalid value for --column-alias: {value} (expected <column>=<header>)")
+                    })?;
+                args.column_aliases
+                    .push((column.to_string(), alias.to_string()));
+            }
+            "--check-force-push" => args.check_force_push = true,
+            "--base-branch" => args.base_branch = Some(take_value(&flag, inline, &mut argv)?),
+            "--remote-filter" => args
+                .remote_filters
+                .push(take_value(&flag, inline, &mut argv)?),
+            "--jobs" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.jobs = Some(value.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(
+                    || format!("Invalid value for --jobs: {value} (expected a positive number)"),
+                )?);
+            }
+            "--min-clean-streak" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.min_clean_streak =
+                    Some(value.parse().ok().filter(|&n: &u32| n > 0).ok_or_else(|| {
+                        format!(
+                            "Invalid value for --min-clean-streak: {value} (expected a positive number)"
+                        )
+                    })?);
+            }
+            "--max-errors" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.max_errors = Some(value.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(
+                    || {
+                        format!(
+                            "Invalid value for --max-errors: {value} (expected a positive number)"
+                        )
+                    },
+                )?);
+            }
+            "--diff" => args.diff = true,
+            "--output" => args.output = Some(PathBuf::from(take_value(&flag, inline, &mut argv)?)),
+            "--color" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.color = match value.as_str() {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => {
+                        return Err(format!(
+                            "Invalid value for --color: {value} (expected auto, always, or never)"
+                        )
+                        .into())
+                    }
+                };
+            }
+            "--format" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.format = match value.as_str() {
+                    "table" => OutputFormat::Table,
+                    "prometheus" => OutputFormat::Prometheus,
+                    "github" => OutputFormat::Github,
+                    "porcelain" => OutputFormat::Porcelain,
+                    "compact" => OutputFormat::Compact,
+                    "tsv" => OutputFormat::Tsv,
+                    "yaml" => OutputFormat::Yaml,
+                    _ => {
+                        return Err(format!(
+                            "Invalid value for --format: {value} (expected table, prometheus, github, porcelain, compact, tsv, or yaml)"
+                        )
+                        .into())
+                    }
+                };
+            }
+            "--porcelain" => args.format = OutputFormat::Porcelain,
+            "--compact" => args.format = OutputFormat::Compact,
+            "--oneline" => {
+                args.format = match inline.as_deref() {
+                    None => OutputFormat::Oneline { counts: false },
+                    Some("counts") => OutputFormat::Oneline { counts: true },
+                    Some(value) => {
+                        return Err(format!(
+                            "Invalid value for --oneline: {value} (expected counts)"
+                        )
+                        .into())
+                    }
+                };
+            }
+            "--date-format" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.date_format = Some(DateFormat::parse(&value).ok_or_else(|| {
+                    format!(
+                        "Invalid value for --date-format: {value} (expected relative, iso, rfc, or locale)"
+                    )
+                })?);
+            }
+            "--format-number" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.number_format = Some(match value.as_str() {
+                    "plain" => NumberFormat::Plain,
+                    "locale" => NumberFormat::Locale,
+                    _ => {
+                        return Err(format!(
+                            "Invalid value for --format-number: {value} (expected plain or locale)"
+                        )
+                        .into())
+                    }
+                });
+            }
+            "--group-by" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.group_by = match value.as_str() {
+                    "section" => GroupBy::Section,
+                    "status" => GroupBy::Status,
+                    "none" => GroupBy::None,
+                    _ => {
+                        return Err(format!(
+                        "Invalid value for --group-by: {value} (expected section, status, or none)"
+                    )
+                        .into())
+                    }
+                };
+            }
+            "--size-threshold" => {
+                let value = take_value(&flag, inline, &mut argv)?;
+                args.size_threshold = Some(
+                    DiskSize::parse(&value)
+                        .ok_or_else(|| format!("Invalid size for --size-threshold: {value}"))?,
+                );
+            }
+            "--help" => return Ok(Invocation::Help),
+            _ if flag.starts_with("--") && flag != "--" => {
+                unreachable!("{flag} is in FLAGS but parse_args doesn't read it")
+            }
+            _ => positionals.push(arg),
+        }
+    }
+
+    // Anything that isn't a subcommand is a directory to check, as is whatever
+    // follows `fetch`, `pull`, `push`, `gc` or `exec`.
+    let split = match positionals.first().map(String::as_str) {
+        Some("fetch" | "pull" | "push" | "gc" | "exec") => 1,
+        Some(first) if !SUBCOMMANDS.contains(&first) => 0,
+        _ => positionals.len(),
+    };
+    args.dirs = positionals.split_off(split);
+    if !args.dirs.is_empty() && args.section.is_some() {
+        return Err("--section cannot be combined with directory arguments".into());
+    }
+    if !args.dirs.is_empty() && (args.config.is_some() || args.profile.is_some()) {
+        return Err("--config and --profile cannot be combined with directory arguments".into());
+    }
+    if !args.dirs.is_empty() && args.diff {
+        return Err("--diff cannot be combined with directory arguments".into());
+    }
+
+    args.command = match positionals
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] if update_self => Subcommand::UpdateSelf,
+        [] | ["status"] => Subcommand::Status,
+        ["status", dir] => {
+            args.dirs = vec![dir.to_string()];
+            args.single = true;
+            Subcommand::Status
+        }
+        ["status", ..] => return Err("status takes a single directory".into()),
+        ["fetch"] => Subcommand::Fetch(CommandOpts { dry_run }),
+        ["pull"] => Subcommand::Pull(CommandOpts { dry_run }),
+        ["push"] => Subcommand::Push(PushOpts {
+            dry_run,
+            set_upstream,
+            confirm,
+        }),
+        ["gc"] => Subcommand::Gc(CommandOpts { dry_run }),
+        ["exec"] => match exec_command.take() {
+            Some(command) if !command.is_empty() => {
+                Subcommand::Exec(ExecOpts { command, fail_fast })
+            }
+            _ => {
+                return Err(
+                    "exec requires a command after --, e.g. jiancha exec -- git status".into(),
+                )
+            }
+        },
+        ["list"] => Subcommand::List { json },
+        ["path"] if all => Subcommand::Path(None),
+        ["path", repo] if !all => Subcommand::Path(Some(repo.to_string())),
+        ["open", repo] => Subcommand::Open {
+            repo: repo.to_string(),
+            shell,
+        },
+        ["open"] => return Err("open requires a repo number or name".into()),
+        ["browse", repo] => Subcommand::Browse {
+            repo: repo.to_string(),
+            target: browse,
+        },
+        ["browse"] => return Err("browse requires a repo number or name".into()),
+        ["path"] => return Err("path requires a repo number or name, or --all".into()),
+        ["path", _] => return Err("path takes a repo or --all, not both".into()),
+        ["clone", repo] => Subcommand::Clone {
+            repo: Some(repo.to_string()),
+            dry_run,
+        },
+        ["clone"] => Subcommand::Clone {
+            repo: None,
+            dry_run,
+        },
+        ["check-stale"] => Subcommand::CheckStale(StaleOpts {
+            days: days.unwrap_or(180),
+            json,
+            github_archive,
+        }),
+        ["config", "diff"] => Subcommand::ConfigDiff,
+        ["config"] => return Err("config requires a subcommand: diff".into()),
+        ["profiles"] | ["profiles", "list"] => Subcommand::ProfilesList,
+        ["profiles", "create", name] => Subcommand::ProfilesCreate(name.to_string()),
+        ["profiles", "create"] => return Err("profiles create requires a name".into()),
+        ["diagnose", dir] => Subcommand::Diagnose {
+            dir: dir.to_string(),
+            json,
+        },
+        ["diagnose"] => return Err("diagnose requires a directory".into()),
+        ["completions", shell] => {
+            Subcommand::Completions(Shell::parse(shell).ok_or_else(|| {
+                format!("Unknown shell: {shell} (expected bash, zsh, fish, or powershell)")
+            })?)
+        }
+        ["completions"] => {
+            return Err("completions requires a shell: bash, zsh, fish, or powershell".into())
+        }
+        ["man"] => Subcommand::Man,
+        ["__complete", kind] => Subcommand::Complete(kind.to_string()),
+        _ => return Err(format!("Unknown argument: {}", positionals[0]).into()),
+    };
+
+    let stale = matches!(args.command, Subcommand::CheckStale(_));
+    if json
+        && !stale
+        && !matches!(
+            args.command,
+            Subcommand::List { .. } | Subcommand::Diagnose { .. }
+        )
+    {
+        return Err("--json only applies to the list, check-stale and diagnose subcommands".into());
+    }
+    // Tables already write $HOME as ~ (see --paths); list prints raw paths unless asked.
+    if args.relative_paths && !matches!(args.command, Subcommand::List { .. }) {
+        return Err("--relative-paths only applies to the list subcommand".into());
+    }
+    if (days.is_some() || github_archive) && !stale {
+        return Err("--days and --github-archive only apply to the check-stale subcommand".into());
+    }
+    let fetches = matches!(args.command, Subcommand::Fetch(_) | Subcommand::Pull(_));
+    let push = matches!(args.command, Subcommand::Push(_));
+    let clone = matches!(args.command, Subcommand::Clone { .. } | Subcommand::Gc(_));
+    if dry_run && !fetches && !push && !clone {
+        return Err(
+            "--dry-run only applies to the fetch, pull, push, clone and gc subcommands".into(),
+        );
+    }
+    if (set_upstream || confirm) && !push {
+        return Err("--set-upstream and --confirm only apply to the push subcommand".into());
+    }
+    if args.no_header && args.format != OutputFormat::Tsv {
+        return Err("--no-header only applies to --format tsv".into());
+    }
+    if exec_command.is_some() {
+        return Err("-- only applies to the exec subcommand".into());
+    }
+    if update_self && !matches!(args.command, Subcommand::UpdateSelf) {
+        return Err("--update-self doesn't take a subcommand or directories".into());
+    }
+    if all && !matches!(args.command, Subcommand::Path(_)) {
+        return Err("--all only applies to the path subcommand".into());
+    }
+    if fail_fast && !matches!(args.command, Subcommand::Exec(_)) {
+        return Err("--fail-fast only applies to the exec subcommand".into());
+    }
+    if shell && !matches!(args.command, Subcommand::Open { .. }) {
+        return Err("--shell only applies to the open subcommand".into());
+    }
+    if browse != BrowseKind::Repo && !matches!(args.command, Subcommand::Browse { .. }) {
+        return Err("--branch and --commit only apply to the browse subcommand".into());
+    }
+    if (fetches || push) && args.offline {
+        return Err("fetch, pull and push cannot be combined with --offline".into());
+    }
+
+    if args.diff && args.format != OutputFormat::Table {
+        return Err("--diff cannot be combined with --format or --oneline".into());
+    }
+
+    if args.watch.is_some() {
+        if !matches!(args.command, Subcommand::Status) {
+            return Err("--watch only applies to status".into());
+        }
+        if args.machine_json
+            || args.stream
+            || args.diff
+            || args.output.is_some()
+            || args.format != OutputFormat::Table
+        {
+            return Err(
+                "--watch cannot be combined with --machine-json, --stream, --diff, --output or --format"
+                    .into(),
+            );
+        }
+    }
+
+    if args.stream {
+        if !matches!(args.command, Subcommand::Status) && !fetches {
+            return Err("--stream only applies to status, fetch and pull".into());
+        }
+        if args.machine_json
+            || args.diff
+            || args.output.is_some()
+            || args.format != OutputFormat::Table
+        {
+            return Err("--stream cannot be combined with --machine-json, --diff, --output, --format or --oneline".into());
+        }
+        // A repo's status group depends on its fetch, so groups aren't known up front.
+        if args.group_by == GroupBy::Status {
+            return Err("--stream cannot be combined with --group-by status".into());
+        }
+    }
+
+    if args.machine_json {
+        if !matches!(args.command, Subcommand::Status) && !fetches {
+            return Err("--machine-json only applies to status, fetch and pull".into());
+        }
+        if args.diff || args.output.is_some() || args.format != OutputFormat::Table {
+            return Err(
+                "--machine-json cannot be combined with --diff, --output, --format or --oneline"
+                    .into(),
+            );
+        }
+    }
+
+    if args.check_force_push && args.offline {
+        return Err("--check-force-push and --offline cannot be used together".into());
+    }
+
+    if args.fresh && args.offline {
+        return Err("--fresh and --offline cannot be used together".into());
+    }
+
+    if args.config.is_some() && args.profile.is_some() {
+        return Err("--config and --profile cannot be used together".into());
+    }
+
+    if args.offline && matches!(args.command, Subcommand::Clone { .. }) {
+        return Err("clone and --offline cannot be used together".into());
+    }
+
+    if args.offline && github_archive {
+        return Err("--github-archive and --offline cannot be used together".into());
+    }
+
+    Ok(Invocation::Run(Box::new(args)))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn cache_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let cache_dir = if let Ok(xdg) = std::env::var("XDG_CACHE_HOME") {
+        PathBuf::from(xdg)
+    } else if let Ok(home) = std::env::var("HOME") {
+        PathBuf::from(home).join(".cache")
+    } else {
+        return Err("HOME not set".into());
+    };
+    Ok(cache_dir.join("jiancha").join("cache.toml"))
+}
+
+fn snapshot_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let data_dir = if let Ok(xdg) = std::env::var("XDG_DATA_HOME") {
+        PathBuf::from(xdg)
+    } else if let Ok(home) = std::env::var("HOME") {
+        PathBuf::from(home).join(".local").join("share")
+    } else {
+        return Err("HOME not set".into());
+    };
+    Ok(data_dir.join("jiancha").join("snapshot.toml"))
+}
+
+fn row_index_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let state_dir = if let Ok(xdg) = std::env::var("XDG_STATE_HOME") {
+        PathBuf::from(xdg)
+    } else if let Ok(home) = std::env::var("HOME") {
+        PathBuf::from(home).join(".local").join("state")
+    } else {
+        return Err("HOME not set".into());
+    };
+    Ok(state_dir.join("jiancha").join("rows.toml"))
+}
+
+/// Every configured repo in table order, which is what row numbers count through.
+fn row_entries(config: &Config) -> Vec<RowEntry> {
+    config
+        .sections
+        .iter()
+        .flat_map(|(section_name, section)| {
+            section.repos.iter().map(|(repo, directory)| RowEntry {
+                section: section_name.clone(),
+                repo: repo.clone(),
+                directory: directory.clone(),
+            })
+        })
+        .collect()
+}
+
+/// A repo reference that fits more than one repo; the message lists them.
+#[derive(Debug)]
+struct AmbiguousRepo(String);
+
+impl std::fmt::Display for AmbiguousRepo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for AmbiguousRepo {}
+
+/// `path --all`: `name<TAB>directory` per repo, for fzf and the like. A name in
+/// more than one section is written `section/name`, as `path` needs it.
+fn render_path_list(rows: &[RowEntry], section: Option<&str>) -> String {
+    let mut out = String::new();
+    for row in rows
+        .iter()
+        .filter(|row| section.is_none_or(|s| s == row.section))
+    {
+        let shared = rows.iter().filter(|other| other.repo == row.repo).count() > 1;
+        let name = if shared {
+            format!("{}/{}", row.section, row.repo)
+        } else {
+            row.repo.clone()
+        };
+        out.push_str(&format!("{name}\t{}\n", absolute_dir(&row.directory)));
+    }
+    out
+}
+
+/// A configured directory as an absolute path, for `cd` from anywhere.
+fn absolute_dir(dir: &str) -> String {
+    std::path::absolute(dir).map_or_else(|_| dir.to_string(), |p| p.display().to_string())
+}
+
+/// Looks a repo up by row number, `section/name`, or a name unique across sections,
+/// falling back to a unique prefix and then a unique substring of a name.
+/// Row numbers are only trusted while the config still lists the same repos in the
+/// same order as the run that showed them.
+fn resolve_repo(
+    config: &Config,
+    rows: &[RowEntry],
+    reference: &str,
+) -> Result<RowEntry, Box<dyn std::error::Error>> {
+    if let Ok(number) = reference.parse::<usize>() {
+        let path = row_index_path()?;
+        let saved = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| toml::from_str::<RowIndex>(&contents).ok())
+            .ok_or("No row numbers yet; run jiancha --numbers first")?;
+        if saved.rows != rows {
+            return Err(
+                "Row numbers are out of date: the config changed since the last run. \
+                 Run jiancha --numbers again"
+                    .into(),
+            );
+        }
+        return number
+            .checked_sub(1)
+            .and_then(|i| rows.get(i))
+            .cloned()
+            .ok_or_else(|| {
+                format!("No row {number}: the last run had {} rows", rows.len()).into()
+            });
+    }
+
+    let matches: Vec<&RowEntry> = match reference.split_once('/') {
+        Some((section, repo)) if config.sections.contains_key(section) => rows
+            .iter()
+            .filter(|row| row.section == section && row.repo == repo)
+            .collect(),
+        _ => rows.iter().filter(|row| row.repo == reference).collect(),
+    };
+    if matches.is_empty() {
+        let needle = reference.to_lowercase();
+        let loose = |is_match: &dyn Fn(&str) -> bool| -> Vec<&RowEntry> {
+            rows.iter()
+                .filter(|row| is_match(&row.repo.to_lowercase()))
+                .collect()
+        };
+        let mut candidates = loose(&|name| name.starts_with(&needle));
+        if candidates.is_empty() {
+            candidates = loose(&|name| name.contains(&needle));
+        }
+        return match candidates.as_slice() {
+            [] => Err(format!("No repo named {reference}").into()),
+            [row] => Ok((*row).clone()),
+            _ => {
+                let names: Vec<String> = candidates
+                    .iter()
+                    .map(|row| format!("{}/{}", row.section, row.repo))
+                    .collect();
+                Err(AmbiguousRepo(format!(
+                    "{reference} matches more than one repo: {}",
+                    names.join(", ")
+                ))
+                .into())
+            }
+        };
+    }
+    match matches.as_slice() {
+        [] => Err(format!("No repo named {reference}").into()),
+        [row] => Ok((*row).clone()),
+        _ => {
+            let sections: Vec<&str> = matches.iter().map(|row| row.section.as_str()).collect();
+            Err(AmbiguousRepo(format!(
+                "{reference} is in more than one section ({}); use <section>/{reference}",
+                sections.join(", ")
+            ))
+            .into())
+        }
+    }
+}
+
+/// Snapshots from a newer, incompatible format are ignored rather than misread.
+fn load_snapshot(path: &Path) -> Option<Snapshot> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| toml::from_str::<Snapshot>(&contents).ok())
+        .filter(|snapshot| snapshot.version <= SNAPSHOT_VERSION)
+}
+
+fn snapshot_entries(repos: &[RepoResult]) -> Vec<SnapshotEntry> {
+    repos
+        .iter()
+        .map(|repo| SnapshotEntry {
+            section: repo.section.clone(),
+            repo: repo.repo_key.clone(),
+            directory: repo.directory.clone(),
+            state: repo_state(repo).name().to_string(),
+            branch: repo.branch.clone(),
+            ahead: repo.ahead,
+            behind: match repo.fetch_status {
+                FetchStatus::UpToDate => Some(0),
+                FetchStatus::Behind(n) => Some(n),
+                _ => None,
+            },
+            todos: repo.todos,
+            head: repo.head.clone(),
+            clean_streak: repo.clean_streak,
+        })
+        .collect()
+}
+
+/// How many runs in a row have found a repo's work tree clean, carried from its
+/// entry in the previous snapshot; the snapshot keeps only the latest run, so the
+/// count lives in the entry. Only `counted` runs (a plain `jiancha`, not a watch
+/// redraw or a fetch, push, gc or exec) add to it, but anything but a clean work
+/// tree starts it over.
+fn compute_streak(previous: Option<&SnapshotEntry>, repo: &RepoResult, counted: bool) -> u32 {
+    if repo.local_error.is_some() || repo.clean != Some(true) {
+        return 0;
+    }
+    previous.map_or(0, |entry| entry.clean_streak) + u32::from(counted)
+}
+
+/// Repo states most urgent first, the order summaries list them in.
+const STATES_BY_SEVERITY: [RepoState; 6] = [
+    RepoState::Error,
+    RepoState::Conflict,
+    RepoState::Dirty,
+    RepoState::Unpushed,
+    RepoState::NoUpstream,
+    RepoState::Clean,
+];
+
+/// ` M work/api` per repo that isn't clean, like `git status --porcelain`: `M`
+/// dirty, `U` conflicted, `↑` unpushed, `E` unreadable, and `?` for a path that
+/// doesn't exist. Clean repos, and ones without an upstream, print nothing.
+fn render_porcelain(results: &[RepoResult]) -> String {
+    let mut out = String::new();
+    for repo in results {
+        let code = match repo_state(repo) {
+            RepoState::Clean | RepoState::NoUpstream => continue,
+            RepoState::Dirty => 'M',
+            RepoState::Conflict => 'U',
+            RepoState::Unpushed => '↑',
+            RepoState::Error if Path::new(&repo.directory).exists() => 'E',
+            RepoState::Error => '?',
+        };
+        out.push_str(&format!(" {code} {}/{}\n", repo.section, repo.repo_key));
+    }
+    out
+}
+
+/// What `--format tsv` lists without `--columns`.
+const DELIMITED_COLUMNS: [Column; 7] = [
+    Column::Section,
+    Column::Repository,
+    Column::Branch,
+    Column::Status,
+    Column::LastCommit,
+    Column::Remote,
+    Column::Error,
+];
+
+/// One line per repo of `columns`, split by `sep`, after a header of column keys.
+/// Status and Remote are state names, as in `[settings.symbols]`; the other
+/// columns read as their table cells do. Backslashes, tabs and line breaks are
+/// written `\\`, `\t`, `\n` and `\r`, so every line is one repo.
+fn render_delimited(
+    results: &[RepoResult],
+    columns: &[Column],
+    header: bool,
+    sep: char,
+    opts: &RenderOptions,
+) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+    }
+
+    let mut out = String::new();
+    let mut push_line = |fields: Vec<String>| {
+        let fields: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+        out.push_str(&fields.join(&sep.to_string()));
+        out.push('\n');
+    };
+    if header {
+        push_line(columns.iter().map(|c| c.key().to_string()).collect());
+    }
+    for repo in results {
+        push_line(
+            columns
+                .iter()
+                .map(|&column| match column {
+                    Column::Status => repo_state(repo).name().to_string(),
+                    Column::Remote => match repo.fetch_status {
+                        FetchStatus::Pending => "pending",
+                        FetchStatus::UpToDate => "up_to_date",
+                        FetchStatus::Behind(_) => "behind",
+                        FetchStatus::Error => "fetch_error",
+                        FetchStatus::Skipped => "skipped",
+                    }
+                    .to_string(),
+                    Column::LastCommit => repo.last_commit.clone().unwrap_or_default(),
+                    Column::Error => repo.local_error.clone().unwrap_or_default(),
+                    column => render_cell(column, repo, opts, u16::MAX).content(),
+                })
+                .collect(),
+        );
+    }
+    out
+}
+
+/// `--compact`: `[section]\trepo\tbranch\tstatus\tsubject` for every repo, in
+/// the order the tables would list them, with the error in place of the subject
+/// for repos that couldn't be read.
+fn render_compact(
+    repos: &[RepoResult],
+    groups: &IndexMap<String, Vec<usize>>,
+    opts: &RenderOptions,
+) -> String {
+    let mut out = String::new();
+    for &idx in groups.values().flatten() {
+        let repo = &repos[idx];
+        let mut status = status_text(repo, opts);
+        let color = opts.symbols.for_state(repo_state(repo)).color;
+        if let Some(fg) = fg_sgr(color).filter(|_| opts.color) {
+            status = format!("\x1b[{fg}m{status}\x1b[0m");
+        }
+        let subject = repo.local_error.as_deref().or(repo.last_commit.as_deref());
+        out.push_str(&format!(
+            "[{}]\t{}\t{}\t{status}\t{}\n",
+            repo.section,
+            repo.repo_key,
+            repo.branch.as_deref().unwrap_or("-"),
+            subject.unwrap_or("-")
+        ));
+    }
+    out
+}
+
+/// `repos: 3✗ 2↑ 1⚠ 44✓`, skipping states nobody is in. With `counts`, every
+/// state's count in `STATES_BY_SEVERITY` order, so positions stay stable for scripts.
+fn render_oneline(repos: &[RepoResult], opts: &RenderOptions, counts: bool) -> String {
+    let tally: Vec<(RepoState, usize)> = STATES_BY_SEVERITY
+        .iter()
+        .map(|&state| {
+            let n = repos
+                .iter()
+                .filter(|repo| repo_state(repo) == state)
+                .count();
+            (state, n)
+        })
+        .collect();
+
+    if counts {
+        let numbers: Vec<String> = tally.iter().map(|(_, n)| n.to_string()).collect();
+        return format!("{}\n", numbers.join(" "));
+    }
+
+    let parts: Vec<String> = tally
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|&(state, n)| {
+            let symbol = opts.symbols.for_state(state);
+            match fg_sgr(symbol.color).filter(|_| opts.color) {
+                Some(fg) => format!("\x1b[{fg}m{n}{}\x1b[0m", symbol.glyph),
+                None => format!("{n}{}", symbol.glyph),
+            }
+        })
+        .collect();
+    format!("repos: {}\n", parts.join(" "))
+}
+
+/// The GitHub Actions annotation for a repo in `state`: `error` for errors and
+/// conflicts, `warning` for the rest, and none for a clean repo.
+fn github_level(state: RepoState) -> Option<&'static str> {
+    match state {
+        RepoState::Clean => None,
+        RepoState::Error | RepoState::Conflict => Some("error"),
+        RepoState::Dirty | RepoState::Unpushed | RepoState::NoUpstream => Some("warning"),
+    }
+}
+
+/// One GitHub Actions annotation per repo that isn't clean; see `github_level`.
+fn render_github(repos: &[RepoResult]) -> String {
+    // Workflow command data must not contain raw `%`, CR or LF.
+    fn escape(s: &str) -> String {
+        s.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    }
+
+    let mut out = String::new();
+    for repo in repos {
+        let state = repo_state(repo);
+        let Some(level) = github_level(state) else {
+            continue;
+        };
+        let mut message = format!("{}/{}: {}", repo.section, repo.repo_key, state.name());
+        if let Some(error) = &repo.local_error {
+            message.push_str(&format!(" ({error})"));
+        }
+        out.push_str(&format!("::{level} title=jiancha::{}\n", escape(&message)));
+    }
+    out
+}
+
+/// Renders the scan in the Prometheus text exposition format, for node_exporter's
+/// textfile collector. Ahead/behind series are omitted for repos where they are unknown.
+fn render_prometheus(repos: &[RepoResult], scan_duration: Duration) -> String {
+    fn label_value(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    type Metric = (&'static str, &'static str, fn(&RepoResult) -> Option<u32>);
+    let metrics: [Metric; 4] = [
+        (
+            "jiancha_repo_dirty",
+            "Whether the repo has uncommitted changes (1) or not (0).",
+            |repo| repo.clean.map(|clean| u32::from(!clean)),
+        ),
+        (
+            "jiancha_repo_ahead",
+            "Number of local commits not pushed to the upstream.",
+            |repo| repo.ahead,
+        ),
+        (
+            "jiancha_repo_behind",
+            "Number of upstream commits not yet pulled.",
+            |repo| match repo.fetch_status {
+                FetchStatus::UpToDate => Some(0),
+                FetchStatus::Behind(n) => Some(n),
+                _ => None,
+            },
+        ),
+        (
+            "jiancha_repo_error",
+            "Whether the repo could not be inspected or fetched (1) or not (0).",
+            |repo| {
+                Some(u32::from(
+                    repo.local_error.is_some() || matches!(repo.fetch_status, FetchStatus::Error),
+                ))
+            },
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, value) in metrics {
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} gauge\n"));
+        for repo in repos {
+            if let Some(v) = value(repo) {
+                out.push_str(&format!(
+                    "{name}{{repo=\"{}\",section=\"{}\"}} {v}\n",
+                    label_value(&repo.repo_key),
+                    label_value(&repo.section)
+                ));
+            }
+        }
+    }
+    out.push_str(
+        "# HELP jiancha_repo_scan_duration_seconds Time taken to scan the repo, fetch included.\n\
+         # TYPE jiancha_repo_scan_duration_seconds gauge\n",
+    );
+    for repo in repos {
+        out.push_str(&format!(
+            "jiancha_repo_scan_duration_seconds{{repo=\"{}\",section=\"{}\"}} {:.3}\n",
+            label_value(&repo.repo_key),
+            label_value(&repo.section),
+            repo.scan_duration.as_secs_f64()
+        ));
+    }
+    out.push_str(&format!(
+        "# HELP jiancha_scan_duration_seconds Time taken to scan all repos.\n\
+         # TYPE jiancha_scan_duration_seconds gauge\n\
+         jiancha_scan_duration_seconds {:.3}\n",
+        scan_duration.as_secs_f64()
+    ));
+    out
+}
+
+/// Writes one repo as a single line of JSON, for `--machine-json`. Unknown
+/// values are left out rather than written as `null`.
+fn write_ndjson_result(result: &RepoResult, writer: &mut impl Write) -> std::io::Result<()> {
+    let table = toml::Value::Table(result_fields(result));
+    writeln!(writer, "{}", to_json(&table))?;
+    writer.flush()
+}
+
+/// `--format yaml`: a list of the same mappings `--machine-json` writes as lines.
+/// Values are written as JSON, which YAML reads the same way.
+fn render_yaml(repos: &[RepoResult]) -> String {
+    let mut out = format!(
+        "# generated by {} v{}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    if repos.is_empty() {
+        out.push_str("[]\n");
+    }
+    for repo in repos {
+        for (i, (key, value)) in result_fields(repo).iter().enumerate() {
+            let marker = if i == 0 { "- " } else { "  " };
+            out.push_str(&format!("{marker}{key}: {}\n", to_json(value)));
+        }
+    }
+    out
+}
+
+/// A repo's fields for `--machine-json` and `--format yaml`; unknown ones are left out.
+fn result_fields(result: &RepoResult) -> toml::map::Map<String, toml::Value> {
+    use toml::Value;
+
+    let mut table = toml::map::Map::new();
+    let mut put = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            table.insert(key.to_string(), value);
+        }
+    };
+    let string = |s: &Option<String>| s.clone().map(Value::String);
+    let integer = |n: Option<u32>| n.map(|n| Value::Integer(n.into()));
+    put("section", Some(Value::String(result.section.clone())));
+    put("repo", Some(Value::String(result.repo_key.clone())));
+    put("directory", Some(Value::String(result.directory.clone())));
+    put("branch", string(&result.branch));
+    put("upstream", string(&result.upstream));
+    put("remote_url", string(&result.remote_url));
+    put("clean", result.clean.map(Value::Boolean));
+    put("conflicted", Some(Value::Boolean(result.conflicted)));
+    put("ahead", integer(result.ahead));
+    let (fetch, behind) = match result.fetch_status {
+        FetchStatus::Pending => ("pending", None),
+        FetchStatus::UpToDate => ("up_to_date", Some(0)),
+        FetchStatus::Behind(n) => ("behind", Some(n)),
+        FetchStatus::Error => ("error", None),
+        FetchStatus::Skipped => ("skipped", None),
+    };
+    put("behind", integer(behind));
+    put("fetch", Some(Value::String(fetch.to_string())));
+    put("last_commit", string(&result.last_commit));
+    if let Some(stat) = result.diffstat {
+        put("files_changed", integer(Some(stat.files)));
+        put("insertions", integer(Some(stat.insertions)));
+        put("deletions", integer(Some(stat.deletions)));
+    }
+    put(
+        "last_commit_at",
+        result
+            .last_commit_at
+            .and_then(|t| i64::try_from(t).ok())
+            .map(Value::Integer),
+    );
+    put("error", string(&result.local_error));
+    put("action", string(&result.action));
+    put(
+        "scan_ms",
+        i64::try_from(result.scan_duration.as_millis())
+            .ok()
+            .map(Value::Integer),
+    );
+    table
+}
+
+fn render_diff(previous: Option<&Snapshot>, current: &[SnapshotEntry], now: u64) -> String {
+    let Some(previous) = previous else {
+        return "No previous snapshot; saved the current state for next time.\n".to_string();
+    };
+
+    fn show<T: ToString>(value: &Option<T>) -> String {
+        value.as_ref().map_or("-".to_string(), T::to_string)
+    }
+
+    let key = |e: &SnapshotEntry| (e.section.clone(), e.repo.clone());
+    let old: HashMap<_, _> = previous.repos.iter().map(|e| (key(e), e)).collect();
+    let new_keys: HashSet<_> = current.iter().map(key).collect();
+
+    let mut lines = Vec::new();
+    for entry in current {
+        let Some(before) = old.get(&key(entry)) else {
+            lines.push(format!("+ {}/{} (added)", entry.section, entry.repo));
+            continue;
+        };
+
+        let mut changes = Vec::new();
+        if before.state != entry.state {
+            changes.push(format!("state {} → {}", before.state, entry.state));
+        }
+        if before.branch != entry.branch {
+            changes.push(format!(
+                "branch {} → {}",
+                show(&before.branch),
+                show(&entry.branch)
+            ));
+        }
+        if before.ahead != entry.ahead {
+            changes.push(format!(
+                "ahead {} → {}",
+                show(&before.ahead),
+                show(&entry.ahead)
+            ));
+        }
+        if before.behind != entry.behind {
+            changes.push(format!(
+                "behind {} → {}",
+                show(&before.behind),
+                show(&entry.behind)
+            ));
+        }
+        if !changes.is_empty() {
+            lines.push(format!(
+                "~ {}/{}: {}",
+                entry.section,
+                entry.repo,
+                changes.join(", ")
+            ));
+        }
+    }
+    for entry in &previous.repos {
+        if !new_keys.contains(&key(entry)) {
+            lines.push(format!("- {}/{} (removed)", entry.section, entry.repo));
+        }
+    }
+
+    let since = format_relative(now.saturating_sub(previous.taken_at));
+    if lines.is_empty() {
+        format!("No changes since the last run ({since}).\n")
+    } else {
+        format!(
+            "Changes since the last run ({since}):\n{}\n",
+            lines.join("\n")
+        )
+    }
+}
+
+fn load_cache(path: &Path) -> Cache {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Writes via a sibling temp file and a rename so readers never see a partial file.
+fn write_atomic(path: &Path, contents: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // Per-process, so concurrent runs never write into each other's temp file.
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+fn save_cache(path: &Path, cache: &Cache) -> Result<(), Box<dyn std::error::Error>> {
+    write_atomic(path, &toml::to_string_pretty(cache)?)
+}
+
+fn cached_fetch_status(entry: &CacheEntry, repo: &RepoResult, now: u64) -> Option<FetchStatus> {
+    if entry.branch != repo.branch.as_deref().unwrap_or("") || entry.upstream != repo.upstream {
+        return None;
+    }
+
+    let age = now.saturating_sub(entry.fetched_at);
+    let ttl = match entry.fetch_status {
+        FetchStatus::Error => ERROR_RETRY_TTL_SECS,
+        _ => REMOTE_TTL_SECS,
+    };
+
+    (age <= ttl).then(|| entry.fetch_status.clone())
+}
+
+/// `stty size` field: 0 is rows, 1 is columns.
+fn stty_size_field(field: usize) -> Option<u16> {
+    Command::new("sh")
+        .args(["-c", "stty size < /dev/tty"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .and_then(|s| {
+            s.split_whitespace()
+                .nth(field)
+                .and_then(|w| w.parse::<u16>().ok())
+        })
+        .filter(|&w| w > 0)
+}
+
+fn terminal_height() -> Option<u16> {
+    stty_size_field(0).or_else(|| {
+        std::env::var("LINES")
+            .ok()
+            .and_then(|s| s.parse::<u16>().ok())
+            .filter(|&h| h > 0)
+    })
+}
+
+fn terminal_width() -> Option<u16> {
+    stty_size_field(1)
+        .or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|s| s.parse::<u16>().ok())
+                .filter(|&w| w > 0)
+        })
+        .or_else(|| Table::new().width())
+}
+
+const DEFAULT_SECTION_RULE_WIDTH: u16 = 27;
+
+/// A day, a week, a month, six months.
+const DEFAULT_COMMIT_AGE_DAYS: [u64; 4] = [1, 7, 30, 180];
+
+/// `n` with its digits grouped by thousands for `NumberFormat::Locale`: `1,000`
+/// where the locale (`LC_ALL`, then `LC_NUMERIC`, then `LANG`) writes decimals
+/// with a point or is unset, `1_000` where a comma is the decimal mark.
+fn format_number(n: u64, fmt: NumberFormat) -> String {
+    let digits = n.to_string();
+    if fmt == NumberFormat::Plain || digits.len() <= 3 {
+        return digits;
+    }
+    let decimal_point = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .is_none_or(|locale| {
+            let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+            matches!(
+                language,
+                "C" | "POSIX" | "en" | "ja" | "zh" | "ko" | "he" | "th" | "hi" | "ga" | "ms"
+            )
+        });
+    let separator = if decimal_point { ',' } else { '_' };
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(separator);
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Whether the locale (`LC_ALL`, then `LC_CTYPE`, then `LANG`) uses UTF-8. An unset
+/// locale is the POSIX `C` locale, which doesn't.
+fn locale_is_utf8() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
+fn section_rule(width: Option<u16>, rule: &str) -> String {
+    let len = width
+        .map(|w| w.min(DEFAULT_SECTION_RULE_WIDTH))
+        .unwrap_or(DEFAULT_SECTION_RULE_WIDTH) as usize;
+    rule.repeat(len)
+}
+
+/// Cuts `s` to `max_len` characters, ending in `...` when anything was dropped.
+fn truncate_string(s: &str, max_len: usize) -> String {
+    if s.chars().count() <= max_len {
+        s.to_string()
+    } else {
+        let kept: String = s.chars().take(max_len.saturating_sub(3)).collect();
+        format!("{kept}...")
+    }
+}
+
+/// Cuts the middle out of `s` so it fits `max_len` characters, keeping up to
+/// `lead` leading characters (at most half) and as much of the end as
+/// fits: `feature/JIRA-1234-implement-new-billing-flow` → `feature/…billing-flow`.
+fn truncate_middle(s: &str, max_len: usize, lead: usize, ellipsis: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    if chars.len() <= max_len {
+        return s.to_string();
+    }
+    let room = max_len.saturating_sub(ellipsis.chars().count());
+    if room == 0 {
+        return chars[..max_len].iter().collect();
+    }
+    let lead = lead.min(room.div_ceil(2));
+    let tail = room - lead;
+    let head: String = chars[..lead].iter().collect();
+    let end: String = chars[chars.len() - tail..].iter().collect();
+    format!("{head}{ellipsis}{end}")
+}
+
+/// Shortens cell text with the column's configured strategy.
+fn fit_text(column: Column, s: &str, max_len: usize, opts: &RenderOptions) -> String {
+    match opts.truncate.for_column(column) {
+        Truncate::Middle => truncate_middle(s, max_len, opts.truncate_lead, opts.glyphs.ellipsis),
+        Truncate::End => truncate_string(s, max_len),
+    }
+}
+
+/// `path` with a leading `home` written as `~`; paths elsewhere are unchanged.
+/// Only for display: git is always given the real path.
+fn display_path(path: &str, home: &str) -> String {
+    let home = home.trim_end_matches('/');
+    if home.is_empty() {
+        return path.to_string();
+    }
+    match path.strip_prefix(home) {
+        Some("") => "~".to_string(),
+        Some(rest) if rest.starts_with('/') => format!("~{rest}"),
+        _ => path.to_string(),
+    }
+}
+
+/// Replaces a leading `home` with `~`, then drops middle components
+/// ("~/work/…/api") until the path fits in `max_len` characters. The last
+/// component is kept whole as long as possible since it is the informative part.
+fn shorten_path(path: &str, home: Option<&str>, max_len: usize, ellipsis: &str) -> String {
+    let path = display_path(path, home.unwrap_or(""));
+    if path.chars().count() <= max_len {
+        return path;
+    }
+
+    let parts: Vec<&str> = path.split('/').collect();
+    if parts.len() > 2 {
+        let last = parts[parts.len() - 1];
+        for keep in (1..parts.len() - 1).rev() {
+            let candidate = format!("{}/{ellipsis}/{}", parts[..keep].join("/"), last);
+            if candidate.chars().count() <= max_len {
+                return candidate;
+            }
+        }
+        let candidate = format!("{ellipsis}/{last}");
+        if candidate.chars().count() <= max_len {
+            return candidate;
+        }
+    }
+
+    let tail: String = path
+        .chars()
+        .rev()
+        .take(max_len.saturating_sub(ellipsis.chars().count()))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    format!("{ellipsis}{tail}")
+}
+
+struct RenderOptions<'a> {
+    symbols: &'a Symbols,
+    show_fetch_time: bool,
+    /// `--numbers`: each repo's row number, keyed by (section, repo).
+    row_numbers: Option<HashMap<(String, String), usize>>,
+    /// `--columns`: which columns to show, in this order.
+    column_order: Option<Vec<Column>>,
+    /// `--column-alias`: header text by column key.
+    column_aliases: HashMap<String, String>,
+    truncate: &'a TruncateConfig,
+    truncate_lead: usize,
+    /// Wrap long commit subjects onto extra lines instead of truncating them.
+    wrap: bool,
+    branch_colors: bool,
+    /// Fade the Last Commit column by age, at these day thresholds.
+    commit_age_days: Option<[u64; 4]>,
+    /// `--min-clean-streak`: badge repos with at least this long a clean streak
+    /// whose newest commits are all on the base branch.
+    min_clean_streak: Option<u32>,
+    align: &'a AlignConfig,
+    padding: (u16, u16),
+    glyphs: &'a GlyphSet,
+    theme: &'a Theme,
+    /// Sections whose Repository column shows the path instead of the config name.
+    path_sections: HashSet<String>,
+    home: Option<String>,
+    show_size: bool,
+    size_threshold: Option<DiskSize>,
+    show_contributors: bool,
+    /// `--show-lfs`: add the LFS storage column.
+    show_lfs: bool,
+    /// `--show-hooks`: add the pre-commit hook column.
+    show_hooks: bool,
+    /// `--show-envrc`: add the column marking repos with an `.envrc`.
+    show_envrc: bool,
+    /// `--show-contributing`: add the column of contribution guidelines and README.
+    show_contributing: bool,
+    /// `--show-diffstat`: add the column of lines changed in dirty work trees.
+    show_diffstat: bool,
+    /// `jiancha fetch`: add the column of refs each fetch changed.
+    show_changes: bool,
+    /// `--security`: add the HEAD signature column.
+    security: bool,
+    /// Nothing was fetched, so the footer warns that behind counts may be stale.
+    offline: bool,
+    /// `--timings`: add the per-repo scan time column.
+    timings: bool,
+    /// `--deep`: add the TODO/FIXME count column.
+    deep: bool,
+    /// Wall-clock time for the whole run, shown in the footer.
+    scan_duration: Duration,
+    group_by: GroupBy,
+    highlight_errors: bool,
+    /// Shows the Committed column in this format when set.
+    date_format: Option<DateFormat>,
+    number_format: NumberFormat,
+    now: u64,
+    icons: bool,
+    /// Emit ANSI styling at all; resolved from `--color` and the output target.
+    color: bool,
+    /// Room for the table when writing to a terminal; `None` keeps full widths.
+    viewport_width: Option<u16>,
+}
+
+fn repo_label(repo: &RepoResult, opts: &RenderOptions, max_len: usize) -> String {
+    let (prefix, max_len) = if opts.icons {
+        let icon = repo.language.map_or(NERD_GIT_ICON, Language::icon);
+        (format!("{icon} "), max_len.saturating_sub(2))
+    } else {
+        (String::new(), max_len)
+    };
+    let label = if opts.path_sections.contains(&repo.section) {
+        shorten_path(
+            &repo.directory,
+            opts.home.as_deref(),
+            max_len,
+            opts.glyphs.ellipsis,
+        )
+    } else {
+        fit_text(Column::Repository, &repo.repo_key, max_len, opts)
+    };
+    format!("{prefix}{label}")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Number,
+    Section,
+    Repository,
+    Branch,
+    Status,
+    LastCommit,
+    Remote,
+    Committed,
+    Fetched,
+    Size,
+    Contributors,
+    Lfs,
+    Hooks,
+    Envrc,
+    Docs,
+    Diffstat,
+    Security,
+    Duration,
+    Todos,
+    Action,
+    Changes,
+    Error,
+}
+
+impl AlignConfig {
+    fn for_column(&self, column: Column) -> Option<Align> {
+        match column {
+            Column::Number => self.number,
+            Column::Section => self.section,
+            Column::Repository => self.repository,
+            Column::Branch => self.branch,
+            Column::Status => self.status,
+            Column::LastCommit => self.last_commit,
+            Column::Committed => self.committed,
+            Column::Fetched => self.fetched,
+            Column::Remote => self.remote,
+            Column::Size => self.size,
+            Column::Contributors => self.contributors,
+            Column::Lfs => self.lfs,
+            Column::Hooks => self.hooks,
+            Column::Envrc => self.envrc,
+            Column::Docs => self.docs,
+            Column::Diffstat => self.diffstat,
+            Column::Security => self.security,
+            Column::Duration => self.duration,
+            Column::Todos => self.todos,
+            Column::Action => self.action,
+            Column::Changes => self.changes,
+            Column::Error => self.error,
+        }
+    }
+}
+
+impl TruncateConfig {
+    /// Branch names keep their descriptive tail by default; everything else is cut at the end.
+    fn for_column(&self, column: Column) -> Truncate {
+        let configured = match column {
+            Column::Section => self.section,
+            Column::Repository => self.repository,
+            Column::Branch => self.branch,
+            Column::LastCommit => self.last_commit,
+            Column::Error => self.error,
+            _ => None,
+        };
+        configured.unwrap_or(match column {
+            Column::Branch => Truncate::Middle,
+            _ => Truncate::End,
+        })
+    }
+}
+
+impl From<Align> for CellAlignment {
+    fn from(align: Align) -> Self {
+        match align {
+            Align::Left => CellAlignment::Left,
+            Align::Center => CellAlignment::Center,
+            Align::Right => CellAlignment::Right,
+        }
+    }
+}
+
+impl Column {
+    const ALL: [Column; 22] = [
+        Column::Number,
+        Column::Section,
+        Column::Repository,
+        Column::Branch,
+        Column::Status,
+        Column::LastCommit,
+        Column::Committed,
+        Column::Remote,
+        Column::Fetched,
+        Column::Size,
+        Column::Contributors,
+        Column::Lfs,
+        Column::Hooks,
+        Column::Envrc,
+        Column::Docs,
+        Column::Diffstat,
+        Column::Security,
+        Column::Duration,
+        Column::Todos,
+        Column::Action,
+        Column::Changes,
+        Column::Error,
+    ];
+
+    /// Name used for the column in the config and on the command line.
+    fn key(self) -> &'static str {
+        match self {
+            Column::Number => "number",
+            Column::Section => "section",
+            Column::Repository => "repository",
+            Column::Branch => "branch",
+            Column::Status => "status",
+            Column::LastCommit => "last_commit",
+            Column::Remote => "remote",
+            Column::Committed => "committed",
+            Column::Fetched => "fetched",
+            Column::Size => "size",
+            Column::Contributors => "contributors",
+            Column::Lfs => "lfs",
+            Column::Hooks => "hooks",
+            Column::Envrc => "envrc",
+            Column::Docs => "docs",
+            Column::Diffstat => "diffstat",
+            Column::Security => "security",
+            Column::Duration => "duration",
+            Column::Todos => "todos",
+            Column::Action => "action",
+            Column::Changes => "changes",
+            Column::Error => "error",
+        }
+    }
+
+    fn title(self, full_size: bool) -> &'static str {
+        match (self, full_size) {
+            (Column::Number, _) => "#",
+            (Column::Section, true) => "Section",
+            (Column::Section, false) => "Sec",
+            (Column::Repository, true) => "Repository",
+            (Column::Repository, false) => "Repo",
+            (Column::Branch, true) => "Branch",
+            (Column::Branch, false) => "Br",
+            (Column::Status, true) => "Status",
+            (Column::Status, false) => "St",
+            (Column::LastCommit, true) => "Last Commit",
+            (Column::LastCommit, false) => "Last",
+            (Column::Remote, true) => "Remote",
+            (Column::Remote, false) => "R",
+            (Column::Committed, true) => "Committed",
+            (Column::Committed, false) => "When",
+            (Column::Fetched, true) => "Fetched",
+            (Column::Fetched, false) => "Ftch",
+            (Column::Size, _) => "Size",
+            (Column::Contributors, true) => "Contrib",
+            (Column::Contributors, false) => "Ctb",
+            (Column::Lfs, _) => "LFS",
+            (Column::Hooks, true) => "Hooks",
+            (Column::Hooks, false) => "Hk",
+            (Column::Envrc, true) => "Envrc",
+            (Column::Envrc, false) => "Ev",
+            (Column::Docs, true) => "Docs",
+            (Column::Docs, false) => "Dc",
+            (Column::Diffstat, true) => "Diffstat",
+            (Column::Diffstat, false) => "Df",
+            (Column::Security, true) => "Security",
+            (Column::Security, false) => "Sig",
+            (Column::Duration, _) => "ms",
+            (Column::Todos, true) => "Todos",
+            (Column::Todos, false) => "TD",
+            (Column::Action, _) => "Action",
+            (Column::Changes, true) => "Changes",
+            (Column::Changes, false) => "Chg",
+            (Column::Error, true) => "Error",
+            (Column::Error, false) => "Err",
+        }
+    }
+
+    fn header_color(self, theme: &Theme) -> Color {
+        match self {
+            Column::Repository => theme.repo_header,
+            Column::Number | Column::Section => theme.extra_header,
+            Column::Branch => theme.branch_header,
+            Column::Status => theme.status_header,
+            Column::LastCommit => theme.last_commit_header,
+            Column::Remote => theme.remote_header,
+            Column::Committed
+            | Column::Fetched
+            | Column::Size
+            | Column::Contributors
+            | Column::Lfs
+            | Column::Hooks
+            | Column::Envrc
+            | Column::Docs
+            | Column::Diffstat
+            | Column::Security
+            | Column::Duration
+            | Column::Todos
+            | Column::Action
+            | Column::Changes => theme.extra_header,
+            Column::Error => theme.error_header,
+        }
+    }
+
+    /// Fixed width (including the one-space right padding) at full size.
+    fn full_width(self) -> u16 {
+        match self {
+            Column::Number => 4,
+            Column::Repository => 15,
+            Column::Section => 12,
+            Column::Branch => 18,
+            Column::Status => 8,
+            Column::LastCommit => 35,
+            Column::Remote => 10,
+            Column::Committed => 22,
+            Column::Fetched => 16,
+            Column::Size => 8,
+            Column::Contributors => 8,
+            Column::Lfs => 10,
+            Column::Hooks => 9,
+            Column::Envrc => 7,
+            Column::Docs => 16,
+            Column::Diffstat => 14,
+            Column::Security => 18,
+            Column::Duration => 12,
+            Column::Todos => 11,
+            Column::Action => 13,
+            Column::Changes => 30,
+            Column::Error => 8,
+        }
+    }
+
+    /// Narrowest useful width (including padding) when the terminal is tight.
+    fn min_width(self) -> u16 {
+        match self {
+            Column::Number => 3,
+            Column::Repository => 6,
+            Column::Section => 5,
+            Column::Branch => 5,
+            Column::Status | Column::Remote => 4,
+            Column::LastCommit => 8,
+            Column::Committed | Column::Fetched => 8,
+            Column::Size => 7,
+            Column::Contributors => 4,
+            Column::Lfs => 6,
+            Column::Hooks => 4,
+            Column::Envrc => 4,
+            Column::Docs => 4,
+            Column::Diffstat => 6,
+            Column::Security => 9,
+            Column::Duration => 5,
+            Column::Todos => 6,
+            Column::Action => 8,
+            Column::Changes => 8,
+            Column::Error => 5,
+        }
+    }
+
+    /// Whether the column soaks up space beyond its full width on wide terminals.
+    fn grows(self) -> bool {
+        matches!(
+            self,
+            Column::Repository | Column::Branch | Column::LastCommit
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ColumnWidths {
+    widths: Vec<u16>,
+    /// True when every column got at least its full width, so full titles fit.
+    full_size: bool,
+}
+
+/// Splits `terminal_width` across `columns`. Below the sum of full widths every
+/// column shrinks proportionally towards its minimum; above it, the extra space
+/// goes to the growable columns in proportion to their full widths.
+fn compute_column_widths(terminal_width: u16, columns: &[Column]) -> ColumnWidths {
+    let borders = columns.len() as u16 + 1;
+    let available = terminal_width.saturating_sub(borders) as u32;
+    let full: Vec<u32> = columns.iter().map(|c| c.full_width() as u32).collect();
+    let min: Vec<u32> = columns.iter().map(|c| c.min_width() as u32).collect();
+    let full_total: u32 = full.iter().sum();
+    let min_total: u32 = min.iter().sum();
+
+    let widths: Vec<u32> = if available >= full_total {
+        let growable: u32 = columns
+            .iter()
+            .zip(&full)
+            .filter(|(c, _)| c.grows())
+            .map(|(_, w)| w)
+            .sum();
+        let extra = available - full_total;
+        columns
+            .iter()
+            .zip(&full)
+            .map(|(c, &w)| {
+                if c.grows() && growable > 0 {
+                    w + extra * w / growable
+                } else {
+                    w
+                }
+            })
+            .collect()
+    } else if available > min_total {
+        let spare = available - min_total;
+        let span = full_total - min_total;
+        min.iter()
+            .zip(&full)
+            .map(|(&lo, &hi)| lo + (hi - lo) * spare / span)
+            .collect()
+    } else {
+        min.clone()
+    };
+
+    let mut widths: Vec<u16> = widths.into_iter().map(|w| w as u16).collect();
+    // Integer division leaves a few columns unassigned; give them to the first column.
+    let used: u32 = widths.iter().map(|&w| w as u32).sum();
+    if let Some(first) = widths.first_mut() {
+        if available > used && available > min_total {
+            *first += (available - used) as u16;
+        }
+    }
+
+    ColumnWidths {
+        widths,
+        full_size: available >= full_total,
+    }
+}
+
+/// Which of the five commit-age buckets `age_secs` falls in: 0 when younger than
+/// `thresholds_days[0]` days, up to 4 at or past `thresholds_days[3]`.
+fn age_bucket(age_secs: u64, thresholds_days: [u64; 4]) -> usize {
+    thresholds_days
+        .iter()
+        .take_while(|&&days| age_secs >= days.saturating_mul(86_400))
+        .count()
+}
+
+/// Off the default branch the Branch cell is tinted; detached HEAD has its own
+/// color. `None` (normal color) on the default branch or when it is unknown.
+fn branch_color(repo: &RepoResult, opts: &RenderOptions) -> Option<Color> {
+    if !opts.branch_colors {
+        return None;
+    }
+    let branch = repo.branch.as_deref()?;
+    if branch == "HEAD" {
+        return Some(opts.theme.detached_head);
+    }
+    let default = repo.default_branch.as_deref()?;
+    (branch != default).then_some(opts.theme.feature_branch)
+}
+
+/// The Status column's text: the state's glyph, then any force-push, base
+/// branch or bisect notes.
+fn status_text(repo: &RepoResult, opts: &RenderOptions) -> String {
+    let symbols = opts.symbols;
+    let mut text = symbols.for_state(repo_state(repo)).glyph.clone();
+    if repo.needs_force_push == Some(true) {
+        text.push_str(opts.glyphs.force_push);
+    }
+    if let Some(n) = repo.ahead_of_base.filter(|&n| n > 0) {
+        text.push_str(&format!(
+            " {}B:{}",
+            symbols.unpushed.glyph,
+            format_number(n.into(), opts.number_format)
+        ));
+    }
+    if repo.stale_tracking_count > 0 {
+        text.push_str(&format!(
+            " GN:{}",
+            format_number(repo.stale_tracking_count.into(), opts.number_format)
+        ));
+    }
+    let on_default_branch = repo.branch.is_some() && repo.branch == repo.default_branch;
+    if on_default_branch
+        && repo.recent_on_base
+        && opts
+            .min_clean_streak
+            .is_some_and(|min| repo.clean_streak >= min)
+    {
+        text.push(' ');
+        text.push_str(opts.glyphs.streak);
+    }
+    if let Some(bisect) = &repo.bisect_state {
+        text.push_str(&match bisect.steps_estimated {
+            0 => " Bisect: started".to_string(),
+            total => format!(
+                " Bisect: step {}/{total}",
+                (bisect.steps_done + 1).min(total)
+            ),
+        });
+    }
+    text
+}
+
+fn render_cell(column: Column, repo: &RepoResult, opts: &RenderOptions, width: u16) -> Cell {
+    let (symbols, theme) = (opts.symbols, opts.theme);
+    let (left, right) = opts.padding;
+    let max_len = width.saturating_sub(left + right + 1) as usize;
+    match column {
+        Column::Number => Cell::new(
+            opts.row_numbers
+                .as_ref()
+                .and_then(|numbers| numbers.get(&(repo.section.clone(), repo.repo_key.clone())))
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        ),
+        Column::Repository => Cell::new(repo_label(repo, opts, max_len)),
+        Column::Section => Cell::new(fit_text(column, &repo.section, max_len, opts)),
+        Column::Branch => {
+            let mut prefix = String::new();
+            if opts.icons && repo.branch.is_some() {
+                prefix = format!("{NERD_BRANCH_ICON} ");
+            }
+            if repo.bisect_state.is_some() {
+                prefix = format!("{}{prefix}", opts.glyphs.bisect);
+            }
+            let room = max_len.saturating_sub(prefix.chars().count());
+            let branch = repo.branch.as_deref().unwrap_or("");
+            let cell = Cell::new(format!("{prefix}{}", fit_text(column, branch, room, opts)));
+            match branch_color(repo, opts) {
+                Some(color) => theme.paint(cell, color),
+                None => cell,
+            }
+        }
+        Column::Status => {
+            // A cell has one color, so stale branches only show through on a clean repo.
+            let state = repo_state(repo);
+            let color = if state == RepoState::Clean && repo.stale_tracking_count > 0 {
+                theme.stale_branches
+            } else {
+                symbols.for_state(state).color
+            };
+            theme
+                .paint(Cell::new(status_text(repo, opts)), color)
+                .add_attribute(Attribute::Bold)
+        }
+        Column::LastCommit => {
+            let cell = match repo.last_commit.as_deref() {
+                // Wrapped subjects are left whole; comfy-table breaks them at the column width.
+                Some(message) if repo.is_merge_commit && opts.wrap => {
+                    Cell::new(format!("{} {message}", opts.glyphs.merge))
+                }
+                Some(message) if repo.is_merge_commit => Cell::new(format!(
+                    "{} {}",
+                    opts.glyphs.merge,
+                    fit_text(
+                        column,
+                        message,
+                        max_len.saturating_sub(opts.glyphs.merge.chars().count() + 1),
+                        opts
+                    )
+                )),
+                message if opts.wrap => Cell::new(message.unwrap_or("")),
+                message => Cell::new(fit_text(column, message.unwrap_or(""), max_len, opts)),
+            };
+            match (opts.commit_age_days, repo.last_commit_at) {
+                (Some(days), Some(ts)) => {
+                    let bucket = age_bucket(opts.now.saturating_sub(ts), days);
+                    theme.paint(cell, theme.commit_age[bucket])
+                }
+                _ => cell,
+            }
+        }
+        Column::Remote => {
+            let (remote_text, remote_color) = match &repo.fetch_status {
+                FetchStatus::Pending => (symbols.pending.glyph.clone(), symbols.pending.color),
+                FetchStatus::Skipped => (symbols.skipped.glyph.clone(), symbols.skipped.color),
+                FetchStatus::UpToDate => {
+                    (symbols.up_to_date.glyph.clone(), symbols.up_to_date.color)
+                }
+                FetchStatus::Behind(n) => (
+                    format!(
+                        "{} {}",
+                        symbols.behind.glyph,
+                        format_number((*n).into(), opts.number_format)
+                    ),
+                    symbols.behind.color,
+                ),
+                FetchStatus::Error => {
+                    (symbols.fetch_error.glyph.clone(), symbols.fetch_error.color)
+                }
+            };
+            theme.paint(Cell::new(remote_text), remote_color)
+        }
+        Column::Size => match repo.size {
+            Some(size) => {
+                let cell = Cell::new(size.to_string());
+                match opts.size_threshold {
+                    Some(threshold) if size.bytes > threshold.bytes => {
+                        theme.paint(cell, theme.warning)
+                    }
+                    _ => cell,
+                }
+            }
+            None => Cell::new("-"),
+        },
+        Column::Todos => Cell::new(match repo.todos {
+            Some(n) => format!("TD:{}", format_number(n.into(), opts.number_format)),
+            None => "-".to_string(),
+        }),
+        // Bare milliseconds, so the column sorts as numbers once copied out.
+        Column::Duration => {
+            let ms = repo.scan_duration.as_millis().to_string();
+            match format!("{ms} cached") {
+                text if repo.info_cached && text.len() <= max_len => Cell::new(text),
+                _ => Cell::new(ms),
+            }
+        }
+        // A lone contributor is a bus-factor risk worth flagging.
+        Column::Contributors => match repo.contributors {
+            Some(1) => theme.paint(Cell::new(1), theme.warning),
+            Some(n) => Cell::new(format_number(n.into(), opts.number_format)),
+            None => Cell::new("-"),
+        },
+        Column::Security => match &repo.signature {
+            Some(Signature::Good { fingerprint }) => {
+                // The long key ID: the fingerprint's last 16 hex digits.
+                let id = &fingerprint[fingerprint.len().saturating_sub(16)..];
+                Cell::new(truncate_string(id, max_len))
+            }
+            Some(Signature::UnknownKey) => theme.paint(Cell::new("UNKNOWN KEY"), theme.unknown_key),
+            Some(Signature::Unsigned) => theme.paint(Cell::new("UNSIGNED"), theme.unsigned),
+            Some(Signature::Bad) => theme.paint(Cell::new("BAD"), theme.unsigned),
+            None => Cell::new("-"),
+        },
+        // Without .lfsconfig, a fresh clone only finds the objects if the remote serves LFS itself.
+        Column::Lfs => {
+            let text = match repo.lfs_storage_bytes {
+                _ if !repo.lfs_enabled => return Cell::new("-"),
+                Some(bytes) => format!("LFS:{}", DiskSize { bytes }),
+                None => "LFS".to_string(),
+            };
+            if repo.has_lfsconfig {
+                Cell::new(text)
+            } else {
+                theme.paint(Cell::new(text), theme.warning)
+            }
+        }
+        Column::Envrc if repo.envrc_present => Cell::new("DV"),
+        Column::Envrc => Cell::new("-"),
+        Column::Docs if repo.local_error.is_some() => Cell::new("-"),
+        Column::Docs => {
+            let mark = |present| {
+                if present {
+                    opts.glyphs.clean
+                } else {
+                    opts.glyphs.dirty
+                }
+            };
+            let text = format!(
+                "{}doc {}readme",
+                mark(repo.has_contributing),
+                mark(repo.has_readme)
+            );
+            let cell = Cell::new(truncate_string(&text, max_len));
+            if repo.has_contributing && repo.has_readme {
+                cell
+            } else {
+                theme.paint(cell, theme.warning)
+            }
+        }
+        Column::Diffstat => match repo.diffstat {
+            Some(stat) => Cell::new(truncate_string(
+                &format!("+{} -{} ({})", stat.insertions, stat.deletions, stat.files),
+                max_len,
+            )),
+            None => Cell::new("-"),
+        },
+        // Configured but not installed means commits skip the checks everyone else runs.
+        Column::Hooks => match repo.precommit_configured {
+            Some(true) => Cell::new("ok"),
+            Some(false) => theme.paint(
+                Cell::new(truncate_string("missing", max_len)),
+                theme.warning,
+            ),
+            None => Cell::new("-"),
+        },
+        Column::Fetched => Cell::new(match (repo.local_error.as_ref(), repo.last_fetch_at) {
+            (Some(_), _) => "-".to_string(),
+            (None, Some(ts)) => {
+                truncate_string(&format_relative(opts.now.saturating_sub(ts)), max_len)
+            }
+            (None, None) => "never".to_string(),
+        }),
+        Column::Committed => Cell::new(match (repo.last_commit_at, opts.date_format) {
+            (Some(ts), Some(fmt)) => truncate_string(&format_date(ts, opts.now, fmt), max_len),
+            _ => "-".to_string(),
+        }),
+        Column::Action => Cell::new(repo.action.as_deref().unwrap_or("-")),
+        // One ref per line: `+new`, `name old..new`, `-deleted`.
+        Column::Changes if repo.ref_updates.is_empty() => Cell::new("-"),
+        Column::Changes => {
+            let lines: Vec<String> = repo
+                .ref_updates
+                .iter()
+                .map(|update| {
+                    let line = match update {
+                        RefUpdate::New(name) => format!("+{name}"),
+                        RefUpdate::Deleted(name) => format!("-{name}"),
+                        RefUpdate::Updated {
+                            name,
+                            old,
+                            new,
+                            forced,
+                        } => {
+                            let dots = if *forced { "..." } else { ".." };
+                            format!("{name} {old}{dots}{new}")
+                        }
+                    };
+                    truncate_string(&line, max_len)
+                })
+                .collect();
+            Cell::new(lines.join("\n"))
+        }
+        Column::Error => Cell::new(fit_text(
+            column,
+            repo.local_error.as_deref().unwrap_or("-"),
+            max_len,
+            opts,
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupBy {
+    Section,
+    Status,
+    None,
+}
+
+/// Headings for `--group-by status`, in severity order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum StatusGroup {
+    Error,
+    Conflict,
+    Dirty,
+    Unpushed,
+    Behind,
+    NoUpstream,
+    Clean,
+}
+
+impl StatusGroup {
+    fn of(repo: &RepoResult) -> Self {
+        let behind = matches!(repo.fetch_status, FetchStatus::Behind(_));
+        match repo_state(repo) {
+            RepoState::Error => StatusGroup::Error,
+            RepoState::Conflict => StatusGroup::Conflict,
+            RepoState::Dirty => StatusGroup::Dirty,
+            RepoState::Unpushed => StatusGroup::Unpushed,
+            RepoState::NoUpstream | RepoState::Clean if behind => StatusGroup::Behind,
+            RepoState::NoUpstream => StatusGroup::NoUpstream,
+            RepoState::Clean => StatusGroup::Clean,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            StatusGroup::Error => "error",
+            StatusGroup::Conflict => "conflict",
+            StatusGroup::Dirty => "dirty",
+            StatusGroup::Unpushed => "unpushed",
+            StatusGroup::Behind => "behind",
+            StatusGroup::NoUpstream => "no upstream",
+            StatusGroup::Clean => "clean",
+        }
+    }
+}
+
+/// Splits repo indices into titled tables, keeping config order within each.
+/// Sections are listed even when empty; status groups only when populated.
+fn group_repos<'a>(
+    repos: &[RepoResult],
+    section_names: impl Iterator<Item = &'a String>,
+    group_by: GroupBy,
+) -> IndexMap<String, Vec<usize>> {
+    let mut groups: IndexMap<String, Vec<usize>> = IndexMap::new();
+    match group_by {
+        GroupBy::Section => {
+            for name in section_names {
+                groups.insert(name.clone(), Vec::new());
+            }
+            for (idx, repo) in repos.iter().enumerate() {
+                groups.entry(repo.section.clone()).or_default().push(idx);
+            }
+        }
+        GroupBy::Status => {
+            let mut by_status: Vec<(StatusGroup, usize)> = repos
+                .iter()
+                .enumerate()
+                .map(|(idx, repo)| (StatusGroup::of(repo), idx))
+                .collect();
+            by_status.sort();
+            for (group, idx) in by_status {
+                groups
+                    .entry(group.title().to_string())
+                    .or_default()
+                    .push(idx);
+            }
+        }
+        GroupBy::None => {
+            groups.insert(String::new(), (0..repos.len()).collect());
+        }
+    }
+    groups
+}
+
+/// ` (5 repos | 3 clean | 1 dirty | 1 unpushed)` for a section heading, each
+/// state in its status color.
+fn section_counts(repos: &[RepoResult], indices: &[usize], opts: &RenderOptions) -> String {
+    let noun = if indices.len() == 1 { "repo" } else { "repos" };
+    let count = format_number(indices.len() as u64, opts.number_format);
+    let mut parts = vec![format!("{count} {noun}")];
+    parts.extend(state_counts(repos, indices, opts));
+    format!(" ({})", parts.join(" | "))
+}
+
+/// `3 clean`, `1 dirty`, ... for the states present among `indices`.
+fn state_counts(repos: &[RepoResult], indices: &[usize], opts: &RenderOptions) -> Vec<String> {
+    let mut parts = Vec::new();
+    for state in STATES_BY_SEVERITY {
+        let n = indices
+            .iter()
+            .filter(|&&idx| repo_state(&repos[idx]) == state)
+            .count();
+        if n == 0 {
+            continue;
+        }
+        let text = format!(
+            "{} {}",
+            format_number(n as u64, opts.number_format),
+            state.name().replace('_', " ")
+        );
+        let color = opts.symbols.for_state(state).color;
+        parts.push(match fg_sgr(color).filter(|_| opts.color) {
+            Some(fg) => format!("\x1b[{fg}m{text}\x1b[0m"),
+            None => text,
+        });
+    }
+    parts
+}
+
+/// Parses a `columns` list, rejecting unknown and repeated names.
+fn parse_columns(names: &[String]) -> Result<Vec<Column>, Box<dyn std::error::Error>> {
+    let mut columns = Vec::new();
+    for name in names {
+        let column = Column::ALL
+            .into_iter()
+            .find(|c| c.key() == name.trim())
+            .ok_or_else(|| {
+                let known: Vec<&str> = Column::ALL.iter().map(|c| c.key()).collect();
+                format!(
+                    "Unknown column {name:?} (expected one of {})",
+                    known.join(", ")
+                )
+            })?;
+        if columns.contains(&column) {
+            return Err(format!("Column {name:?} is listed twice").into());
+        }
+        columns.push(column);
+    }
+    Ok(columns)
+}
+
+/// Parses `--column-alias` pairs, naming a column by its key (`last_commit`) or its
+/// full header (`Last Commit`), in any case.
+fn parse_column_aliases(
+    pairs: &[(String, String)],
+) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let mut aliases = HashMap::new();
+    for (name, alias) in pairs {
+        let column = Column::ALL
+            .into_iter()
+            .find(|c| {
+                c.key().eq_ignore_ascii_case(name) || c.title(true).eq_ignore_ascii_case(name)
+            })
+            .ok_or_else(|| {
+                let known: Vec<&str> = Column::ALL.iter().map(|c| c.title(true)).collect();
+                format!(
+                    "Unknown column {name:?} in --column-alias (expected one of {})",
+                    known.join(", ")
+                )
+            })?;
+        aliases.insert(column.key().to_string(), alias.clone());
+    }
+    Ok(aliases)
+}
+
+/// A column's header: its `--column-alias` when it has one, else its title.
+fn column_label(column: Column, full_size: bool, aliases: &HashMap<String, String>) -> &str {
+    aliases
+        .get(column.key())
+        .map_or(column.title(full_size), String::as_str)
+}
+
+/// The columns every table in a run shares, and how wide each one is.
+struct TableLayout {
+    columns: Vec<Column>,
+    widths: Vec<u16>,
+    full_size: bool,
+    rule: String,
+}
+
+fn table_layout(repos: &[RepoResult], opts: &RenderOptions) -> TableLayout {
+    let mut all_columns = Vec::new();
+    if opts.row_numbers.is_some() {
+        all_columns.push(Column::Number);
+    }
+    if opts.group_by != GroupBy::Section {
+        all_columns.push(Column::Section);
+    }
+    all_columns.extend([
+        Column::Repository,
+        Column::Branch,
+        Column::Status,
+        Column::LastCommit,
+    ]);
+    if opts.date_format.is_some() {
+        all_columns.push(Column::Committed);
+    }
+    // A dry run reports what it would do in place of remote results it never fetched.
+    if repos.iter().any(|repo| repo.action.is_some()) {
+        all_columns.push(Column::Action);
+    } else {
+        all_columns.push(Column::Remote);
+    }
+    if opts.show_changes {
+        all_columns.push(Column::Changes);
+    }
+    if opts.show_fetch_time {
+        all_columns.push(Column::Fetched);
+    }
+    if opts.show_size {
+        all_columns.push(Column::Size);
+    }
+    if opts.show_contributors {
+        all_columns.push(Column::Contributors);
+    }
+    if opts.show_lfs {
+        all_columns.push(Column::Lfs);
+    }
+    if opts.show_hooks {
+        all_columns.push(Column::Hooks);
+    }
+    if opts.show_envrc {
+        all_columns.push(Column::Envrc);
+    }
+    if opts.show_contributing {
+        all_columns.push(Column::Docs);
+    }
+    if opts.show_diffstat {
+        all_columns.push(Column::Diffstat);
+    }
+    if opts.security {
+        all_columns.push(Column::Security);
+    }
+    if opts.timings {
+        all_columns.push(Column::Duration);
+    }
+    if opts.deep {
+        all_columns.push(Column::Todos);
+    }
+    all_columns.push(Column::Error);
+    // A custom order only picks among the columns this run has; Action stands in for Remote.
+    if let Some(order) = &opts.column_order {
+        let available = std::mem::take(&mut all_columns);
+        for &column in order {
+            let column = match column {
+                Column::Remote | Column::Action if available.contains(&Column::Action) => {
+                    Column::Action
+                }
+                column => column,
+            };
+            if available.contains(&column) && !all_columns.contains(&column) {
+                all_columns.push(column);
+            }
+        }
+    }
+    let full_table_width =
+        all_columns.iter().map(|c| c.full_width()).sum::<u16>() + all_columns.len() as u16 + 1;
+
+    let viewport_width = opts.viewport_width;
+    let fits = viewport_width.is_none_or(|w| w >= full_table_width);
+    let compact = viewport_width.is_some_and(|w| w < 80);
+    let narrow = viewport_width.is_some_and(|w| w < 60);
+    let tiny = viewport_width.is_some_and(|w| w < 40);
+    let ultra_tiny = viewport_width.is_some_and(|w| w < 28);
+    let columns: Vec<Column> = all_columns
+        .into_iter()
+        .filter(|&column| {
+            fits || match column {
+                Column::Branch => !tiny,
+                Column::LastCommit
+                | Column::Committed
+                | Column::Fetched
+                | Column::Size
+                | Column::Contributors
+                | Column::Lfs
+                | Column::Hooks
+                | Column::Envrc
+                | Column::Docs
+                | Column::Diffstat
+                | Column::Security
+                | Column::Duration
+                | Column::Todos => !narrow,
+                Column::Remote | Column::Action | Column::Changes => !ultra_tiny,
+                Column::Error => !compact,
+                Column::Section => !narrow,
+                Column::Number | Column::Repository | Column::Status => true,
+            }
+        })
+        .collect();
+    let ColumnWidths {
+        mut widths,
+        full_size,
+    } = match viewport_width {
+        Some(width) => compute_column_widths(width, &columns),
+        None => ColumnWidths {
+            widths: columns.iter().map(|c| c.full_width()).collect(),
+            full_size: true,
+        },
+    };
+    // Pull results name a reason or two commits; widen Action rather than wrap.
+    if let Some(i) = columns.iter().position(|&c| c == Column::Action) {
+        let (left, right) = opts.padding;
+        let longest = repos
+            .iter()
+            .filter_map(|repo| repo.action.as_deref())
+            .map(|action| action.chars().count() as u16 + left + right + 1)
+            .max();
+        widths[i] = widths[i].max(longest.unwrap_or(0));
+    }
+    // Bisect and stale branch notes are too long for the usual Status width; widen
+    // it rather than wrap.
+    if let Some(i) = columns.iter().position(|&c| c == Column::Status) {
+        let (left, right) = opts.padding;
+        let longest = repos
+            .iter()
+            .filter(|repo| repo.bisect_state.is_some() || repo.stale_tracking_count > 0)
+            .map(|repo| status_text(repo, opts).chars().count() as u16 + left + right + 1)
+            .max();
+        widths[i] = widths[i].max(longest.unwrap_or(0));
+    }
+    TableLayout {
+        columns,
+        widths,
+        full_size,
+        rule: section_rule(viewport_width, opts.glyphs.rule),
+    }
+}
+
+/// The blank line and ruled title above a group's table; `counts` follows the title.
+fn group_heading(title: &str, counts: &str, layout: &TableLayout, opts: &RenderOptions) -> String {
+    let mut output = String::from("\n");
+    if opts.group_by == GroupBy::None {
+        return output;
+    }
+    let heading = format!("    {}", title.to_uppercase());
+    for (i, line) in [&layout.rule, &heading, &layout.rule]
+        .into_iter()
+        .enumerate()
+    {
+        if opts.color {
+            output.push_str(&opts.theme.section_line(line));
+        } else {
+            output.push_str(line);
+        }
+        if i == 1 {
+            output.push_str(counts);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// A table with the header row and column widths set, ready for repo rows.
+fn repo_table(layout: &TableLayout, opts: &RenderOptions) -> Table {
+    let theme = opts.theme;
+    let mut table = Table::new();
+    table.load_preset(ASCII_FULL);
+    table.set_content_arrangement(ContentArrangement::Disabled);
+    if opts.color {
+        table.enforce_styling();
+    } else {
+        table.force_no_tty();
+    }
+
+    table.set_header(layout.columns.iter().map(|&c| {
+        let label = column_label(c, layout.full_size, &opts.column_aliases);
+        theme.paint(Cell::new(label), c.header_color(theme))
+    }));
+
+    for (i, (&column, &width)) in layout.columns.iter().zip(&layout.widths).enumerate() {
+        let table_column = table.column_mut(i).unwrap();
+        table_column
+            .set_constraint(ColumnConstraint::Absolute(Width::Fixed(width)))
+            .set_padding(opts.padding);
+        if let Some(align) = opts.align.for_column(column) {
+            table_column.set_cell_alignment(align.into());
+        }
+    }
+    table
+}
+
+fn add_repo_row(table: &mut Table, repo: &RepoResult, layout: &TableLayout, opts: &RenderOptions) {
+    let highlight =
+        opts.highlight_errors && matches!(repo_state(repo), RepoState::Error | RepoState::Conflict);
+    table.add_row(
+        layout
+            .columns
+            .iter()
+            .zip(&layout.widths)
+            .map(|(&c, &width)| {
+                let cell = render_cell(c, repo, opts, width);
+                if highlight {
+                    opts.theme.highlight_error(cell)
+                } else {
+                    cell
+                }
+            }),
+    );
+}
+
+fn scan_footer(repo_count: usize, opts: &RenderOptions) -> String {
+    let noun = if repo_count == 1 { "repo" } else { "repos" };
+    let offline = if opts.offline { " (offline)" } else { "" };
+    format!(
+        "\nScanned {repo_count} {noun} in {:.2}s{offline}",
+        opts.scan_duration.as_secs_f64()
+    )
+}
+
+fn render_all(
+    repos: &[RepoResult],
+    groups: &IndexMap<String, Vec<usize>>,
+    opts: &RenderOptions,
+) -> String {
+    let layout = table_layout(repos, opts);
+    let mut output = String::new();
+    for (title, repo_indices) in groups {
+        let counts = match opts.group_by {
+            GroupBy::Section => section_counts(repos, repo_indices, opts),
+            _ => String::new(),
+        };
+        output.push_str(&group_heading(title, &counts, &layout, opts));
+        let mut table = repo_table(&layout, opts);
+        for &idx in repo_indices {
+            add_repo_row(&mut table, &repos[idx], &layout, opts);
+        }
+        output.push_str(&table.to_string());
+        output.push('\n');
+    }
+    output.push_str(&scan_footer(repos.len(), opts));
+    output.push('\n');
+    output
+}
+
+/// `--stream`: prints each group's table a row at a time as its repos finish.
+/// Repos in later groups wait until every group before theirs is done, so
+/// tables are never interleaved; within a group, rows follow finishing order.
+struct StreamTable {
+    layout: TableLayout,
+    groups: Vec<(String, Vec<usize>)>,
+    /// The group being printed, and how many of its rows are out.
+    current: usize,
+    printed: usize,
+    /// Whether `current`'s heading and table header are out.
+    opened: bool,
+    /// Finished repos not printed yet.
+    finished: Vec<usize>,
+}
+
+impl StreamTable {
+    fn new(
+        repos: &[RepoResult],
+        groups: IndexMap<String, Vec<usize>>,
+        opts: &RenderOptions,
+    ) -> Self {
+        StreamTable {
+            layout: table_layout(repos, opts),
+            groups: groups.into_iter().collect(),
+            current: 0,
+            printed: 0,
+            opened: false,
+            finished: Vec::new(),
+        }
+    }
+
+    /// Records that `repos[idx]` won't change any more, and returns what can be
+    /// printed now.
+    fn finish(&mut self, repos: &[RepoResult], idx: usize, opts: &RenderOptions) -> String {
+        self.finished.push(idx);
+        self.drain(repos, opts)
+    }
+
+    /// Prints every group that can be completed from the repos finished so far;
+    /// with none outstanding, that's all of them.
+    fn drain(&mut self, repos: &[RepoResult], opts: &RenderOptions) -> String {
+        let mut output = String::new();
+        while let Some((title, indices)) = self.groups.get(self.current) {
+            if !self.opened {
+                output.push_str(&group_heading(title, "", &self.layout, opts));
+                self.opened = true;
+                if indices.is_empty() {
+                    output.push_str(&repo_table(&self.layout, opts).to_string());
+                    output.push('\n');
+                    self.next_group();
+                    continue;
+                }
+            }
+            let (ready, waiting) = std::mem::take(&mut self.finished)
+                .into_iter()
+                .partition(|i| indices.contains(i));
+            self.finished = waiting;
+            for idx in ready {
+                let (head, rows, foot) = self.row_lines(&repos[idx], opts);
+                if self.printed == 0 {
+                    output.push_str(&head);
+                } else {
+                    // ASCII_FULL draws the line between rows as the bottom border with `|` ends.
+                    let inner = foot.get(1..foot.len() - 1).unwrap_or_default();
+                    output.push_str(&format!("|{inner}|\n"));
+                }
+                output.push_str(&rows);
+                self.printed += 1;
+            }
+            if self.printed < indices.len() {
+                break;
+            }
+            let foot = self.row_lines(&repos[indices[0]], opts).2;
+            output.push_str(&foot);
+            output.push('\n');
+            self.next_group();
+        }
+        output
+    }
+
+    fn next_group(&mut self) {
+        self.current += 1;
+        self.printed = 0;
+        self.opened = false;
+    }
+
+    /// Renders `repo` alone under the header and splits the result into the part
+    /// down to the header rule, the row itself, and the bottom border.
+    fn row_lines(&self, repo: &RepoResult, opts: &RenderOptions) -> (String, String, String) {
+        let mut table = repo_table(&self.layout, opts);
+        add_repo_row(&mut table, repo, &self.layout, opts);
+        let text = table.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        let rule = lines
+            .iter()
+            .position(|line| line.starts_with("+="))
+            .unwrap_or(0);
+        let (foot, body) = lines[rule + 1..].split_last().unwrap_or((&"", &[]));
+        let join = |lines: &[&str]| lines.iter().map(|line| format!("{line}\n")).collect();
+        (join(&lines[..=rule]), join(body), foot.to_string())
+    }
+}
+
+/// Narrows the config to a single section, e.g. for `--section work`.
+fn select_section(config: &mut Config, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    check_section(config, name)?;
+    config.sections.retain(|section, _| section == name);
+    Ok(())
+}
+
+fn check_section(config: &Config, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if !config.sections.contains_key(name) {
+        let known: Vec<&str> = config.sections.keys().map(String::as_str).collect();
+        return Err(format!(
+            "Unknown section {name:?} (configured: {})",
+            known.join(", ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// A repo `check-stale` suggests archiving.
+struct StaleRepo {
+    label: String,
+    directory: String,
+    last_commit_at: u64,
+    author: String,
+}
+
+/// Clean repos whose HEAD is older than `days` and that have no local branch
+/// besides the checked-out one. Author and branch lookups only run for repos that
+/// pass the cheaper checks.
+fn find_stale(repos: &[RepoResult], days: u64, now: u64) -> Vec<StaleRepo> {
+    let cutoff = now.saturating_sub(days * 86_400);
+    repos
+        .iter()
+        .filter(|repo| repo.local_error.is_none() && repo.clean == Some(true))
+        .filter_map(|repo| {
+            let last_commit_at = repo.last_commit_at.filter(|&at| at < cutoff)?;
+            let branches = git_cmd(&repo.directory, &["for-each-ref", "refs/heads"])?;
+            if branches.lines().count() > 1 {
+                return None;
+            }
+            Some(StaleRepo {
+                label: format!("{}/{}", repo.section, repo.repo_key),
+                directory: repo.directory.clone(),
+                last_commit_at,
+                author: git_cmd(&repo.directory, &["log", "-1", "--format=%an"])
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+fn render_stale(stale: &[StaleRepo], json: bool, now: u64) -> String {
+    let date = |repo: &StaleRepo| format_date(repo.last_commit_at, now, DateFormat::Iso);
+    if json {
+        let array = toml::Value::Array(
+            stale
+                .iter()
+                .map(|repo| {
+                    let mut table = toml::map::Map::new();
+                    for (key, value) in [
+                        ("repo", repo.label.clone()),
+                        ("directory", repo.directory.clone()),
+                        ("last_commit", date(repo)),
+                        ("author", repo.author.clone()),
+                    ] {
+                        table.insert(key.to_string(), toml::Value::String(value));
+                    }
+                    toml::Value::Table(table)
+                })
+                .collect(),
+        );
+        return format!("{}\n", to_json(&array));
+    }
+    if stale.is_empty() {
+        return "No stale repos.\n".to_string();
+    }
+    let width = stale
+        .iter()
+        .map(|r| r.label.chars().count())
+        .max()
+        .unwrap_or(0);
+    stale
+        .iter()
+        .map(|repo| format!("{:width$}  {}  {}\n", repo.label, date(repo), repo.author))
+        .collect()
+}
+
+/// Runs `gh repo archive` in each repo once the user confirms on the terminal.
+fn archive_on_github(stale: &[StaleRepo]) -> Result<(), Box<dyn std::error::Error>> {
+    if !std::io::stdin().is_terminal() {
+        return Err("--github-archive asks for confirmation, so stdin must be a terminal".into());
+    }
+    eprint!("Archive these {} repos on GitHub? [y/N] ", stale.len());
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        eprintln!("Nothing archived.");
+        return Ok(());
+    }
+    for repo in stale {
+        let status = Command::new("gh")
+            .args(["repo", "archive", "--yes"])
+            .current_dir(&repo.directory)
+            .status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => warn(format_args!("{}: gh repo archive failed", repo.label)),
+            Err(e) => return Err(format!("Failed to run gh: {e}").into()),
+        }
+    }
+    Ok(())
+}
+
+/// Launches `open_command` (with `{path}` replaced by the repo's directory), else
+/// `$VISUAL` or `$EDITOR`, from the repo's directory, and waits for it so a
+/// terminal editor gets the terminal.
+fn open_repo(row: &RowEntry, open_command: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let label = format!("{}/{}", row.section, row.repo);
+    if !Path::new(&row.directory).is_dir() {
+        return Err(format!("{label} isn't on disk at {}", row.directory).into());
+    }
+    let editor = |name| {
+        std::env::var(name)
+            .ok()
+            .filter(|v: &String| !v.trim().is_empty())
+    };
+    // The path goes in as `$1`, so it never needs quoting.
+    let script = match open_command {
+        Some(template) if template.contains("{path}") => template.replace("{path}", "\"$1\""),
+        Some(template) => format!("{template} \"$1\""),
+        None => match editor("VISUAL").or_else(|| editor("EDITOR")) {
+            Some(editor) => format!("{editor} \"$1\""),
+            None => return Err(
+                "Nothing to open with: set $VISUAL or $EDITOR, or open_command under [settings]"
+                    .into(),
+            ),
+        },
+    };
+    let status = Command::new("sh")
+        .args(["-c", &script, "sh", &row.directory])
+        .current_dir(&row.directory)
+        .status()
+        .map_err(|e| format!("Failed to run sh: {e}"))?;
+    if !status.success() {
+        return Err(format!("Opening {label} failed ({status})").into());
+    }
+    Ok(())
+}
+
+/// Opens the web page of a repo's `origin`, or of its current branch or HEAD
+/// commit there, with the platform's opener.
+fn browse_repo(
+    config: &Config,
+    row: &RowEntry,
+    kind: BrowseKind,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let label = format!("{}/{}", row.section, row.repo);
+    let dir = row.directory.as_str();
+    let remote = git_cmd(dir, &["remote", "get-url", "origin"])
+        .ok_or_else(|| format!("{label} has no origin remote"))?;
+    let page = config
+        .browser_url(&remote)
+        .ok_or_else(|| format!("{label}: origin ({remote}) has no web page"))?;
+    let target = match kind {
+        BrowseKind::Repo => BrowseTarget::Repo,
+        BrowseKind::Branch => BrowseTarget::Branch(
+            git_cmd(dir, &["symbolic-ref", "--quiet", "--short", "HEAD"])
+                .ok_or_else(|| format!("{label} is on a detached HEAD"))?,
+        ),
+        BrowseKind::Commit => BrowseTarget::Commit(
+            git_cmd(dir, &["rev-parse", "HEAD"])
+                .ok_or_else(|| format!("{label} has no commits"))?,
+        ),
+    };
+    let url = browse_url(&page, &target);
+    eprintln!("Opening {url}");
+    let mut opener = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        // `start` treats its first quoted argument as a window title.
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+    let status = opener
+        .arg(&url)
+        .status()
+        .map_err(|e| format!("Failed to launch a browser: {e}"))?;
+    if !status.success() {
+        return Err(format!("Opening {url} failed ({status})").into());
+    }
+    Ok(())
+}
+
+/// `git clone [--depth 1] <url> <path>`, as the repo's config entry asks.
+fn clone_command(section: &Section, repo: &str, url: &str, path: &str) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("clone");
+    if section.shallow.iter().any(|name| name == repo) {
+        cmd.args(["--depth", "1"]);
+    }
+    cmd.args([url, path]);
+    cmd
+}
+
+/// Runs `git clone <url> <path>` for a repo configured with a `url`, with git's
+/// own progress output passed through.
+fn clone_repo(
+    config: &Config,
+    row: &RowEntry,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let label = format!("{}/{}", row.section, row.repo);
+    let section = config
+        .sections
+        .get(&row.section)
+        .ok_or_else(|| format!("No section {}", row.section))?;
+    let url = section
+        .clone_urls
+        .get(&row.repo)
+        .ok_or_else(|| format!("{label} has no url to clone from"))?;
+    if Path::new(&row.directory).exists() {
+        return Err(format!("{label} is already at {}", row.directory).into());
+    }
+    if dry_run {
+        println!("Would clone {url} into {}", row.directory);
+        return Ok(());
+    }
+    let status = clone_command(section, &row.repo, url, &row.directory)
+        .status()
+        .map_err(|e| format!("Failed to run git: {e}"))?;
+    if !status.success() {
+        return Err(format!("git clone failed for {label}").into());
+    }
+    Ok(())
+}
+
+/// `jiancha clone` without a name: clones every configured repo whose path doesn't
+/// exist, `jobs` at a time, creating parent directories. Returns the Action for
+/// each such repo by `(section, repo)`, with git's reason when a clone failed.
+fn clone_missing(
+    config: &Config,
+    dry_run: bool,
+    jobs: usize,
+) -> HashMap<(String, String), (String, Option<String>)> {
+    let mut results = HashMap::new();
+    let mut pending = Vec::new();
+    for (section_name, section) in &config.sections {
+        for (repo, path) in &section.repos {
+            if Path::new(path).exists() {
+                continue;
+            }
+            let key = (section_name.clone(), repo.clone());
+            match section.clone_urls.get(repo) {
+                None => {
+                    results.insert(
+                        key,
+                        ("CANNOT CLONE (no remote configured)".to_string(), None),
+                    );
+                }
+                Some(_) if dry_run => {
+                    results.insert(key, ("WOULD CLONE".to_string(), None));
+                }
+                Some(url) => pending.push((key, clone_command(section, repo, url, path), path)),
+            }
+        }
+    }
+    if pending.is_empty() {
+        if results.is_empty() {
+            eprintln!("Every configured repo is already on disk.");
+        }
+        return results;
+    }
+
+    let queue = Mutex::new(pending);
+    let done = Mutex::new(Vec::new());
+    let next = || queue.lock().unwrap_or_else(PoisonError::into_inner).pop();
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| {
+                while let Some((key, mut cmd, path)) = next() {
+                    if let Some(parent) = Path::new(path).parent() {
+                        let _ = std::fs::create_dir_all(parent);
+                    }
+                    // Clones run side by side, so git's progress output would interleave.
+                    cmd.arg("--quiet").env("GIT_TERMINAL_PROMPT", "0");
+                    let outcome = match cmd.output() {
+                        Ok(output) if output.status.success() => ("CLONED".to_string(), None),
+                        Ok(output) => (
+                            "FAILED".to_string(),
+                            last_git_error(&String::from_utf8_lossy(&output.stderr)),
+                        ),
+                        Err(e) => (
+                            "FAILED".to_string(),
+                            Some(format!("Failed to run git: {e}")),
+                        ),
+                    };
+                    done.lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((key, outcome));
+                }
+            });
+        }
+    });
+    results.extend(done.into_inner().unwrap_or_else(PoisonError::into_inner));
+    results
+}
+
+/// Where `jiancha list` read the config from.
+struct ListSource<'a> {
+    file: &'a Path,
+    profile: Option<&'a str>,
+}
+
+/// `jiancha list`: the config file and profile in use, then every configured repo
+/// after glob and `~` expansion, with its section and path, marking paths that
+/// don't exist. Never runs git, so it's also how to check what a glob matched.
+fn render_list(config: &Config, source: &ListSource, json: bool, home: Option<&str>) -> String {
+    let entries: Vec<(&str, &str, String, bool)> = config
+        .sections
+        .iter()
+        .flat_map(|(section, entries)| {
+            entries.repos.iter().map(move |(repo, path)| {
+                let shown = match home {
+                    Some(home) => display_path(path, home),
+                    None => path.clone(),
+                };
+                (
+                    section.as_str(),
+                    repo.as_str(),
+                    shown,
+                    Path::new(path).exists(),
+                )
+            })
+        })
+        .collect();
+
+    if json {
+        use toml::Value;
+
+        let mut table = toml::map::Map::new();
+        let file = source.file.to_string_lossy().into_owned();
+        table.insert("config".into(), Value::String(file));
+        if let Some(profile) = source.profile {
+            table.insert("profile".into(), Value::String(profile.to_string()));
+        }
+        let repos = entries
+            .iter()
+            .map(|(section, repo, path, exists)| {
+                let mut entry = toml::map::Map::new();
+                entry.insert("section".into(), Value::String(section.to_string()));
+                entry.insert("repo".into(), Value::String(repo.to_string()));
+                entry.insert("path".into(), Value::String(path.clone()));
+                entry.insert("exists".into(), Value::Boolean(*exists));
+                Value::Table(entry)
+            })
+            .collect();
+        table.insert("repos".into(), Value::Array(repos));
+        let disabled = config
+            .disabled_sections
+            .iter()
+            .map(|name| Value::String(name.clone()))
+            .collect();
+        table.insert("disabled_sections".into(), Value::Array(disabled));
+        return format!("{}\n", to_json(&Value::Table(table)));
+    }
+
+    let mut out = format!("config:  {}\n", source.file.display());
+    out.push_str(&format!(
+        "profile: {}\n",
+        source.profile.unwrap_or("(none)")
+    ));
+    if !config.disabled_sections.is_empty() {
+        out.push_str(&format!(
+            "disabled sections: {}\n",
+            config.disabled_sections.join(", ")
+        ));
+    }
+    out.push('\n');
+    let section_width = entries
+        .iter()
+        .map(|e| e.0.chars().count())
+        .max()
+        .unwrap_or(0);
+    let repo_width = entries
+        .iter()
+        .map(|e| e.1.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (section, repo, path, exists) in &entries {
+        let marker = if *exists { "" } else { "  (missing)" };
+        out.push_str(&format!(
+            "{section:<section_width$}  {repo:<repo_width$}  {path}{marker}\n"
+        ));
+    }
+    out
+}
+
+/// `jiancha list --paths`: configured repo paths, one per line or as a JSON array.
+fn render_list_paths(config: &Config, json: bool, home: Option<&str>) -> String {
+    let paths: Vec<String> = config
+        .sections
+        .values()
+        .flat_map(|section| section.repos.values())
+        .map(|path| match home {
+            Some(home) => display_path(path, home),
+            None => path.clone(),
+        })
+        .collect();
+
+    if json {
+        let array = toml::Value::Array(
+            paths
+                .iter()
+                .map(|p| toml::Value::String(p.to_string()))
+                .collect(),
+        );
+        return format!("{}\n", to_json(&array));
+    }
+    paths.iter().map(|p| format!("{p}\n")).collect()
+}
+
+/// Reconciles the config against the filesystem: configured repos that are gone,
+/// Git repos sitting next to configured ones that aren't listed, and paths listed
+/// more than once. With `only`, reports are limited to that section.
+fn render_config_diff(config: &Config, only: Option<&str>) -> String {
+    let in_scope = |section: &str| only.is_none_or(|name| name == section);
+
+    let mut missing = Vec::new();
+    let mut configured: IndexMap<PathBuf, Vec<String>> = IndexMap::new();
+    for (section_name, section) in &config.sections {
+        for (repo_name, dir_str) in &section.repos {
+            let label = format!("{section_name}/{repo_name}");
+            match PathBuf::from(dir_str).canonicalize() {
+                Ok(path) if path.join(".git").exists() => {
+                    configured.entry(path).or_default().push(label);
+                }
+                Ok(_) if in_scope(section_name) => {
+                    missing.push(format!("  {label}  {dir_str} (not a Git repository)"));
+                }
+                Err(_) if in_scope(section_name) => {
+                    missing.push(format!("  {label}  {dir_str} (no such directory)"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Only the parents of configured repos are scanned; jiancha has no notion of a
+    // project root, so this is where moved or newly cloned repos usually turn up.
+    let mut parents: IndexMap<PathBuf, Vec<&str>> = IndexMap::new();
+    for (path, labels) in &configured {
+        let Some(parent) = path.parent() else {
+            continue;
+        };
+        for label in labels {
+            let section = label.split_once('/').map_or(label.as_str(), |(s, _)| s);
+            if in_scope(section) {
+                let sections = parents.entry(parent.to_path_buf()).or_default();
+                if !sections.contains(&section) {
+                    sections.push(section);
+                }
+            }
+        }
+    }
+    let mut untracked = Vec::new();
+    for (parent, sections) in &parents {
+        let Ok(entries) = std::fs::read_dir(parent) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.join(".git").exists() && !configured.contains_key(path))
+            .collect();
+        found.sort();
+        for path in found {
+            untracked.push(format!(
+                "  {}  (alongside {})",
+                path.display(),
+                sections.join(", ")
+            ));
+        }
+    }
+
+    let mut inconsistencies = Vec::new();
+    for (path, labels) in &configured {
+        let scoped = labels
+            .iter()
+            .any(|label| in_scope(label.split_once('/').map_or(label.as_str(), |(s, _)| s)));
+        if labels.len() > 1 && scoped {
+            inconsistencies.push(format!(
+                "  {} is listed as {}",
+                path.display(),
+                labels.join(" and ")
+            ));
+        }
+    }
+    for (section_name, section) in &config.sections {
+        if section.repos.is_empty() && in_scope(section_name) {
+            inconsistencies.push(format!("  section {section_name:?} has no repos"));
+        }
+    }
+
+    let mut out = String::new();
+    for (title, lines) in [
+        ("In config but not on disk:", missing),
+        ("On disk but not in config:", untracked),
+        ("Section inconsistencies:", inconsistencies),
+    ] {
+        if !lines.is_empty() {
+            out.push_str(&format!("{title}\n{}\n", lines.join("\n")));
+        }
+    }
+    if out.is_empty() {
+        out.push_str("Config matches the filesystem.\n");
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Serialises a `toml::Value` (what serde structs convert to via
+/// `toml::Value::try_from`) as compact JSON.
+fn to_json(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => json_string(s),
+        toml::Value::Integer(n) => n.to_string(),
+        toml::Value::Float(f) if f.is_finite() => f.to_string(),
+        toml::Value::Float(_) => "null".to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(dt) => json_string(&dt.to_string()),
+        toml::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(to_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        toml::Value::Table(table) => {
+            let fields: Vec<String> = table
+                .iter()
+                .map(|(k, v)| format!("{}:{}", json_string(k), to_json(v)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+    }
+}
+
+fn profile_names() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let dir = profiles_dir()?;
+    let mut names: Vec<String> = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e).into()),
+    };
+    names.sort();
+    Ok(names)
+}
+
+fn list_profiles(active: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    for name in profile_names()? {
+        let marker = if Some(name.as_str()) == active {
+            "*"
+        } else {
+            " "
+        };
+        println!("{marker} {name}");
+    }
+    Ok(())
+}
+
+/// The checkout jiancha was built from: the nearest directory above the running
+/// binary whose `Cargo.toml` is this package's, as with `target/release/jiancha`.
+fn source_checkout() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let exe = std::env::current_exe()?;
+    let exe = exe.canonicalize().unwrap_or(exe);
+    exe.ancestors()
+        .skip(1)
+        .find(|dir| {
+            std::fs::read_to_string(dir.join("Cargo.toml"))
+                .ok()
+                .and_then(|manifest| toml::from_str::<toml::Table>(&manifest).ok())
+                .and_then(|manifest| {
+                    manifest
+                        .get("package")?
+                        .get("name")?
+                        .as_str()
+                        .map(|name| name == env!("CARGO_PKG_NAME"))
+                })
+                .unwrap_or(false)
+        })
+        .map(Path::to_path_buf)
+        .ok_or_else(|| {
+            format!(
+                "{} isn't inside a jiancha source checkout, so there's nothing to update from",
+                exe.display()
+            )
+            .into()
+        })
+}
+
+/// `--update-self`: `git pull --ff-only` in the source checkout, then
+/// `cargo install --path .` there, with both commands' output passed through.
+fn update_self() -> Result<(), Box<dyn std::error::Error>> {
+    let root = source_checkout()?;
+    eprintln!("Updating jiancha from {}", root.display());
+    for (program, args) in [
+        ("git", &["pull", "--ff-only"][..]),
+        ("cargo", &["install", "--path", "."][..]),
+    ] {
+        let status = Command::new(program)
+            .args(args)
+            .current_dir(&root)
+            .status()
+            .map_err(|e| format!("Failed to run {program}: {e}"))?;
+        if !status.success() {
+            return Err(format!("{program} {} failed ({status})", args.join(" ")).into());
+        }
+    }
+    Ok(())
+}
+
+fn create_profile(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let source = config_path(None)?;
+    let target = config_path(Some(name))?;
+    if target.exists() {
+        return Err(format!("Profile {name:?} already exists at {}", target.display()).into());
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(&source, &target)
+        .map_err(|e| format!("Failed to copy {}: {}", source.display(), e))?;
+    println!("Created profile {name:?} at {}", target.display());
+    Ok(())
+}
+
+/// How `diagnose` explains a command's trimmed output.
+type Meaning = fn(&str) -> String;
+
+/// One git command run by `jiancha diagnose`, and what jiancha reads into its output.
+struct DiagnosticStep {
+    args: &'static [&'static str],
+    stdout: String,
+    stderr: String,
+    /// `None` when git couldn't be started or was killed by a signal.
+    exit_code: Option<i32>,
+    meaning: String,
+}
+
+/// Runs the commands `get_git_info` may use in `dir` one by one, fallbacks
+/// included, then reports what the row would show. Plain text with a section
+/// per command, or JSON with `--json`.
+fn diagnose(dir: &str, json: bool) -> String {
+    fn or_none(s: Option<String>) -> String {
+        s.unwrap_or_else(|| "none".to_string())
+    }
+    let status_meaning = |out: &str| match parse_branch_status(out) {
+        Some(status) => format!(
+            "branch {}, upstream {}, ahead {}, {}{}",
+            status.branch,
+            or_none(status.upstream),
+            or_none(status.ahead.map(|n| n.to_string())),
+            if status.clean { "clean" } else { "dirty" },
+            if status.conflicted {
+                ", conflicted"
+            } else {
+                ""
+            }
+        ),
+        None => "unusable (no commits yet, or git older than 2.11); \
+                 the fallback commands below are used instead"
+            .to_string(),
+    };
+    let log_meaning = |out: &str| {
+        let (committed_at, merge, subject) = parse_head_log(out);
+        format!(
+            "committed {}, {}subject {subject:?}",
+            or_none(committed_at.map(|ts| format_date(ts, now_secs(), DateFormat::Iso))),
+            if merge { "a merge commit, " } else { "" }
+        )
+    };
+    let rev_parse_meaning = |out: &str| match out.split_once('\n') {
+        Some((fetch_head, branch)) => format!("FETCH_HEAD at {fetch_head}, branch {branch}"),
+        None => "unreadable, so the repo can't be read".to_string(),
+    };
+    let porcelain_meaning = |out: &str| {
+        if out.is_empty() {
+            "clean".to_string()
+        } else {
+            let conflicted = out.lines().any(is_unmerged_status);
+            format!(
+                "dirty: {} changed paths{}",
+                out.lines().count(),
+                if conflicted { ", conflicted" } else { "" }
+            )
+        }
+    };
+    let ahead_meaning = |out: &str| match out.parse::<u32>() {
+        Ok(n) => format!("{n} commits ahead of the upstream"),
+        Err(_) => "no upstream, so no ahead count".to_string(),
+    };
+    let upstream_meaning = |out: &str| match out {
+        "" => "no upstream".to_string(),
+        upstream => format!("upstream {upstream}"),
+    };
+    let commands: [(&[&str], Meaning); 6] = [
+        (&BRANCH_STATUS_ARGS, status_meaning),
+        (&HEAD_LOG_ARGS, log_meaning),
+        (
+            &[
+                "rev-parse",
+                "--git-path",
+                "FETCH_HEAD",
+                "--abbrev-ref",
+                "HEAD",
+            ],
+            rev_parse_meaning,
+        ),
+        (&["status", "--porcelain"], porcelain_meaning),
+        (&["rev-list", "--count", "@{u}..HEAD"], ahead_meaning),
+        (
+            &["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"],
+            upstream_meaning,
+        ),
+    ];
+    let steps: Vec<DiagnosticStep> = commands
+        .into_iter()
+        .map(|(args, meaning)| {
+            match git(dir).args(args).stdin(Stdio::null()).output() {
+                Ok(output) => {
+                    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+                    // git_cmd trims output and ignores it on failure; so does the meaning.
+                    let meaning = if output.status.success() {
+                        meaning(stdout.trim())
+                    } else {
+                        "failed, so jiancha treats the value as unknown".to_string()
+                    };
+                    DiagnosticStep {
+                        args,
+                        stdout,
+                        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                        exit_code: output.status.code(),
+                        meaning,
+                    }
+                }
+                Err(e) => DiagnosticStep {
+                    args,
+                    stdout: String::new(),
+                    stderr: e.to_string(),
+                    exit_code: None,
+                    meaning: "git couldn't be run".to_string(),
+                },
+            }
+        })
+        .collect();
+    let result = match get_git_info(Path::new(dir)) {
+        Some(info) => format!(
+            "branch {}, {}, ahead {}{}",
+            info.branch,
+            match info.diffstat {
+                Some(stat) => format!("dirty ({stat})"),
+                None if info.clean => "clean".to_string(),
+                None => "dirty".to_string(),
+            },
+            or_none(info.ahead.map(|n| n.to_string())),
+            if info.bisect_state.is_some() {
+                ", mid-bisect"
+            } else {
+                ""
+            }
+        ),
+        None if !Path::new(dir).is_dir() => "? with \"Not a valid directory\"".to_string(),
+        None if !is_git_repo(Path::new(dir)) => "? with \"Not a Git repository\"".to_string(),
+        None => "? with \"Failed to get git info\"".to_string(),
+    };
+    if json {
+        return diagnose_json(dir, &steps, &result);
+    }
+    let mut out = String::new();
+    for step in &steps {
+        // Quoted so the line can be pasted back into a shell.
+        let args: Vec<String> = step
+            .args
+            .iter()
+            .map(|arg| {
+                if arg.contains(' ') {
+                    format!("'{arg}'")
+                } else {
+                    arg.to_string()
+                }
+            })
+            .collect();
+        out.push_str(&format!("== git -C {dir} {}\n", args.join(" ")));
+        out.push_str(&format!(
+            "exit code: {}\n",
+            or_none(step.exit_code.map(|c| c.to_string()))
+        ));
+        for (label, text) in [("stdout", &step.stdout), ("stderr", &step.stderr)] {
+            if text.trim().is_empty() {
+                out.push_str(&format!("{label}: (empty)\n"));
+            } else {
+                out.push_str(&format!("{label}:\n"));
+                for line in text.trim_end().lines() {
+                    out.push_str(&format!("  {line}\n"));
+                }
+            }
+        }
+        out.push_str(&format!("means: {}\n\n", step.meaning));
+    }
+    out.push_str(&format!("== result\n{result}\n"));
+    out
+}
+
+fn diagnose_json(dir: &str, steps: &[DiagnosticStep], result: &str) -> String {
+    use toml::Value;
+
+    let commands = steps
+        .iter()
+        .map(|step| {
+            let mut table = toml::map::Map::new();
+            let args = ["git", "-C", dir]
+                .iter()
+                .chain(step.args)
+                .map(|arg| Value::String(arg.to_string()))
+                .collect();
+            table.insert("command".into(), Value::Array(args));
+            table.insert("stdout".into(), Value::String(step.stdout.clone()));
+            table.insert("stderr".into(), Value::String(step.stderr.clone()));
+            if let Some(code) = step.exit_code {
+                table.insert("exit_code".into(), Value::Integer(code.into()));
+            }
+            table.insert("meaning".into(), Value::String(step.meaning.clone()));
+            Value::Table(table)
+        })
+        .collect();
+    let mut table = toml::map::Map::new();
+    table.insert("directory".into(), Value::String(dir.to_string()));
+    table.insert("commands".into(), Value::Array(commands));
+    table.insert("result".into(), Value::String(result.to_string()));
+    format!("{}\n", to_json(&Value::Table(table)))
+}
+
+/// Shells `jiancha completions` writes scripts for.
+#[derive(Debug, Clone, Copy)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl Shell {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "powershell" => Some(Shell::Powershell),
+            _ => None,
+        }
+    }
+}
+
+/// What a completion script offers after a flag or subcommand word.
+enum Completion {
+    /// A switch: nothing follows.
+    Nothing,
+    /// A free-form value, such as a number.
+    Anything,
+    Words(&'static str),
+    /// Names from `jiancha __complete <kind>`, read from the config at completion time.
+    Names(&'static str),
+    Files,
+}
+
+/// Subcommands offered in first position, and what follows each of them.
+const COMPLETION_SUBCOMMANDS: [(&str, Completion); 17] = [
+    ("status", Completion::Files),
+    ("fetch", Completion::Nothing),
+    ("pull", Completion::Nothing),
+    ("push", Completion::Nothing),
+    ("gc", Completion::Nothing),
+    ("exec", Completion::Nothing),
+    ("list", Completion::Nothing),
+    ("path", Completion::Names("repos")),
+    ("open", Completion::Names("repos")),
+    ("browse", Completion::Names("repos")),
+    ("clone", Completion::Names("repos")),
+    ("check-stale", Completion::Nothing),
+    ("config", Completion::Words("diff")),
+    ("profiles", Completion::Words("list create")),
+    ("diagnose", Completion::Files),
+    ("completions", Completion::Words("bash zsh fish powershell")),
+    ("man", Completion::Nothing),
+];
+
+/// Escapes text for roff: backslashes, and hyphens so they aren't typeset as dashes.
+fn roff_escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// `jiancha man`: the man page in roff. SYNOPSIS is `--help`'s usage and OPTIONS
+/// comes from `FLAGS`, so both follow the CLI; the rest is written here.
+fn man_page() -> String {
+    let mut page = format!(
+        ".TH JIANCHA 1 \"\" \"jiancha {}\" \"User Commands\"\n\
+         .SH NAME\n\
+         jiancha \\- keep track of git repos across machines\n\
+         .SH SYNOPSIS\n\
+         .nf\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for line in usage().trim_start_matches("Usage: ").lines() {
+        page.push_str(&roff_escape(line.trim()));
+        page.push('\n');
+    }
+    page.push_str(
+        ".fi\n\
+         .SH DESCRIPTION\n\
+         Shows the local state of every configured git repo in one table: branch, \
+         uncommitted changes, unpushed commits, and how the branch compares with its \
+         upstream. Remote state is fetched in the background and cached, so repeated \
+         runs stay fast. Directories given on the command line are checked instead of \
+         the configured repos.\n\
+         .SH COMMANDS\n",
+    );
+    for (name, _) in &COMPLETION_SUBCOMMANDS {
+        page.push_str(&format!(".TP\n.B {}\n", roff_escape(name)));
+        let usage = SUBCOMMAND_USAGE
+            .into_iter()
+            .find(|line| line.starts_with(&format!("jiancha {name}")))
+            .unwrap_or_default();
+        page.push_str(&roff_escape(usage));
+        page.push('\n');
+    }
+    page.push_str(".SH OPTIONS\n");
+    for flag in FLAGS {
+        page.push_str(&format!(
+            ".TP\n.B {}{}\n",
+            roff_escape(&flag.spellings().join(", ")),
+            roff_escape(&flag.value_text())
+        ));
+    }
+    page.push_str(MAN_CONFIGURATION);
+    page.push_str(".SH STATUS SYMBOLS\n");
+    for (glyph, ascii, meaning) in [
+        (
+            PLAIN_GLYPHS.clean,
+            ASCII_GLYPHS.clean,
+            "clean, and even with its upstream",
+        ),
+        (
+            PLAIN_GLYPHS.unpushed,
+            ASCII_GLYPHS.unpushed,
+            "commits not pushed",
+        ),
+        (
+            PLAIN_GLYPHS.no_upstream,
+            ASCII_GLYPHS.no_upstream,
+            "no upstream branch",
+        ),
+        (
+            PLAIN_GLYPHS.dirty,
+            ASCII_GLYPHS.dirty,
+            "uncommitted changes or conflicts",
+        ),
+        (
+            PLAIN_GLYPHS.error,
+            ASCII_GLYPHS.error,
+            "not a repo, or git failed",
+        ),
+        (
+            PLAIN_GLYPHS.behind,
+            ASCII_GLYPHS.behind,
+            "the upstream has commits to pull",
+        ),
+    ] {
+        page.push_str(&format!(
+            ".TP\n.B {glyph}\n({}) {meaning}\n",
+            roff_escape(ascii)
+        ));
+    }
+    page.push_str(MAN_EXIT_STATUS);
+    page
+}
+
+const MAN_CONFIGURATION: &str = r#".SH CONFIGURATION
+The config is TOML, read from
+.I $XDG_CONFIG_HOME/jiancha/config.toml
+(default
+.IR ~/.config/jiancha/config.toml ),
+or
+.I profiles/<name>.toml
+beside it for
+.B \-\-profile
+(or
+.B $JIANCHA_PROFILE
+or
+.BR $JIAN_CHA_PROFILE ,
+in that order).
+Each table other than the ones below is a section: repo names mapped to directories.
+.PP
+.nf
+[work]
+api = "~/src/api"
+clients = "~/projects/work\-*"
+web = { path = "~/src/web", url = "git@github.com:acme/web.git", shallow = true }
+.fi
+.PP
+A section may also set
+.B paths
+(show paths instead of names),
+.BR enabled ,
+.B jobs
+and
+.B refresh_interval_secs
+(with
+.BR \-\-watch ,
+scan the section at most this often).
+Top\-level keys:
+.B ignore_sections
+and
+.B display_order
+(lists of section names), and
+.B git_hosts
+(tables of
+.B domain
+and
+.BR https_base ).
+.TP
+.B [settings]
+pager, check_upstream, size_scope, number_format, highlight_errors, numbers, jobs, cache_ttl,
+timeout, icons, ascii, base_branch, wrap, branch_colors, feature_branch_color,
+detached_head_color, commit_age_colors, commit_age_days, min_clean_streak, open_command, columns,
+truncate_lead, padding, and the tables symbols, align and truncate.
+.TP
+.B [theme]
+name, date_format, and the table section_header (bold, underline, italic, color,
+background).
+.PP
+A repo's local state is reused for
+.B cache_ttl
+seconds (default 60) unless something under
+.I .git
+changed (a commit, checkout, stage or fetch). Edits to files that haven't been
+staged don't touch
+.IR .git ,
+so a dirty work tree can show as clean until the entry expires; use
+.B \-\-cache\-ttl 0
+to read git every time.
+"#;
+
+const MAN_EXIT_STATUS: &str = r#".SH EXIT STATUS
+.TP
+.B 0
+Success, whatever state the repos are in.
+.TP
+.B 1
+An error: a bad flag, an unreadable config, or
+.B \-\-max\-errors
+reached.
+.B path
+exits 1 when no repo matches, and
+.B \-\-format github
+when it writes an
+.B ::error::
+annotation.
+.TP
+.B 2
+.B path
+was given a name that fits more than one repo.
+.TP
+.I n
+.B exec
+exits with the highest status its command returned, or 127 when it couldn't be run.
+.PP
+.B status
+.I dir
+exits 0 when the repo is clean and in sync, 1 when it can't be read,
+3 with uncommitted changes, and 4 when it's ahead of or behind its upstream.
+.PP
+.B \-\-watch
+runs until interrupted with Ctrl\-C. A cycle interrupted midway is discarded: the
+cache and snapshot are only replaced whole, so they keep what the last completed cycle wrote.
+"#;
+
+/// A completion script for `shell`, generated from `FLAGS` and
+/// `COMPLETION_SUBCOMMANDS`. Section, repo and profile names are looked up each
+/// time through `jiancha __complete`, so they follow the config.
+fn completion_script(shell: Shell) -> String {
+    // Short aliases aren't offered; a word starting with `-` completes to a long flag.
+    let flags: Vec<String> = FLAGS
+        .iter()
+        .flat_map(Flag::spellings)
+        .filter(|spelling| spelling.starts_with("--"))
+        .collect();
+    let flags = flags.join(" ");
+    let subcommands: Vec<&str> = COMPLETION_SUBCOMMANDS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect();
+    let subcommands = subcommands.join(" ");
+    // Flags taking a value come first, then the words that follow a subcommand.
+    let arguments = FLAGS
+        .iter()
+        .flat_map(|flag| {
+            flag.spellings()
+                .into_iter()
+                .map(move |spelling| (spelling, &flag.completion))
+        })
+        .chain(
+            COMPLETION_SUBCOMMANDS
+                .iter()
+                .map(|(name, completion)| (name.to_string(), completion)),
+        )
+        .filter(|(_, completion)| !matches!(completion, Completion::Nothing));
+    let mut out = String::new();
+    match shell {
+        Shell::Bash => {
+            out.push_str(
+                "_jiancha() {\n    \
+                 local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n    \
+                 case \"$prev\" in\n",
+            );
+            for (word, completion) in arguments {
+                let reply = match completion {
+                    Completion::Words(words) => format!("$(compgen -W \"{words}\" -- \"$cur\")"),
+                    Completion::Names(kind) => format!(
+                        "$(compgen -W \"$(jiancha __complete {kind} 2>/dev/null)\" -- \"$cur\")"
+                    ),
+                    Completion::Files => "$(compgen -f -- \"$cur\")".to_string(),
+                    Completion::Anything | Completion::Nothing => String::new(),
+                };
+                out.push_str(&format!("        {word}) COMPREPLY=({reply}); return ;;\n"));
+            }
+            out.push_str(&format!(
+                "    esac\n    \
+                 if [[ \"$cur\" == -* ]]; then\n        \
+                 COMPREPLY=($(compgen -W \"{flags}\" -- \"$cur\"))\n    \
+                 elif [[ $COMP_CWORD -eq 1 ]]; then\n        \
+                 COMPREPLY=($(compgen -W \"{subcommands}\" -- \"$cur\") $(compgen -d -- \"$cur\"))\n    \
+                 else\n        \
+                 COMPREPLY=($(compgen -d -- \"$cur\"))\n    \
+                 fi\n\
+                 }}\n\
+                 complete -F _jiancha jiancha\n"
+            ));
+        }
+        Shell::Zsh => {
+            out.push_str(
+                "#compdef jiancha\n\n\
+                 _jiancha() {\n    \
+                 case ${words[CURRENT-1]} in\n",
+            );
+            for (word, completion) in arguments {
+                let action = match completion {
+                    Completion::Words(words) => format!("compadd -- {words}"),
+                    Completion::Names(kind) => {
+                        format!("compadd -- ${{(f)\"$(jiancha __complete {kind} 2>/dev/null)\"}}")
+                    }
+                    Completion::Files => "_files".to_string(),
+                    Completion::Anything | Completion::Nothing => "_message value".to_string(),
+                };
+                out.push_str(&format!("        {word}) {action}; return ;;\n"));
+            }
+            out.push_str(&format!(
+                "    esac\n    \
+                 if [[ $PREFIX == -* ]]; then\n        \
+                 compadd -- {flags}\n    \
+                 elif (( CURRENT == 2 )); then\n        \
+                 compadd -- {subcommands}\n        \
+                 _files -/\n    \
+                 else\n        \
+                 _files -/\n    \
+                 fi\n\
+                 }}\n\n\
+                 if [[ $funcstack[1] == _jiancha ]]; then\n    \
+                 _jiancha \"$@\"\n\
+                 else\n    \
+                 compdef _jiancha jiancha\n\
+                 fi\n"
+            ));
+        }
+        Shell::Fish => {
+            out.push_str(&format!(
+                "complete -c jiancha -f\n\
+                 complete -c jiancha -n __fish_use_subcommand -a '{subcommands}'\n\
+                 complete -c jiancha -n __fish_use_subcommand -a '(__fish_complete_directories)'\n"
+            ));
+            let fish_args = |completion: &Completion| match completion {
+                Completion::Nothing => String::new(),
+                Completion::Anything => " -x".to_string(),
+                Completion::Words(words) => format!(" -x -a '{words}'"),
+                Completion::Names(kind) => {
+                    format!(" -x -a '(jiancha __complete {kind} 2>/dev/null)'")
+                }
+                Completion::Files => " -r -F".to_string(),
+            };
+            for (name, completion) in &COMPLETION_SUBCOMMANDS {
+                if !matches!(completion, Completion::Nothing) {
+                    out.push_str(&format!(
+                        "complete -c jiancha -n '__fish_seen_subcommand_from {name}'{}\n",
+                        fish_args(completion).replacen(" -x", "", 1)
+                    ));
+                }
+            }
+            for flag in FLAGS {
+                let names: Vec<String> = flag
+                    .spellings()
+                    .iter()
+                    .map(|spelling| match spelling.strip_prefix("--") {
+                        Some(long) => format!(" -l {long}"),
+                        None => format!(" -s {}", spelling.trim_start_matches('-')),
+                    })
+                    .collect();
+                out.push_str(&format!(
+                    "complete -c jiancha{}{}\n",
+                    names.concat(),
+                    fish_args(&flag.completion)
+                ));
+            }
+        }
+        Shell::Powershell => {
+            out.push_str(
+                "Register-ArgumentCompleter -Native -CommandName jiancha -ScriptBlock {\n    \
+                 param($wordToComplete, $commandAst, $cursorPosition)\n    \
+                 $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n    \
+                 if ($wordToComplete) { $words = $words[0..($words.Count - 2)] }\n    \
+                 $prev = if ($words.Count -gt 1) { $words[-1] } else { '' }\n    \
+                 $candidates = switch ($prev) {\n",
+            );
+            for (word, completion) in arguments {
+                let candidates = match completion {
+                    Completion::Words(words) => {
+                        let quoted: Vec<String> =
+                            words.split(' ').map(|word| format!("'{word}'")).collect();
+                        quoted.join(", ")
+                    }
+                    Completion::Names(kind) => format!("jiancha __complete {kind} 2>$null"),
+                    Completion::Files => {
+                        "Get-ChildItem -Name -Path \"$wordToComplete*\"".to_string()
+                    }
+                    Completion::Anything | Completion::Nothing => "@()".to_string(),
+                };
+                out.push_str(&format!("        '{word}' {{ {candidates}; break }}\n"));
+            }
+            let quote = |list: &str| {
+                let quoted: Vec<String> = list.split(' ').map(|word| format!("'{word}'")).collect();
+                quoted.join(", ")
+            };
+            out.push_str(&format!(
+                "        default {{\n            \
+                 if ($wordToComplete -like '-*') {{ {} }}\n            \
+                 elseif ($words.Count -eq 1) {{ {}; Get-ChildItem -Directory -Name -Path \"$wordToComplete*\" }}\n            \
+                 else {{ Get-ChildItem -Directory -Name -Path \"$wordToComplete*\" }}\n        \
+                 }}\n    \
+                 }}\n    \
+                 $candidates | Where-Object {{ $_ -like \"$wordToComplete*\" }} | ForEach-Object {{\n        \
+                 [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    \
+                 }}\n\
+                 }}\n",
+                quote(&flags),
+                quote(&subcommands)
+            ));
+        }
+    }
+    out
+}
+
+/// `jiancha __complete <kind>`: one name per line for the completion scripts.
+/// `repos` qualifies a name with its section when more than one section has it,
+/// as `jiancha path` expects.
+fn complete_names(kind: &str, profile: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let names = match kind {
+        "profiles" => profile_names()?,
+        // Warnings would land in the middle of the command line being completed.
+        "sections" => load_profile_config(profile, false)?
+            .sections
+            .into_keys()
+            .collect(),
+        "repos" => {
+            let config = load_profile_config(profile, false)?;
+            let rows = row_entries(&config);
+            rows.iter()
+                .map(|row| {
+                    let shared = rows.iter().filter(|other| other.repo == row.repo).count() > 1;
+                    if shared {
+                        format!("{}/{}", row.section, row.repo)
+                    } else {
+                        row.repo.clone()
+                    }
+                })
+                .collect()
+        }
+        _ => return Err(format!("Unknown completion kind: {kind}").into()),
+    };
+    for name in names {
+        println!("{name}");
+    }
+    Ok(())
+}
+
+/// The `jiancha` command line: parses arguments, scans, and prints the report.
+/// Returns the exit status for the binary to exit with.
+pub fn run() -> Result<i32, Box<dyn std::error::Error>> {
+    let args = match parse_args(std::env::args().skip(1))? {
+        Invocation::Run(args) => args,
+        Invocation::Help => {
+            println!("{}\n\n{HELP_NOTES}", usage());
+            return Ok(EXIT_OK);
+        }
+        Invocation::Version => {
+            println!("jiancha {}", env!("CARGO_PKG_VERSION"));
+            return Ok(EXIT_OK);
+        }
+    };
+    match args.watch {
+        Some(interval) => watch(*args, interval).map(|()| EXIT_OK),
+        None => run_once(&args, None).map(|(_, code)| code),
+    }
+}
+
+/// What `--watch` keeps from one cycle to the next.
+#[derive(Default)]
+struct WatchState {
+    /// The config file's modification time and contents when it last parsed, so
+    /// it's only reread after a change and a broken edit doesn't replace it.
+    config: Option<(Option<SystemTime>, String)>,
+    /// Shown in the heading for the cycle that reloaded the config.
+    notice: Option<&'static str>,
+    /// Why the file on disk isn't in use, shown until it parses again.
+    config_error: Option<String>,
+    /// When each section with a `refresh_interval_secs` was last scanned, and its
+    /// rows from then.
+    sections: HashMap<String, (Instant, Vec<RepoResult>)>,
+}
+
+impl WatchState {
+    /// This cycle's config: the file at `path` when it changed and parses, else the
+    /// last version that did. Only a failure on the first read is an error.
+    fn load_config(&mut self, path: PathBuf, strict: bool) -> Result<Config, ConfigError> {
+        let modified = std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        self.notice = None;
+        if let Some((seen, contents)) = &self.config {
+            if *seen == modified {
+                return parse_config(contents, strict);
+            }
+        }
+        let loaded = std::fs::read_to_string(&path)
+            .map_err(|source| ConfigError::Read { path, source })
+            .and_then(|contents| Ok((parse_config(&contents, strict)?, contents)));
+        match (loaded, &mut self.config) {
+            (Ok((loaded, contents)), previous) => {
+                if previous.is_some() {
+                    self.notice = Some("config reloaded");
+                    // Kept rows may be for repos the edit moved or removed.
+                    self.sections.clear();
+                }
+                *previous = Some((modified, contents));
+                self.config_error = None;
+                Ok(loaded)
+            }
+            (Err(e), Some((seen, contents))) => {
+                *seen = modified;
+                self.config_error = Some(e.to_string());
+                parse_config(contents, strict)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Sections to leave out of this cycle's scan: their last rows are younger
+    /// than their `refresh_interval_secs`.
+    fn held_sections(&self, config: &Config) -> HashSet<String> {
+        config
+            .sections
+            .iter()
+            .filter(|(name, section)| {
+                let interval = section.refresh_interval_secs.map(Duration::from_secs);
+                self.sections
+                    .get(*name)
+                    .zip(interval)
+                    .is_some_and(|((scanned_at, _), interval)| scanned_at.elapsed() < interval)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// This cycle's rows in config order: `scanned` for the sections it scanned,
+    /// the kept rows for `held` ones. Scanned rows of sections with an interval
+    /// are kept for the next cycles.
+    fn merge(
+        &mut self,
+        config: &Config,
+        scanned: Vec<RepoResult>,
+        held: &HashSet<String>,
+        scanned_at: Instant,
+    ) -> Vec<RepoResult> {
+        let mut by_section: HashMap<String, Vec<RepoResult>> = HashMap::new();
+        for repo in scanned {
+            by_section
+                .entry(repo.section.clone())
+                .or_default()
+                .push(repo);
+        }
+        self.sections.retain(|name, _| {
+            config
+                .sections
+                .get(name)
+                .is_some_and(|s| s.refresh_interval_secs.is_some())
+        });
+        let mut repos = Vec::new();
+        for (name, section) in &config.sections {
+            if held.contains(name) {
+                if let Some((_, rows)) = self.sections.get(name) {
+                    repos.extend(rows.iter().cloned());
+                }
+                continue;
+            }
+            let rows = by_section.remove(name).unwrap_or_default();
+            if section.refresh_interval_secs.is_some() {
+                self.sections
+                    .insert(name.clone(), (scanned_at, rows.clone()));
+            }
+            repos.extend(rows);
+        }
+        repos
+    }
+}
+
+/// Redraws the report every `interval` until interrupted. The config is reread
+/// when its file changes, keeping the last good one while an edit doesn't parse,
+/// and the caches keep each cycle cheap; an unchanged report only has its
+/// heading's time updated, so the screen doesn't flicker.
+///
+/// There's no interrupt handler: Ctrl-C ends the process wherever it is, and a
+/// cycle cut short is simply lost. That's safe because the cache and snapshot
+/// are only ever replaced whole (see `write_atomic`), so the next run starts
+/// from the last completed cycle.
+fn watch(mut args: Args, interval: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let mut shown: Option<String> = None;
+    let mut state = WatchState::default();
+    loop {
+        // A failing cycle is shown like a report and retried next time.
+        // The exit status only matters for a single run.
+        let output = match run_once(&args, Some(&mut state)) {
+            Ok((output, _)) => output.unwrap_or_default(),
+            Err(e) => format!("\nError: {e}\n"),
+        };
+        let output = match &state.config_error {
+            Some(e) => format!("\nConfig error, still showing the last good config: {e}\n{output}"),
+            None => output,
+        };
+        // Only the first cycle fetches everything for --fresh.
+        args.fresh = false;
+        let mut heading = format!(
+            "Every {}s, updated {}",
+            interval.as_secs(),
+            clock_time(now_secs())
+        );
+        if let Some(notice) = state.notice {
+            heading.push_str(&format!(" ({notice})"));
+        }
+        // The footer's scan time differs every cycle, so it doesn't count as a change.
+        let report = output
+            .trim_end()
+            .rsplit_once('\n')
+            .map_or(output.as_str(), |(report, _)| report);
+        let mut stdout = std::io::stdout().lock();
+        if shown.as_deref() == Some(report) {
+            // Save and restore the cursor so Ctrl-C leaves the prompt below the report.
+            write!(stdout, "\x1b7\x1b[H{heading}\x1b[K\x1b8")?;
+        } else {
+            write!(stdout, "\x1b[H\x1b[2J{heading}\n{output}")?;
+            shown = Some(report.to_string());
+        }
+        stdout.flush()?;
+        drop(stdout);
+        thread::sleep(interval);
+    }
+}
+
+/// One status run, or any other subcommand, and the status jiancha should exit
+/// with. With `--watch` the report is handed back instead of printed, and `watch`
+/// supplies the config.
+fn run_once(
+    args: &Args,
+    watch: Option<&mut WatchState>,
+) -> Result<(Option<String>, i32), Box<dyn std::error::Error>> {
+    // The scan's own threads get theirs through `CollectOptions::git_options`.
+    let git_options = GitOptions {
+        load_envrc: args.load_envrc,
+        ..GitOptions::default()
+    };
+    let mut exit_status = EXIT_OK;
+    let (output, _) = with_git_options(git_options, || run_command(args, watch, &mut exit_status));
+    Ok((output?, exit_status))
+}
+
+/// `run_once`'s body. `exit_status` is left at 0 unless something sets it.
+fn run_command(
+    args: &Args,
+    mut watch: Option<&mut WatchState>,
+    exit_status: &mut i32,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let profile = active_profile(args);
+    let command_opts = match &args.command {
+        Subcommand::Status
+        | Subcommand::Push(_)
+        | Subcommand::Gc(_)
+        | Subcommand::Exec(_)
+        | Subcommand::List { .. }
+        | Subcommand::Path(_)
+        | Subcommand::Open { .. }
+        | Subcommand::Browse { .. }
+        | Subcommand::Clone { .. }
+        | Subcommand::CheckStale(_)
+        | Subcommand::ConfigDiff => None,
+        Subcommand::Fetch(opts) | Subcommand::Pull(opts) => Some(opts),
+        Subcommand::ProfilesList => return list_profiles(profile.as_deref()).map(|()| None),
+        Subcommand::UpdateSelf => return update_self().map(|()| None),
+        Subcommand::ProfilesCreate(name) => return create_profile(name).map(|()| None),
+        Subcommand::Diagnose { dir, json } => {
+            print!("{}", diagnose(dir, *json));
+            return Ok(None);
+        }
+        Subcommand::Completions(shell) => {
+            print!("{}", completion_script(*shell));
+            return Ok(None);
+        }
+        Subcommand::Man => {
+            print!("{}", man_page());
+            return Ok(None);
+        }
+        Subcommand::Complete(kind) => {
+            return complete_names(kind, profile.as_deref()).map(|()| None)
+        }
+    };
+    let force_fetch = args.fresh || command_opts.is_some();
+    // Prompt redraws must stay fast and shouldn't count as a run: no fetching, and
+    // the cache and snapshot are left alone.
+    let prompt = matches!(args.format, OutputFormat::Oneline { .. })
+        && matches!(args.command, Subcommand::Status);
+    let offline = args.offline || prompt;
+    let dry_run = command_opts.is_some_and(|opts| opts.dry_run);
+    // A pull has to fetch to know what it would pull, even on a dry run.
+    let pull = matches!(args.command, Subcommand::Pull(_));
+
+    // Directories on the command line stand in for the config file entirely.
+    let adhoc = !args.dirs.is_empty();
+    let mut config = match (&args.config, watch.as_deref_mut()) {
+        _ if args.single => single_repo_config(args, profile.as_deref())?,
+        _ if adhoc => adhoc_config(&args.dirs)?,
+        (Some(path), Some(watch)) => show_warnings(watch.load_config(path.clone(), args.strict)?),
+        (None, Some(watch)) => {
+            show_warnings(watch.load_config(config_path(profile.as_deref())?, args.strict)?)
+        }
+        (Some(path), None) => show_warnings(load_config_file(path.clone(), args.strict)?),
+        (None, None) => show_warnings(load_profile_config(profile.as_deref(), args.strict)?),
+    };
+    let check_upstream = !args.no_fetch_check && config.settings.check_upstream.unwrap_or(true);
+    // Checked here rather than in parse_args so `check_upstream = false` counts too.
+    if matches!(args.command, Subcommand::Pull(_) | Subcommand::Push(_)) && !check_upstream {
+        return Err(
+            "pull and push cannot run with --no-fetch-check or check_upstream = false".into(),
+        );
+    }
+    if matches!(args.command, Subcommand::ConfigDiff) {
+        // Repos from every section still count as configured, so don't narrow the config.
+        if let Some(section) = &args.section {
+            check_section(&config, section)?;
+        }
+        print!("{}", render_config_diff(&config, args.section.as_deref()));
+        return Ok(None);
+    }
+    // Rows are numbered across every section, so `--section` doesn't renumber them.
+    let rows = row_entries(&config);
+    if let Subcommand::Path(reference) = &args.command {
+        let Some(reference) = reference else {
+            if let Some(section) = &args.section {
+                check_section(&config, section)?;
+            }
+            print!("{}", render_path_list(&rows, args.section.as_deref()));
+            return Ok(None);
+        };
+        match resolve_repo(&config, &rows, reference) {
+            Ok(row) => println!("{}", absolute_dir(&row.directory)),
+            // Exit 2 tells a shell function to show the list rather than cd anywhere.
+            Err(e) if e.downcast_ref::<AmbiguousRepo>().is_some() => {
+                eprintln!("{e}");
+                *exit_status = EXIT_AMBIGUOUS;
+            }
+            Err(e) => return Err(e),
+        }
+        return Ok(None);
+    }
+    if let Subcommand::Open { repo, shell } = &args.command {
+        let row = resolve_repo(&config, &rows, repo)?;
+        if *shell {
+            println!("{}", row.directory);
+        } else {
+            open_repo(&row, config.settings.open_command.as_deref())?;
+        }
+        return Ok(None);
+    }
+    if let Subcommand::Browse { repo, target } = &args.command {
+        let row = resolve_repo(&config, &rows, repo)?;
+        browse_repo(&config, &row, *target)?;
+        return Ok(None);
+    }
+    if let Subcommand::Clone {
+        repo: Some(reference),
+        dry_run,
+    } = &args.command
+    {
+        clone_repo(&config, &resolve_repo(&config, &rows, reference)?, *dry_run)?;
+        return Ok(None);
+    }
+    if let Some(section) = &args.section {
+        select_section(&mut config, section)?;
+    }
+    let jobs = args
+        .jobs
+        .or(config.settings.jobs)
+        .unwrap_or_else(default_jobs);
+    // `jiancha clone` fills in what's missing, then reports on everything as usual.
+    let mut cloned = match args.command {
+        Subcommand::Clone {
+            repo: None,
+            dry_run,
+        } => clone_missing(&config, dry_run, jobs),
+        _ => HashMap::new(),
+    };
+    if let Subcommand::List { json } = args.command {
+        let home = std::env::var("HOME").ok().filter(|_| args.relative_paths);
+        let output = if args.paths {
+            render_list_paths(&config, json, home.as_deref())
+        } else {
+            let file = match &args.config {
+                Some(path) => path.clone(),
+                None => config_path(profile.as_deref())?,
+            };
+            let source = ListSource {
+                file: &file,
+                profile: profile.as_deref(),
+            };
+            render_list(&config, &source, json, home.as_deref())
+        };
+        print!("{output}");
+        return Ok(None);
+    }
+    if let Subcommand::CheckStale(opts) = &args.command {
+        let now = now_secs();
+        let stale = find_stale(&collect_repo_results(&config), opts.days, now);
+        print!("{}", render_stale(&stale, opts.json, now));
+        if opts.github_archive && !stale.is_empty() {
+            archive_on_github(&stale)?;
+        }
+        return Ok(None);
+    }
+    let theme_name = args
+        .theme
+        .as_deref()
+        .or(config.theme.name.as_deref())
+        .unwrap_or("default");
+    let mut theme = Theme::by_name(theme_name, ColorMode::detect())?;
+    let header = &config.theme.section_header;
+    let style = &mut theme.section_header;
+    style.bold = header.bold.unwrap_or(style.bold);
+    style.underline = header.underline.unwrap_or(style.underline);
+    style.italic = header.italic.unwrap_or(style.italic);
+    for (key, spec, color) in [
+        (
+            "settings.feature_branch_color",
+            &config.settings.feature_branch_color,
+            &mut theme.feature_branch,
+        ),
+        (
+            "settings.detached_head_color",
+            &config.settings.detached_head_color,
+            &mut theme.detached_head,
+        ),
+        (
+            "theme.section_header.color",
+            &header.color,
+            &mut theme.section_header.fg,
+        ),
+        (
+            "theme.section_header.background",
+            &header.background,
+            &mut theme.section_header.bg,
+        ),
+    ] {
+        if let Some(spec) = spec {
+            let parsed =
+                parse_color(spec).ok_or_else(|| format!("Invalid color for {key}: {spec:?}"))?;
+            *color = theme.mode.adapt(parsed);
+        }
+    }
+    let commit_age_days = config
+        .settings
+        .commit_age_days
+        .unwrap_or(DEFAULT_COMMIT_AGE_DAYS);
+    if !commit_age_days.is_sorted() {
+        return Err(format!(
+            "settings.commit_age_days must be in ascending order: {commit_age_days:?}"
+        )
+        .into());
+    }
+    let column_order = args
+        .columns
+        .as_ref()
+        .or(config.settings.columns.as_ref())
+        .map(|names| parse_columns(names))
+        .transpose()?;
+    let icons = args.icons || config.settings.icons.unwrap_or(false);
+    let ascii = args.ascii || config.settings.ascii.unwrap_or_else(|| !locale_is_utf8());
+    // Nerd Font icons are never ASCII, so --ascii wins over --icons.
+    let icons = icons && !ascii;
+    let glyphs = if ascii {
+        &ASCII_GLYPHS
+    } else if icons {
+        &NERD_GLYPHS
+    } else {
+        &PLAIN_GLYPHS
+    };
+    let symbols = Symbols::from_config(&config.settings.symbols, &theme, glyphs)?;
+    let now = now_secs();
+    let cache_path = cache_path()?;
+    let mut cache = load_cache(&cache_path);
+    let previous_run_at = cache.last_run_at;
+    let force_session_refresh = previous_run_at
+        .map(|last_run_at| now.saturating_sub(last_run_at) > SESSION_GAP_REFRESH_SECS)
+        .unwrap_or(true);
+
+    let cache_ttl = args
+        .cache_ttl
+        .or(config.settings.cache_ttl)
+        .unwrap_or(DEFAULT_CACHE_TTL_SECS);
+    let timeout = Some(
+        args.timeout
+            .or(config.settings.timeout)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS),
+    )
+    .filter(|&secs| secs > 0)
+    .map(Duration::from_secs);
+
+    let snapshot_path = snapshot_path()?;
+    let mut previous = load_snapshot(&snapshot_path);
+
+    let scan_started = Instant::now();
+    let collect_opts = CollectOptions {
+        check_upstream,
+        show_size: args.show_size,
+        size_scope: config.settings.size_scope.unwrap_or_default(),
+        icons,
+        base_branch: args
+            .base_branch
+            .clone()
+            .or(config.settings.base_branch.clone()),
+        check_force_push: args.check_force_push,
+        min_clean_streak: args.min_clean_streak.or(config.settings.min_clean_streak),
+        show_contributors: args.show_contributors,
+        diffstat: args.show_diffstat || args.machine_json || args.format == OutputFormat::Yaml,
+        brief: prompt,
+        offline,
+        show_lfs: args.show_lfs,
+        security: args.security,
+        remote_url: !args.remote_filters.is_empty(),
+        jobs,
+        skip_sections: watch
+            .as_deref()
+            .map_or_else(HashSet::new, |watch| watch.held_sections(&config)),
+        max_errors: args.max_errors,
+        timeout,
+        scan_deadline: timeout.map(|timeout| scan_started + timeout * SCAN_DEADLINE_FACTOR),
+        load_envrc: args.load_envrc,
+        // Machine formats are read by other programs, which don't want the noise.
+        progress: Progress {
+            enabled: !args.quiet
+                && !args.machine_json
+                && args.watch.is_none()
+                && std::io::stderr().is_terminal()
+                && !args.format.is_machine(),
+            home: std::env::var("HOME").ok(),
+            width: terminal_width().map_or(80, usize::from),
+            ellipsis: glyphs.ellipsis,
+        },
+        info_cache: if args.no_cache || args.fresh {
+            HashMap::new()
+        } else {
+            std::mem::take(&mut cache.info)
+                .into_iter()
+                .filter(|(_, entry)| now.saturating_sub(entry.checked_at) <= cache_ttl)
+                .collect()
+        },
+        fresh_info: (!args.no_cache).then(|| Mutex::new(HashMap::new())),
+        todo_counts: args.deep.then(|| {
+            previous
+                .iter()
+                .flat_map(|snapshot| &snapshot.repos)
+                .filter_map(|e| Some((e.directory.clone(), (e.head.clone()?, e.todos?))))
+                .collect()
+        }),
+    };
+    let mut repos = collect_all(&config, &collect_opts)?;
+    let streak_counted = matches!(args.command, Subcommand::Status) && watch.is_none();
+    for repo in &mut repos {
+        if let Some((action, error)) = cloned.remove(&(repo.section.clone(), repo.repo_key.clone()))
+        {
+            repo.action = Some(action);
+            // Otherwise it's just "Not a valid directory".
+            if error.is_some() {
+                repo.local_error = error;
+            }
+        }
+        let entry = previous
+            .iter()
+            .flat_map(|snapshot| &snapshot.repos)
+            .find(|entry| entry.directory == repo.directory);
+        repo.clean_streak = compute_streak(entry, repo, streak_counted);
+    }
+    // Dropped before fetching, so filtered-out repos cost no network round trip.
+    let mut filtered_out = HashSet::new();
+    if !args.remote_filters.is_empty() || args.dirty || args.filter_stale_branches {
+        repos.retain(|repo| {
+            let url = repo.remote_url.as_deref().unwrap_or_default();
+            let keep = (args.remote_filters.is_empty()
+                || args.remote_filters.iter().any(|f| url.contains(f.as_str())))
+                && (!args.dirty || repo.clean == Some(false))
+                && (!args.filter_stale_branches || repo.stale_tracking_count > 0);
+            if !keep {
+                filtered_out.insert(repo.directory.clone());
+            }
+            keep
+        });
+    }
+
+    let mut fetch_indices = Vec::new();
+    for (idx, repo) in repos.iter_mut().enumerate() {
+        if repo.local_error.is_some() {
+            continue;
+        }
+
+        if !check_upstream {
+            repo.fetch_status = FetchStatus::Skipped;
+            continue;
+        }
+
+        if dry_run || pull {
+            fetch_indices.push(idx);
+            continue;
+        }
+
+        let cached_status = cache
+            .repos
+            .get(&repo.directory)
+            .and_then(|entry| cached_fetch_status(entry, repo, now));
+
+        if !force_fetch && !force_session_refresh {
+            if let Some(status) = cached_status {
+                repo.fetch_status = status;
+                continue;
+            }
+        }
+
+        if collect_opts.offline {
+            if let Some(status) = cached_status {
+                repo.fetch_status = status;
+            }
+            continue;
+        }
+
+        fetch_indices.push(idx);
+    }
+
+    if pull {
+        for (idx, repo) in repos.iter_mut().enumerate() {
+            if !fetch_indices.contains(&idx) {
+                let reason = if repo.local_error.is_some() {
+                    "SKIPPED: error"
+                } else {
+                    "SKIPPED: not fetched"
+                };
+                repo.action = Some(reason.into());
+            }
+        }
+    } else if dry_run {
+        for repo in repos.iter_mut() {
+            repo.action = Some("SKIPPED".into());
+        }
+        for idx in fetch_indices.drain(..) {
+            repos[idx].action = Some("WOULD FETCH".into());
+        }
+    }
+
+    let mut opts = RenderOptions {
+        symbols: &symbols,
+        align: &config.settings.align,
+        column_order,
+        column_aliases: parse_column_aliases(&args.column_aliases)?,
+        show_fetch_time: args.show_fetch_time,
+        row_numbers: (args.numbers || config.settings.numbers.unwrap_or(false)).then(|| {
+            rows.iter()
+                .enumerate()
+                .map(|(i, row)| ((row.section.clone(), row.repo.clone()), i + 1))
+                .collect()
+        }),
+        truncate: &config.settings.truncate,
+        truncate_lead: config.settings.truncate_lead.unwrap_or(8),
+        wrap: args.wrap || config.settings.wrap.unwrap_or(false),
+        branch_colors: config.settings.branch_colors.unwrap_or(true),
+        commit_age_days: config
+            .settings
+            .commit_age_colors
+            .unwrap_or(true)
+            .then_some(commit_age_days),
+        min_clean_streak: args.min_clean_streak.or(config.settings.min_clean_streak),
+        padding: config.settings.padding.unwrap_or((0, 1)),
+        glyphs,
+        theme: &theme,
+        path_sections: config
+            .sections
+            .iter()
+            .filter(|(_, section)| args.paths || section.paths.unwrap_or(false))
+            .map(|(name, _)| name.clone())
+            .collect(),
+        home: std::env::var("HOME").ok(),
+        show_size: args.show_size,
+        size_threshold: args.size_threshold,
+        show_contributors: args.show_contributors,
+        show_lfs: args.show_lfs,
+        show_hooks: args.show_hooks,
+        show_envrc: args.show_envrc,
+        show_contributing: args.show_contributing,
+        show_diffstat: args.show_diffstat,
+        show_changes: matches!(args.command, Subcommand::Fetch(_)) && !dry_run,
+        security: args.security,
+        offline,
+        timings: args.timings,
+        deep: args.deep,
+        scan_duration: Duration::ZERO,
+        group_by: args.group_by,
+        highlight_errors: config.settings.highlight_errors.unwrap_or(false),
+        date_format: args.date_format.or(config.theme.date_format),
+        number_format: args
+            .number_format
+            .or(config.settings.number_format)
+            .unwrap_or_default(),
+        now,
+        icons,
+        color: match args.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                args.output.is_none()
+                    && std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+        },
+        // Only adapt to the terminal when writing to it; redirected output keeps full widths.
+        viewport_width: if std::io::stdout().is_terminal() {
+            terminal_width().map(|w| w.saturating_sub(2))
+        } else {
+            None
+        },
+    };
+    // With --machine-json or --stream a repo is written as soon as nothing about it
+    // can change: right away unless it's being fetched, otherwise when its fetch finishes.
+    let mut stream = args.stream.then(|| {
+        let groups = group_repos(&repos, config.sections.keys(), args.group_by);
+        StreamTable::new(&repos, groups, &opts)
+    });
+    let mut emit = |repos: &[RepoResult], idx: usize| -> std::io::Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        if args.machine_json {
+            write_ndjson_result(&repos[idx], &mut stdout)?;
+        }
+        if let Some(stream) = stream.as_mut() {
+            let mut text = stream.finish(repos, idx, &opts);
+            if args.ansi_strip {
+                text = strip_ansi(&text);
+            }
+            if !text.is_empty() {
+                // Rows go out between progress updates; erase the line first so they don't tear.
+                collect_opts.progress.clear();
+                stdout.write_all(text.as_bytes())?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    };
+    for idx in 0..repos.len() {
+        if !fetch_indices.contains(&idx) {
+            emit(&repos, idx)?;
+        }
+    }
+
+    // `jiancha fetch` updates every remote and reports what moved.
+    let update_all = matches!(args.command, Subcommand::Fetch(_));
+    let (fetched_tx, fetched) = mpsc::channel();
+    let fetch_total = fetch_indices.len();
+    let fetch_started = Instant::now();
+    for &idx in &fetch_indices {
+        let dir = repos[idx].directory.clone();
+        let started = Instant::now();
+        let git_options = collect_opts.git_options(started);
+        let fetched_tx = fetched_tx.clone();
+        thread::spawn(move || {
+            let result = panic::catch_unwind(|| {
+                with_git_options(git_options, || run_git_fetch(&dir, update_all))
+            })
+            .unwrap_or_else(|_| (FetchOutcome::failed(None), false));
+            let _ = fetched_tx.send((idx, result, started.elapsed()));
+        });
+    }
+    drop(fetched_tx);
+
+    // Fetches are taken in the order they finish, not the order they started.
+    for (done, (idx, (outcome, timed_out), elapsed)) in fetched.iter().enumerate() {
+        collect_opts
+            .progress
+            .show("fetching", done + 1, fetch_total, &repos[idx].directory);
+        if timed_out {
+            let message = collect_opts.timeout_message(fetch_started);
+            repos[idx].local_error = Some(format!("fetch {message}"));
+        } else if update_all && outcome.error.is_some() {
+            // An explicit fetch reports why a repo failed rather than just `err`.
+            repos[idx].local_error = outcome.error;
+        }
+        repos[idx].scan_duration += elapsed;
+        repos[idx].ref_updates = outcome.updates;
+        repos[idx].fetch_status = outcome.status;
+        if pull {
+            let started = Instant::now();
+            let action = pull_repo(&repos[idx], dry_run);
+            if action.starts_with("PULLED") {
+                // Re-read so the row shows the new HEAD.
+                let repo = &repos[idx];
+                let dir = repo.directory.clone();
+                let row = collect_repo(&repo.section, &repo.repo_key, &dir, &collect_opts);
+                repos[idx] = RepoResult {
+                    scan_duration: repo.scan_duration,
+                    fetch_status: FetchStatus::UpToDate,
+                    clean_streak: repo.clean_streak,
+                    ..row
+                };
+            }
+            repos[idx].scan_duration += started.elapsed();
+            repos[idx].action = Some(action);
+        }
+        let status = repos[idx].fetch_status.clone();
+        cache.repos.insert(
+            repos[idx].directory.clone(),
+            CacheEntry {
+                repo_key: repos[idx].repo_key.clone(),
+                branch: repos[idx].branch.clone().unwrap_or_default(),
+                upstream: repos[idx].upstream.clone(),
+                fetched_at: now,
+                fetch_status: status,
+            },
+        );
+        emit(&repos, idx)?;
+    }
+
+    collect_opts.progress.clear();
+    if let Some(watch) = watch {
+        repos = watch.merge(&config, repos, &collect_opts.skip_sections, scan_started);
+    }
+
+    if let Subcommand::Push(push) = &args.command {
+        push_repos(&mut repos, push, &collect_opts)?;
+    }
+    if let Subcommand::Gc(gc) = &args.command {
+        gc_repos(&mut repos, gc.dry_run, &collect_opts);
+    }
+    if let Subcommand::Exec(exec) = &args.command {
+        let worst = exec_repos(&mut repos, exec, collect_opts.jobs, &opts);
+        // The worst status any command exited with becomes jiancha's own.
+        *exit_status = worst;
+    }
+    if let ([repo], true) = (repos.as_slice(), args.single) {
+        *exit_status = single_repo_exit(repo);
+    }
+
+    if !dry_run && !prompt {
+        // Entries still within the TTL carry over; repos read this run replace theirs.
+        if let Some(fresh) = collect_opts.fresh_info {
+            cache.info = collect_opts.info_cache;
+            cache
+                .info
+                .extend(fresh.into_inner().unwrap_or_else(PoisonError::into_inner));
+        }
+        cache.last_run_at = Some(now);
+        save_cache(&cache_path, &cache)?;
+    }
+    let scan_duration = scan_started.elapsed();
+    opts.scan_duration = scan_duration;
+
+    let mut entries = snapshot_entries(&repos);
+    // Sections left out by `--section`, and repos left out by `--remote-filter` or
+    // `--dirty`, are carried over untouched, not reported as removed.
+    let mut carried_over = Vec::new();
+    if let Some(previous) = previous.as_mut() {
+        let (scanned, skipped) = previous.repos.drain(..).partition(|e| {
+            config.sections.contains_key(&e.section)
+                && !filtered_out.contains(&e.directory)
+                && !collect_opts.skip_sections.contains(&e.section)
+        });
+        previous.repos = scanned;
+        carried_over = skipped;
+    }
+    if args.format.is_machine() {
+        for repo in &repos {
+            let label = format!("{}/{}", repo.section, repo.repo_key);
+            if let Some(error) = &repo.local_error {
+                warn(format_args!("{label}: {error}"));
+            } else if matches!(repo.fetch_status, FetchStatus::Error) {
+                warn(format_args!("{label}: fetch failed"));
+            }
+        }
+    }
+    let output = if args.machine_json {
+        String::new()
+    } else if let Some(stream) = stream.as_mut() {
+        let all: Vec<usize> = (0..repos.len()).collect();
+        format!(
+            "{}{}: {}\n",
+            stream.drain(&repos, &opts),
+            scan_footer(repos.len(), &opts),
+            state_counts(&repos, &all, &opts).join(" | ")
+        )
+    } else if args.diff {
+        render_diff(previous.as_ref(), &entries, now)
+    } else if args.format == OutputFormat::Prometheus {
+        render_prometheus(&repos, scan_duration)
+    } else if args.format == OutputFormat::Porcelain {
+        render_porcelain(&repos)
+    } else if let OutputFormat::Oneline { counts } = args.format {
+        render_oneline(&repos, &opts, counts)
+    } else if args.format == OutputFormat::Tsv {
+        let columns = opts.column_order.as_deref().unwrap_or(&DELIMITED_COLUMNS);
+        render_delimited(&repos, columns, !args.no_header, '\t', &opts)
+    } else if args.format == OutputFormat::Yaml {
+        render_yaml(&repos)
+    } else if args.format == OutputFormat::Compact {
+        let groups = group_repos(&repos, config.sections.keys(), args.group_by);
+        render_compact(&repos, &groups, &opts)
+    } else if args.format == OutputFormat::Github {
+        // An error annotation fails the workflow step, as a linter's would.
+        if repos
+            .iter()
+            .any(|repo| github_level(repo_state(repo)) == Some("error"))
+        {
+            *exit_status = EXIT_ERROR;
+        }
+        let groups = group_repos(&repos, config.sections.keys(), args.group_by);
+        render_github(&repos) + &render_all(&repos, &groups, &opts)
+    } else {
+        let groups = group_repos(&repos, config.sections.keys(), args.group_by);
+        render_all(&repos, &groups, &opts)
+    };
+    let output = if args.ansi_strip {
+        strip_ansi(&output)
+    } else {
+        output
+    };
+    // An ad-hoc run isn't the configured set of repos, so it mustn't replace the
+    // snapshot that `--diff` and `jiancha path <n>` read back.
+    if !dry_run && !prompt && !adhoc {
+        // Keep TODO counts from earlier `--deep` runs for the next one.
+        if let Some(previous) = &previous {
+            for entry in entries.iter_mut().filter(|e| e.todos.is_none()) {
+                if let Some(old) = previous
+                    .repos
+                    .iter()
+                    .find(|p| p.directory == entry.directory)
+                {
+                    entry.todos = old.todos;
+                    entry.head = old.head.clone();
+                }
+            }
+        }
+        entries.extend(carried_over);
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at: now,
+            repos: entries,
+        };
+        write_atomic(&snapshot_path, &toml::to_string_pretty(&snapshot)?)?;
+        write_atomic(
+            &row_index_path()?,
+            &toml::to_string_pretty(&RowIndex { rows })?,
+        )?;
+    }
+
+    // Everything but the stream's footer is already out.
+    if args.machine_json || args.stream {
+        print!("{output}");
+        return Ok(None);
+    }
+
+    if let Some(path) = &args.output {
+        write_atomic(path, &output)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        eprintln!("Wrote report to {}", path.display());
+        return Ok(None);
+    }
+
+    if args.watch.is_some() {
+        return Ok(Some(output));
+    }
+
+    let use_pager =
+        !args.no_pager && config.settings.pager.unwrap_or(true) && !args.format.is_machine();
+    if !(use_pager && page_output(&output)) {
+        print!("{output}");
+    }
+    Ok(None)
+}
+
+/// Sends `output` through `$PAGER` (default `less -R`) when stdout is a terminal
+/// and the output is taller than it. Returns false if the caller should print instead.
+fn page_output(output: &str) -> bool {
+    if !std::io::stdout().is_terminal() {
+        return false;
+    }
+    let Some(height) = terminal_height() else {
+        return false;
+    };
+    if output.lines().count() < height as usize {
+        return false;
+    }
+
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    if pager.trim().is_empty() || pager.trim() == "cat" {
+        return false;
+    }
+
+    let Ok(mut child) = Command::new("sh")
+        .args(["-c", &pager])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+    else {
+        return false;
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may quit before reading everything; a broken pipe is expected then.
+        let _ = stdin.write_all(output.as_bytes());
+    }
+    let _ = child.wait();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    mod support;
+
+    use super::*;
+    use support::{fixture_git, fixture_repo, parse_json, scratch_dir, Json};
+
+    #[test]
+    fn rgb_to_ansi256_picks_cube_or_gray() {
+        assert_eq!(rgb_to_ansi256(255, 140, 0), 208);
+        assert_eq!(rgb_to_ansi256(0, 0, 0), 16);
+        assert_eq!(rgb_to_ansi256(255, 255, 255), 231);
+        assert_eq!(rgb_to_ansi256(128, 128, 128), 244);
+        assert_eq!(rgb_to_ansi256(28, 28, 28), 234);
+    }
+
+    #[test]
+    fn truecolor_is_kept_only_when_the_terminal_has_it() {
+        let orange = Color::Rgb {
+            r: 255,
+            g: 140,
+            b: 0,
+        };
+        assert_eq!(ColorMode::TrueColor.adapt(orange), orange);
+        assert_eq!(ColorMode::Ansi256.adapt(orange), Color::AnsiValue(208));
+        assert_eq!(ColorMode::Ansi256.adapt(Color::Red), Color::Red);
+        assert_eq!(fg_sgr(orange).as_deref(), Some("38;2;255;140;0"));
+        assert_eq!(fg_sgr(Color::AnsiValue(208)).as_deref(), Some("38;5;208"));
+        assert_eq!(fg_sgr(Color::Reset), None);
+    }
+
+    #[test]
+    fn shorten_path_collapses_home() {
+        assert_eq!(
+            shorten_path("/home/u/src/api", Some("/home/u"), 40, "…"),
+            "~/src/api"
+        );
+        assert_eq!(
+            shorten_path("/home/u/src/api", None, 40, "…"),
+            "/home/u/src/api"
+        );
+    }
+
+    #[test]
+    fn shorten_path_elides_middle_components() {
+        let path = "/home/u/work/clients/acme/api";
+        assert_eq!(
+            shorten_path(path, Some("/home/u"), 23, "…"),
+            "~/work/clients/acme/api"
+        );
+        assert_eq!(
+            shorten_path(path, Some("/home/u"), 22, "…"),
+            "~/work/clients/…/api"
+        );
+        assert_eq!(shorten_path(path, Some("/home/u"), 14, "…"), "~/work/…/api");
+        assert_eq!(shorten_path(path, Some("/home/u"), 7, "…"), "~/…/api");
+        assert_eq!(shorten_path(path, Some("/home/u"), 6, "…"), "…/api");
+        assert_eq!(shorten_path(path, Some("/home/u"), 12, "..."), "~/.../api");
+    }
+
+    #[test]
+    fn shorten_path_outside_home() {
+        assert_eq!(
+            shorten_path("/srv/repos/api", Some("/home/u"), 40, "…"),
+            "/srv/repos/api"
+        );
+        assert_eq!(
+            shorten_path("/srv/git/team/project/api", Some("/home/u"), 14, "…"),
+            "/srv/git/…/api"
+        );
+    }
+
+    #[test]
+    fn shorten_path_keeps_the_tail_of_a_long_name() {
+        assert_eq!(
+            shorten_path(
+                "/home/u/a-very-long-repository-name",
+                Some("/home/u"),
+                10,
+                "…"
+            ),
+            "…tory-name"
+        );
+    }
+
+    /// A snapshot row for a clean repo on `main`, even with its upstream.
+    fn entry(section: &str, repo: &str) -> SnapshotEntry {
+        SnapshotEntry {
+            section: section.to_string(),
+            repo: repo.to_string(),
+            directory: format!("/src/{repo}"),
+            state: "clean".to_string(),
+            branch: Some("main".to_string()),
+            ahead: Some(0),
+            behind: Some(0),
+            todos: None,
+            head: None,
+            clean_streak: 0,
+        }
+    }
+
+    #[test]
+    fn diff_lists_added_changed_and_removed_repos() {
+        let previous = Snapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at: 1_000,
+            repos: vec![
+                entry("work", "api"),
+                entry("work", "web"),
+                entry("home", "dots"),
+            ],
+        };
+        let current = [
+            SnapshotEntry {
+                state: "dirty".to_string(),
+                branch: Some("fix/login".to_string()),
+                ahead: Some(2),
+                behind: None,
+                ..entry("work", "api")
+            },
+            entry("work", "web"),
+            entry("work", "cli"),
+        ];
+        assert_eq!(
+            render_diff(Some(&previous), &current, 1_000 + 2 * 3600),
+            "Changes since the last run (2 hours ago):\n\
+             ~ work/api: state clean → dirty, branch main → fix/login, ahead 0 → 2, behind 0 → -\n\
+             + work/cli (added)\n\
+             - home/dots (removed)\n"
+        );
+    }
+
+    #[test]
+    fn diff_without_changes_or_a_snapshot() {
+        let previous = Snapshot {
+            version: SNAPSHOT_VERSION,
+            taken_at: 1_000,
+            repos: vec![entry("work", "api")],
+        };
+        assert_eq!(
+            render_diff(Some(&previous), &[entry("work", "api")], 1_030),
+            "No changes since the last run (just now).\n"
+        );
+        assert_eq!(
+            render_diff(None, &[entry("work", "api")], 1_030),
+            "No previous snapshot; saved the current state for next time.\n"
+        );
+    }
+
+    #[test]
+    fn prometheus_exposition() {
+        let mut ok = RepoResult::new("api", "work", "/src/api".to_string());
+        ok.clean = Some(true);
+        ok.ahead = Some(0);
+        ok.fetch_status = FetchStatus::UpToDate;
+        ok.scan_duration = Duration::from_millis(250);
+        let mut odd = RepoResult::new("we\"ird\\name\nx", "side", "/src/odd".to_string());
+        odd.clean = Some(false);
+        odd.ahead = Some(2);
+        odd.fetch_status = FetchStatus::Behind(3);
+        let mut unknown = RepoResult::new("gone", "work", "/src/gone".to_string());
+        unknown.local_error = Some("not a git repository".to_string());
+        unknown.fetch_status = FetchStatus::Skipped;
+
+        let out = render_prometheus(&[ok, odd, unknown], Duration::from_millis(1500));
+        let expected = [
+            "# HELP jiancha_repo_dirty Whether the repo has uncommitted changes (1) or not (0).",
+            "# TYPE jiancha_repo_dirty gauge",
+            "jiancha_repo_dirty{repo=\"api\",section=\"work\"} 0",
+            "jiancha_repo_dirty{repo=\"we\\\"ird\\\\name\\nx\",section=\"side\"} 1",
+            "# HELP jiancha_repo_ahead Number of local commits not pushed to the upstream.",
+            "# TYPE jiancha_repo_ahead gauge",
+            "jiancha_repo_ahead{repo=\"api\",section=\"work\"} 0",
+            "jiancha_repo_ahead{repo=\"we\\\"ird\\\\name\\nx\",section=\"side\"} 2",
+            "# HELP jiancha_repo_behind Number of upstream commits not yet pulled.",
+            "# TYPE jiancha_repo_behind gauge",
+            "jiancha_repo_behind{repo=\"api\",section=\"work\"} 0",
+            "jiancha_repo_behind{repo=\"we\\\"ird\\\\name\\nx\",section=\"side\"} 3",
+            "# HELP jiancha_repo_error Whether the repo could not be inspected or fetched (1) or not (0).",
+            "# TYPE jiancha_repo_error gauge",
+            "jiancha_repo_error{repo=\"api\",section=\"work\"} 0",
+            "jiancha_repo_error{repo=\"we\\\"ird\\\\name\\nx\",section=\"side\"} 0",
+            "jiancha_repo_error{repo=\"gone\",section=\"work\"} 1",
+            "# HELP jiancha_repo_scan_duration_seconds Time taken to scan the repo, fetch included.",
+            "# TYPE jiancha_repo_scan_duration_seconds gauge",
+            "jiancha_repo_scan_duration_seconds{repo=\"api\",section=\"work\"} 0.250",
+            "jiancha_repo_scan_duration_seconds{repo=\"we\\\"ird\\\\name\\nx\",section=\"side\"} 0.000",
+            "jiancha_repo_scan_duration_seconds{repo=\"gone\",section=\"work\"} 0.000",
+            "# HELP jiancha_scan_duration_seconds Time taken to scan all repos.",
+            "# TYPE jiancha_scan_duration_seconds gauge",
+            "jiancha_scan_duration_seconds 1.500",
+        ];
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn glob_match_wildcards_and_classes() {
+        let matches = |pattern: &str, name: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let name: Vec<char> = name.chars().collect();
+            glob_match(&pattern, &name)
+        };
+        assert!(matches("work-*", "work-api"));
+        assert!(matches("work-*", "work-"));
+        assert!(!matches("work-*", "home-api"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("v[0-9]", "v7"));
+        assert!(!matches("v[0-9]", "vx"));
+        assert!(matches("v[!0-9]", "vx"));
+        assert!(matches("v[^0-9]", "vx"));
+        assert!(matches("[]x]", "]"));
+        // An unclosed class is a literal `[`.
+        assert!(matches("a[b", "a[b"));
+        assert!(!matches("a[b", "ab"));
+    }
+
+    #[test]
+    fn expand_config_value_tilde_and_globs() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        assert_eq!(
+            expand_config_value("~/src/api"),
+            vec![("api".to_string(), PathBuf::from(format!("{home}/src/api")))]
+        );
+        // Only a leading `~/` is the home directory.
+        assert_eq!(
+            expand_config_value("~other/api"),
+            vec![("api".to_string(), PathBuf::from("~other/api"))]
+        );
+
+        let root = std::env::temp_dir().join(format!("jiancha-glob-{}", std::process::id()));
+        for dir in ["work-api", "work-cli", "home-dots", ".work-hidden"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(root.join("work-notes.txt"), "").unwrap();
+
+        let found = expand_config_value(&format!("{}/work-*", root.display()));
+        assert_eq!(
+            found,
+            vec![
+                ("work-api".to_string(), root.join("work-api")),
+                ("work-cli".to_string(), root.join("work-cli")),
+            ]
+        );
+        let hidden = expand_config_value(&format!("{}/.work-*", root.display()));
+        assert_eq!(
+            hidden,
+            vec![(".work-hidden".to_string(), root.join(".work-hidden"))]
+        );
+        assert!(expand_config_value(&format!("{}/none-*", root.display())).is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    /// A fixed "now" so commit ages don't depend on the clock.
+    const NOW: u64 = 1_700_000_000;
+
+    /// What a run with no flags and an empty config renders with: plain glyphs and
+    /// the default theme in truecolor.
+    fn render_opts(color: bool) -> RenderOptions<'static> {
+        let theme: &'static Theme = Box::leak(Box::new(
+            Theme::by_name("default", ColorMode::TrueColor).unwrap(),
+        ));
+        let symbols = Symbols::from_config(&SymbolsConfig::default(), theme, &PLAIN_GLYPHS);
+        RenderOptions {
+            symbols: Box::leak(Box::new(symbols.unwrap())),
+            show_fetch_time: false,
+            row_numbers: None,
+            column_order: None,
+            column_aliases: HashMap::new(),
+            wrap: false,
+            truncate: Box::leak(Box::default()),
+            truncate_lead: 8,
+            branch_colors: true,
+            commit_age_days: Some(DEFAULT_COMMIT_AGE_DAYS),
+            min_clean_streak: None,
+            align: Box::leak(Box::default()),
+            padding: (0, 1),
+            glyphs: &PLAIN_GLYPHS,
+            theme,
+            path_sections: HashSet::new(),
+            home: None,
+            show_size: false,
+            size_threshold: None,
+            show_contributors: false,
+            show_lfs: false,
+            show_hooks: false,
+            show_envrc: false,
+            show_contributing: false,
+            show_diffstat: false,
+            show_changes: false,
+            security: false,
+            offline: false,
+            timings: false,
+            deep: false,
+            scan_duration: Duration::ZERO,
+            group_by: GroupBy::Section,
+            highlight_errors: false,
+            date_format: None,
+            number_format: NumberFormat::Plain,
+            now: NOW,
+            icons: false,
+            color,
+            viewport_width: None,
+        }
+    }
+
+    /// A clean repo on `main`, even with its upstream, last committed an hour ago.
+    fn repo(name: &str, section: &str) -> RepoResult {
+        RepoResult {
+            branch: Some("main".to_string()),
+            last_commit: Some("Fix the build".to_string()),
+            last_commit_at: Some(NOW - 3600),
+            clean: Some(true),
+            ahead: Some(0),
+            upstream: Some("origin/main".to_string()),
+            fetch_status: FetchStatus::UpToDate,
+            ..RepoResult::new(name, section, format!("/src/{name}"))
+        }
+    }
+
+    fn one_group(repos: &[RepoResult]) -> IndexMap<String, Vec<usize>> {
+        IndexMap::from([(repos[0].section.clone(), (0..repos.len()).collect())])
+    }
+
+    #[test]
+    fn wrapped_subject_fits_80_columns() {
+        let mut api = repo("api", "work");
+        api.last_commit = Some(
+            "Retry flaky uploads with backoff and log the final failure reason (JIRA-4821007)"
+                .to_string(),
+        );
+        assert_eq!(api.last_commit.as_ref().unwrap().chars().count(), 80);
+        let repos = [api, repo("web", "work")];
+        let mut opts = render_opts(false);
+        opts.wrap = true;
+        opts.viewport_width = Some(78);
+        let out = render_all(&repos, &one_group(&repos), &opts);
+        let expected = "
+═══════════════════════════
+    WORK (2 repos | 2 clean)
+═══════════════════════════
++---------------+--------------+-------+----------------------------+--------+
+|Repo           |Br            |St     |Last                        |R       |
++============================================================================+
+|api            |main          |✓      |Retry flaky uploads with    |✓       |
+|               |              |       |backoff and log the final   |        |
+|               |              |       |failure reason              |        |
+|               |              |       |(JIRA-4821007)              |        |
+|---------------+--------------+-------+----------------------------+--------|
+|web            |main          |✓      |Fix the build               |✓       |
++---------------+--------------+-------+----------------------------+--------+
+
+Scanned 2 repos in 0.00s
+";
+        assert_eq!(out, expected);
+        assert!(out.lines().all(|line| line.chars().count() <= 80));
+    }
+
+    #[test]
+    fn age_bucket_boundaries() {
+        const DAY: u64 = 86_400;
+        let days = DEFAULT_COMMIT_AGE_DAYS;
+        assert_eq!(age_bucket(0, days), 0);
+        for (bucket, &threshold) in days.iter().enumerate() {
+            let at = threshold * DAY;
+            assert_eq!(age_bucket(at - 1, days), bucket, "{threshold} days - 1s");
+            assert_eq!(age_bucket(at, days), bucket + 1, "{threshold} days");
+            assert_eq!(
+                age_bucket(at + 1, days),
+                bucket + 1,
+                "{threshold} days + 1s"
+            );
+        }
+        assert_eq!(age_bucket(u64::MAX, days), 4);
+        assert_eq!(age_bucket(5 * DAY, [10, 20, 30, 40]), 0);
+        // A zero threshold means nothing is ever in the first bucket.
+        assert_eq!(age_bucket(0, [0, 2, 3, 4]), 1);
+    }
+
+    #[test]
+    fn commit_age_gradient_only_with_color() {
+        let repos = [RepoResult {
+            last_commit_at: Some(NOW - 365 * 86_400),
+            ..repo("api", "work")
+        }];
+        let groups = one_group(&repos);
+        // The oldest bucket of the default theme.
+        let faded = "\x1b[38;2;175;95;95m";
+        assert!(render_all(&repos, &groups, &render_opts(true)).contains(faded));
+        assert!(!render_all(&repos, &groups, &render_opts(false)).contains('\x1b'));
+
+        for machine in [
+            render_github(&repos),
+            render_porcelain(&repos),
+            render_prometheus(&repos, Duration::ZERO),
+            render_delimited(&repos, &Column::ALL, true, '\t', &render_opts(true)),
+            render_yaml(&repos),
+        ] {
+            assert!(!machine.contains('\x1b'), "{machine}");
+        }
+    }
+
+    #[test]
+    fn truncate_middle_fits() {
+        let branch = "feature/JIRA-1234-implement-new-billing-flow";
+        assert_eq!(truncate_middle(branch, 21, 8, "…"), "feature/…billing-flow");
+        // Exact fit and shorter strings come back untouched.
+        assert_eq!(truncate_middle("abcdef", 6, 2, "…"), "abcdef");
+        assert_eq!(truncate_middle("abc", 10, 2, "…"), "abc");
+        // One over drops a single character, plus room for the ellipsis.
+        assert_eq!(truncate_middle("abcdefg", 6, 2, "…"), "ab…efg");
+        assert_eq!(truncate_middle("abcdefg", 6, 2, "..."), "ab...g");
+        // The lead never takes more than half.
+        assert_eq!(truncate_middle("abcdefghij", 5, 8, "…"), "ab…ij");
+    }
+
+    #[test]
+    fn truncate_middle_tiny_widths() {
+        assert_eq!(truncate_middle("feature/x", 1, 8, "…"), "f");
+        assert_eq!(truncate_middle("feature/x", 2, 8, "..."), "fe");
+        assert_eq!(truncate_middle("feature/x", 0, 8, "…"), "");
+    }
+
+    #[test]
+    fn truncate_middle_multibyte() {
+        assert_eq!(
+            truncate_middle("日本語のブランチ名です", 6, 2, "…"),
+            "日本…名です"
+        );
+        assert_eq!(truncate_middle("日本語", 3, 2, "…"), "日本語");
+        assert_eq!(truncate_middle("ñandú-émigré", 7, 3, "…"), "ñan…gré");
+    }
+
+    fn rows_of(config: &str) -> (Config, Vec<RowEntry>) {
+        let config: Config = toml::from_str(config).unwrap();
+        let rows = row_entries(&config);
+        (config, rows)
+    }
+
+    #[test]
+    fn resolve_repo_by_name_or_section() {
+        let (config, rows) =
+            rows_of("[work]\napi = \"/src/api\"\ncli = \"/src/cli\"\n[home]\napi = \"/h/api\"\n");
+        let found = |reference| resolve_repo(&config, &rows, reference).map(|row| row.directory);
+        assert_eq!(found("cli").unwrap(), "/src/cli");
+        assert_eq!(found("work/api").unwrap(), "/src/api");
+        assert_eq!(found("home/api").unwrap(), "/h/api");
+        assert_eq!(
+            found("api").unwrap_err().to_string(),
+            "api is in more than one section (work, home); use <section>/api"
+        );
+        assert_eq!(found("web").unwrap_err().to_string(), "No repo named web");
+        assert_eq!(
+            found("work/web").unwrap_err().to_string(),
+            "No repo named work/web"
+        );
+    }
+
+    #[test]
+    fn resolve_repo_slash_in_a_name_outside_any_section() {
+        let (config, rows) = rows_of("[work]\n\"team/api\" = \"/src/api\"\n");
+        let row = resolve_repo(&config, &rows, "team/api").unwrap();
+        assert_eq!(
+            (row.section.as_str(), row.repo.as_str()),
+            ("work", "team/api")
+        );
+    }
+
+    /// The `Args` of a command line that asks for a run.
+    fn run_args(argv: impl IntoIterator<Item = String>) -> Args {
+        match parse_args(argv).unwrap() {
+            Invocation::Run(args) => *args,
+            Invocation::Help | Invocation::Version => panic!("not a run"),
+        }
+    }
+
+    /// Local-only scan options, as `collect_repo_results` uses.
+    fn local_collect_opts(jobs: usize) -> CollectOptions {
+        CollectOptions {
+            check_upstream: true,
+            show_size: false,
+            size_scope: SizeScope::default(),
+            icons: false,
+            base_branch: None,
+            check_force_push: false,
+            show_contributors: false,
+            min_clean_streak: None,
+            diffstat: true,
+            brief: false,
+            offline: true,
+            show_lfs: false,
+            security: false,
+            remote_url: false,
+            jobs,
+            skip_sections: HashSet::new(),
+            max_errors: None,
+            progress: Progress::hidden(),
+            timeout: None,
+            scan_deadline: None,
+            load_envrc: false,
+            info_cache: HashMap::new(),
+            fresh_info: None,
+            todo_counts: None,
+        }
+    }
+
+    #[test]
+    fn parallel_scan_matches_serial() {
+        let root = scratch_dir("parallel");
+        let mut config = String::new();
+        for section in ["work", "drive"] {
+            config.push_str(&format!("[{section}]\n"));
+            for i in 0..6 {
+                let dir = root.join(format!("{section}-{i}"));
+                match i {
+                    // A missing directory and a plain one, as error rows.
+                    4 => {}
+                    5 => std::fs::create_dir_all(&dir).unwrap(),
+                    _ => fixture_repo(&dir),
+                }
+                if i == 1 {
+                    std::fs::write(dir.join("README"), "changed\n").unwrap();
+                }
+                config.push_str(&format!("r{i} = {:?}\n", dir.to_string_lossy()));
+            }
+        }
+        let config: Config = toml::from_str(&config).unwrap();
+
+        let summary = |jobs| -> Vec<_> {
+            collect_all(&config, &local_collect_opts(jobs))
+                .unwrap()
+                .into_iter()
+                .map(|row| {
+                    (
+                        row.section,
+                        row.repo_key,
+                        row.branch,
+                        row.clean,
+                        row.last_commit,
+                        row.local_error,
+                    )
+                })
+                .collect()
+        };
+        let serial = summary(1);
+        assert_eq!(serial.len(), 12);
+        assert_eq!(serial[0].2.as_deref(), Some("main"));
+        assert_eq!(serial[1].3, Some(false));
+        assert_eq!(serial[4].5.as_deref(), Some("Not a valid directory"));
+        assert_eq!(serial[5].5.as_deref(), Some("Not a Git repository"));
+        assert_eq!(summary(4), serial);
+        assert_eq!(summary(16), serial);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parallel_scan_is_faster_than_serial() {
+        const REPOS: usize = 4;
+        let root = scratch_dir("speedup");
+        let mut config = String::from("[work]\n");
+        for i in 0..REPOS {
+            let dir = root.join(format!("r{i}"));
+            fixture_repo(&dir);
+            // Every index refresh runs the fsmonitor hook first. It sleeps, then
+            // fails so git falls back to a full scan; that makes each repo slow
+            // without touching PATH, which other tests running in parallel share.
+            fixture_git(&dir, &["config", "core.fsmonitor", "sleep 0.3; false"]);
+            config.push_str(&format!("r{i} = {:?}\n", dir.to_string_lossy()));
+        }
+        let config: Config = toml::from_str(&config).unwrap();
+
+        let timed = |jobs| {
+            let started = Instant::now();
+            let rows = collect_all(&config, &local_collect_opts(jobs)).unwrap();
+            assert!(rows.iter().all(|row| row.local_error.is_none()));
+            started.elapsed()
+        };
+        let serial = timed(1);
+        let parallel = timed(REPOS);
+        // Ideally REPOS times faster; half that leaves room for a loaded machine.
+        assert!(
+            parallel * 2 < serial,
+            "{REPOS} jobs took {parallel:?}, 1 job took {serial:?}"
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn section_header_attribute_combinations() {
+        let orange = Color::Rgb {
+            r: 255,
+            g: 140,
+            b: 0,
+        };
+        let style = |bold, underline, italic| SectionHeaderStyle {
+            bold,
+            underline,
+            italic,
+            fg: orange,
+            bg: Color::Reset,
+        };
+        let cases = [
+            ((false, false, false), "\x1b[38;2;255;140;0m"),
+            ((true, false, false), "\x1b[1;38;2;255;140;0m"),
+            ((false, true, false), "\x1b[4;38;2;255;140;0m"),
+            ((false, false, true), "\x1b[3;38;2;255;140;0m"),
+            ((true, true, false), "\x1b[1;4;38;2;255;140;0m"),
+            ((true, false, true), "\x1b[1;3;38;2;255;140;0m"),
+            ((false, true, true), "\x1b[3;4;38;2;255;140;0m"),
+            ((true, true, true), "\x1b[1;3;4;38;2;255;140;0m"),
+        ];
+        for ((bold, underline, italic), expected) in cases {
+            assert_eq!(
+                section_header_ansi(&style(bold, underline, italic)),
+                expected,
+                "bold={bold} underline={underline} italic={italic}"
+            );
+        }
+    }
+
+    #[test]
+    fn section_header_colors() {
+        let plain = SectionHeaderStyle {
+            bold: false,
+            underline: false,
+            italic: false,
+            fg: Color::Reset,
+            bg: Color::Reset,
+        };
+        assert_eq!(section_header_ansi(&plain), "");
+        let attributes_only = SectionHeaderStyle {
+            underline: true,
+            ..plain
+        };
+        assert_eq!(section_header_ansi(&attributes_only), "\x1b[4m");
+        let background = SectionHeaderStyle {
+            bg: Color::Rgb {
+                r: 28,
+                g: 28,
+                b: 28,
+            },
+            ..SectionHeaderStyle::bold(Color::AnsiValue(208))
+        };
+        assert_eq!(
+            section_header_ansi(&background),
+            "\x1b[1;38;5;208;48;2;28;28;28m"
+        );
+    }
+
+    #[test]
+    fn display_path_under_home() {
+        assert_eq!(
+            display_path("/home/user/src/api", "/home/user"),
+            "~/src/api"
+        );
+        assert_eq!(display_path("/home/user", "/home/user"), "~");
+        assert_eq!(display_path("/home/user/src", "/home/user/"), "~/src");
+    }
+
+    #[test]
+    fn display_path_outside_home() {
+        assert_eq!(display_path("/srv/git/api", "/home/user"), "/srv/git/api");
+        assert_eq!(display_path("relative/api", "/home/user"), "relative/api");
+        assert_eq!(display_path("/home/user/api", ""), "/home/user/api");
+    }
+
+    #[test]
+    fn display_path_needs_a_whole_component() {
+        assert_eq!(display_path("/home/userX", "/home/user"), "/home/userX");
+        assert_eq!(
+            display_path("/home/userX/api", "/home/user"),
+            "/home/userX/api"
+        );
+    }
+
+    /// Puts an executable `git` script with `body` in a fresh directory, and
+    /// returns a `PATH` that finds it first.
+    #[cfg(unix)]
+    fn fake_git(name: &str, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch_dir(name);
+        let shim = dir.join("git");
+        std::fs::write(&shim, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+        format!(
+            "{}:{}",
+            dir.display(),
+            std::env::var("PATH").unwrap_or_default()
+        )
+    }
+
+    /// `run_bounded` on `git` from `path` under `deadline`. Another test's fork
+    /// can briefly hold the just-written shim open (ETXTBSY), so that's retried.
+    #[cfg(unix)]
+    fn run_fake_git(path: &str, deadline: Option<Instant>) -> (std::io::Result<Output>, bool) {
+        loop {
+            let git_options = GitOptions {
+                deadline,
+                ..GitOptions::default()
+            };
+            let run = with_git_options(git_options, || {
+                run_bounded(Command::new("git").arg("status").env("PATH", path))
+            });
+            match &run.0 {
+                Err(e) if e.raw_os_error() == Some(26) => thread::sleep(Duration::from_millis(10)),
+                _ => return run,
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_bounded_gives_up_on_a_slow_git() {
+        let slow = fake_git("slow-git", "echo started\nexec sleep 30");
+        let started = Instant::now();
+        let (result, timed_out) = run_fake_git(&slow, Some(started + Duration::from_millis(300)));
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(timed_out);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // A deadline that has already passed doesn't start anything.
+        let (result, timed_out) = run_fake_git(&slow, Some(Instant::now()));
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(timed_out);
+
+        let fast = fake_git("fast-git", "echo \"$@\"\necho oops >&2\nexit 3");
+        let deadline = Instant::now() + Duration::from_secs(30);
+        for deadline in [Some(deadline), None] {
+            let (result, timed_out) = run_fake_git(&fast, deadline);
+            let output = result.unwrap();
+            assert_eq!(output.stdout, b"status\n");
+            assert_eq!(output.stderr, b"oops\n");
+            assert_eq!(output.status.code(), Some(3));
+            assert!(!timed_out);
+        }
+    }
+
+    /// Every command `run_bounded` starts, program first, so tests can check which
+    /// git calls a code path makes.
+    static COMMANDS: Mutex<Vec<Vec<String>>> = Mutex::new(Vec::new());
+
+    pub(super) fn record_command(cmd: &Command) {
+        let words = std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|word| word.to_string_lossy().into_owned())
+            .collect();
+        COMMANDS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(words);
+    }
+
+    /// Takes the recorded commands that mention `dir`, leaving other tests' alone.
+    fn take_commands(dir: &str) -> Vec<Vec<String>> {
+        let mut commands = COMMANDS.lock().unwrap_or_else(PoisonError::into_inner);
+        let (mine, others) = std::mem::take(&mut *commands)
+            .into_iter()
+            .partition(|words: &Vec<String>| words.iter().any(|word| word.contains(dir)));
+        *commands = others;
+        mine
+    }
+
+    #[test]
+    fn cached_repo_skips_local_git_reads() {
+        let root = scratch_dir("info-cache");
+        let dir = root.join("api");
+        fixture_repo(&dir);
+        // On a branch off main, so the base branch and the lead on it are looked up.
+        fixture_git(&dir, &["checkout", "-q", "-b", "feature"]);
+        fixture_git(&dir, &["commit", "-q", "--allow-empty", "-m", "Start"]);
+        // git status keeps rewriting an index written in the same second as the
+        // files it lists, which would invalidate the entry straight away; backdate
+        // the file and let git record that once.
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("README"))
+            .and_then(|file| file.set_modified(SystemTime::now() - Duration::from_secs(10)))
+            .unwrap();
+        fixture_git(&dir, &["status", "--porcelain"]);
+        let dir = dir.canonicalize().unwrap().to_string_lossy().into_owned();
+        let runs = |words: &[Vec<String>], subcommand: &str| {
+            words
+                .iter()
+                .filter(|words| words.iter().any(|word| word == subcommand))
+                .count()
+        };
+
+        let mut opts = local_collect_opts(1);
+        opts.fresh_info = Some(Mutex::new(HashMap::new()));
+        take_commands(&dir);
+        let uncached = collect_repo("work", "api", &dir, &opts);
+        let uncached_commands = take_commands(&dir);
+        assert!(!uncached.info_cached);
+        assert!(runs(&uncached_commands, "status") > 0);
+        assert!(runs(&uncached_commands, "log") > 0);
+        assert!(runs(&uncached_commands, "symbolic-ref") > 0);
+        assert_eq!(uncached.default_branch.as_deref(), Some("main"));
+        assert_eq!(uncached.ahead_of_base, Some(1));
+
+        opts.info_cache = opts.fresh_info.take().unwrap().into_inner().unwrap();
+        assert!(opts.info_cache.contains_key(&dir));
+        let cached = collect_repo("work", "api", &dir, &opts);
+        let cached_commands = take_commands(&dir);
+        assert!(cached.info_cached);
+        assert_eq!(cached_commands, Vec::<Vec<String>>::new());
+        assert_eq!(
+            (cached.branch, cached.clean, cached.last_commit),
+            (uncached.branch, uncached.clean, uncached.last_commit)
+        );
+        assert_eq!(
+            (cached.default_branch, cached.ahead_of_base),
+            (uncached.default_branch, uncached.ahead_of_base)
+        );
+
+        // The base branch is part of what was cached, so another one reads again.
+        opts.base_branch = Some("feature".to_string());
+        assert!(!collect_repo("work", "api", &dir, &opts).info_cached);
+        take_commands(&dir);
+        opts.base_branch = None;
+
+        // A new commit moves HEAD's ref, so the entry no longer applies.
+        std::fs::write(Path::new(&dir).join("README"), "changed\n").unwrap();
+        fixture_git(Path::new(&dir), &["commit", "-q", "-am", "Change"]);
+        let stale = collect_repo("work", "api", &dir, &opts);
+        assert!(!stale.info_cached);
+        assert_eq!(stale.last_commit.as_deref(), Some("Change"));
+        assert!(runs(&take_commands(&dir), "status") > 0);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn diffstat_is_only_read_when_asked_for() {
+        let root = scratch_dir("diffstat");
+        let dir = root.join("api");
+        fixture_repo(&dir);
+        std::fs::write(dir.join("README"), "changed\nand more\n").unwrap();
+        let dir = dir.canonicalize().unwrap().to_string_lossy().into_owned();
+        let scan = |diffstat| {
+            let opts = CollectOptions {
+                diffstat,
+                ..local_collect_opts(1)
+            };
+            take_commands(&dir);
+            let row = collect_repo("work", "api", &dir, &opts);
+            let diffs = take_commands(&dir)
+                .iter()
+                .filter(|words| words.iter().any(|word| word == "diff"))
+                .count();
+            (row.diffstat, diffs)
+        };
+
+        let (stat, diffs) = scan(true);
+        assert_eq!(diffs, 2);
+        assert_eq!(stat.map(|stat| stat.files), Some(1));
+        assert_eq!(scan(false), (None, 0));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn prompt_scan_skips_commit_and_base_lookups() {
+        let root = scratch_dir("brief");
+        let dir = root.join("api");
+        fixture_repo(&dir);
+        fixture_git(&dir, &["checkout", "-q", "-b", "feature"]);
+        fixture_git(&dir, &["commit", "-q", "--allow-empty", "-m", "Start"]);
+        let dir = dir.canonicalize().unwrap().to_string_lossy().into_owned();
+        let scan = |brief| {
+            let opts = CollectOptions {
+                brief,
+                ..local_collect_opts(1)
+            };
+            take_commands(&dir);
+            let row = collect_repo("work", "api", &dir, &opts);
+            let subcommands: Vec<String> = take_commands(&dir)
+                .into_iter()
+                .map(|words| words[3].clone())
+                .collect();
+            (row, subcommands)
+        };
+
+        let (full, full_subcommands) = scan(false);
+        assert!(full_subcommands.iter().any(|s| s == "log"));
+        assert!(full_subcommands.iter().any(|s| s == "symbolic-ref"));
+        assert_eq!(full.ahead_of_base, Some(1));
+
+        let (brief, brief_subcommands) = scan(true);
+        for skipped in ["log", "for-each-ref", "symbolic-ref"] {
+            assert!(
+                !brief_subcommands.iter().any(|s| s == skipped),
+                "{skipped} in {brief_subcommands:?}"
+            );
+        }
+        assert!(brief_subcommands.len() < full_subcommands.len());
+        assert_eq!(brief.ahead_of_base, None);
+        // Everything --oneline reads is still there.
+        assert_eq!(repo_state(&brief), repo_state(&full));
+        assert_eq!(
+            (brief.branch, brief.clean, brief.ahead, brief.upstream),
+            (full.branch, full.clean, full.ahead, full.upstream)
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn streak_needs_recent_commits_on_the_base_branch() {
+        let root = scratch_dir("recent-on-base");
+        let dir = root.join("api");
+        fixture_repo(&dir);
+        fixture_git(&dir, &["commit", "-q", "--allow-empty", "-m", "Second"]);
+        let path = dir.canonicalize().unwrap().to_string_lossy().into_owned();
+        let scan = || {
+            let opts = CollectOptions {
+                min_clean_streak: Some(2),
+                ..local_collect_opts(1)
+            };
+            collect_repo("work", "api", &path, &opts).recent_on_base
+        };
+        assert!(recent_on_base(&path, "main", 2));
+        assert!(scan());
+
+        fixture_git(&dir, &["checkout", "-q", "-b", "feature"]);
+        fixture_git(&dir, &["commit", "-q", "--allow-empty", "-m", "Elsewhere"]);
+        fixture_git(&dir, &["checkout", "-q", "main"]);
+        assert!(!recent_on_base(&path, "main", 2));
+        assert!(!scan());
+        assert!(!collect_repo("work", "api", &path, &local_collect_opts(1)).recent_on_base);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn branch_status_from_porcelain_v2() {
+        let root = scratch_dir("branch-status");
+        let origin = root.join("origin");
+        fixture_repo(&origin);
+        fixture_git(&root, &["clone", "-q", "origin", "api"]);
+        let api = root.join("api");
+        for message in ["One", "Two"] {
+            fixture_git(&api, &["commit", "-q", "--allow-empty", "-m", message]);
+        }
+        let dir = api.to_string_lossy().into_owned();
+
+        let status = branch_status(&dir).unwrap();
+        assert_eq!(status.branch, "main");
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!(status.ahead, Some(2));
+        assert!(status.clean && !status.conflicted);
+
+        std::fs::write(api.join("new.txt"), "untracked\n").unwrap();
+        assert!(!branch_status(&dir).unwrap().clean);
+        std::fs::remove_file(api.join("new.txt")).unwrap();
+
+        // An upstream that's gone is named by git but has no counts.
+        fixture_git(&api, &["update-ref", "-d", "refs/remotes/origin/main"]);
+        let status = branch_status(&dir).unwrap();
+        assert_eq!((status.upstream, status.ahead), (None, None));
+
+        fixture_git(&api, &["checkout", "-q", "--detach"]);
+        assert_eq!(branch_status(&dir).unwrap().branch, "HEAD");
+
+        let empty = root.join("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+        fixture_git(&empty, &["init", "-q"]);
+        assert!(branch_status(&empty.to_string_lossy()).is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn porcelain_lists_repos_that_need_attention() {
+        let dirty = RepoResult {
+            clean: Some(false),
+            ..repo("dirty", "work")
+        };
+        let conflict = RepoResult {
+            clean: Some(false),
+            conflicted: true,
+            ..repo("conflict", "work")
+        };
+        let unpushed = RepoResult {
+            ahead: Some(2),
+            ..repo("unpushed", "work")
+        };
+        let no_upstream = RepoResult {
+            upstream: None,
+            ahead: None,
+            ..repo("local", "work")
+        };
+        let unreadable = RepoResult {
+            directory: std::env::temp_dir().to_string_lossy().into_owned(),
+            ..RepoResult::new("broken", "home", String::new()).with_error("Not a Git repository")
+        };
+        let missing = RepoResult::new("gone", "home", "/nonexistent/gone".to_string())
+            .with_error("Not a valid directory");
+        let repos = [
+            repo("clean", "work"),
+            dirty,
+            conflict,
+            unpushed,
+            no_upstream,
+            unreadable,
+            missing,
+        ];
+        assert_eq!(
+            render_porcelain(&repos),
+            " M work/dirty\n U work/conflict\n ↑ work/unpushed\n E home/broken\n ? home/gone\n"
+        );
+    }
+
+    #[test]
+    fn bisect_state_counts_marks_and_steps() {
+        let root = scratch_dir("bisect");
+        let dir = root.join("api");
+        fixture_repo(&dir);
+        for i in 0..8 {
+            fixture_git(
+                &dir,
+                &["commit", "-q", "--allow-empty", "-m", &format!("c{i}")],
+            );
+        }
+        let path = dir.to_string_lossy().into_owned();
+        assert_eq!(bisect_state(&path), None);
+
+        fixture_git(&dir, &["bisect", "start", "HEAD", "HEAD~8"]);
+        let state = bisect_state(&path).unwrap();
+        assert_eq!((state.good, state.bad, state.steps_done), (1, 1, 0));
+        // Eight candidates take three steps, the checked-out one included.
+        assert_eq!(state.steps_estimated, 3);
+
+        fixture_git(&dir, &["bisect", "good"]);
+        fixture_git(&dir, &["bisect", "skip"]);
+        let state = bisect_state(&path).unwrap();
+        assert_eq!((state.good, state.bad, state.steps_done), (2, 1, 2));
+        assert!(state.steps_estimated > state.steps_done);
+
+        fixture_git(&dir, &["bisect", "reset"]);
+        assert_eq!(bisect_state(&path), None);
+
+        // Custom terms are read from BISECT_TERMS.
+        fixture_git(
+            &dir,
+            &[
+                "bisect",
+                "start",
+                "--term-old=fast",
+                "--term-new=slow",
+                "HEAD",
+                "HEAD~8",
+            ],
+        );
+        fixture_git(&dir, &["bisect", "slow"]);
+        let state = bisect_state(&path).unwrap();
+        assert_eq!((state.good, state.bad, state.steps_done), (1, 2, 1));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Checks the generated scripts with the shells' own syntax check, for the
+    /// shells that are installed.
+    #[test]
+    fn completion_scripts_parse() {
+        let checks = [
+            (Shell::Bash, "bash", ["-n"]),
+            (Shell::Zsh, "zsh", ["-n"]),
+            (Shell::Fish, "fish", ["--no-execute"]),
+        ];
+        for (shell, program, args) in checks {
+            let path =
+                std::env::temp_dir().join(format!("jiancha-{}-{program}", std::process::id()));
+            std::fs::write(&path, completion_script(shell)).unwrap();
+            let status = Command::new(program).args(args).arg(&path).status();
+            std::fs::remove_file(&path).unwrap();
+            match status {
+                Ok(status) => assert!(status.success(), "{program} rejects its completion script"),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => panic!("running {program}: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_branch_status_headers_and_entries() {
+        let status = parse_branch_status(
+            "# branch.oid 1f2e3d\n# branch.head main\n# branch.upstream origin/main\n\
+             # branch.ab +2 -1\n1 .M N... 100644 100644 100644 aa bb README\n\
+             u UU N... 100644 100644 100644 100644 aa bb cc lib.rs\n",
+        )
+        .unwrap();
+        assert_eq!(status.branch, "main");
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!(status.ahead, Some(2));
+        assert!(!status.clean && status.conflicted);
+
+        let detached = parse_branch_status("# branch.oid 1f2e3d\n# branch.head (detached)\n");
+        let detached = detached.unwrap();
+        assert_eq!(detached.branch, "HEAD");
+        assert!(detached.clean && !detached.conflicted);
+        assert_eq!((detached.ahead, detached.upstream), (None, None));
+
+        // An untracked file alone makes the tree dirty but not conflicted.
+        let untracked =
+            parse_branch_status("# branch.oid 1f2e3d\n# branch.head main\n? notes.txt\n").unwrap();
+        assert!(!untracked.clean && !untracked.conflicted);
+    }
+
+    #[test]
+    fn parse_branch_status_rejects_what_it_cant_use() {
+        // No commits yet.
+        assert!(parse_branch_status("# branch.oid (initial)\n# branch.head main\n").is_none());
+        // No branch.head line at all.
+        assert!(parse_branch_status("# branch.oid 1f2e3d\n").is_none());
+        // A gone upstream is named but has no counts, so it's dropped.
+        let gone = parse_branch_status(
+            "# branch.oid 1f2e3d\n# branch.head main\n# branch.upstream origin/main\n",
+        )
+        .unwrap();
+        assert_eq!((gone.upstream, gone.ahead), (None, None));
+        // Counts git never writes are a parse failure, not a zero.
+        assert!(parse_branch_status(
+            "# branch.oid 1f2e3d\n# branch.head main\n# branch.upstream origin/main\n\
+             # branch.ab 2 -1\n"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn parse_head_log_fields() {
+        assert_eq!(
+            parse_head_log("1700000000 aaa\0Fix the build"),
+            (Some(1_700_000_000), false, "Fix the build")
+        );
+        assert_eq!(
+            parse_head_log("1700000000 aaa bbb\0Merge branch 'x'"),
+            (Some(1_700_000_000), true, "Merge branch 'x'")
+        );
+        // A root commit has no parents; a subject may contain anything but NUL.
+        assert_eq!(
+            parse_head_log("1700000000 \0a: b \"c\""),
+            (Some(1_700_000_000), false, "a: b \"c\"")
+        );
+        assert_eq!(parse_head_log("garbage"), (None, false, ""));
+    }
+
+    #[test]
+    fn parse_ref_update_lines() {
+        let updated = |name: &str, old: &str, new: &str, forced| RefUpdate::Updated {
+            name: name.to_string(),
+            old: old.to_string(),
+            new: new.to_string(),
+            forced,
+        };
+        let cases = [
+            (
+                " * [new branch]      feature    -> origin/feature",
+                Some(RefUpdate::New("origin/feature".to_string())),
+            ),
+            (
+                " * [new tag]         v1.0       -> v1.0",
+                Some(RefUpdate::New("v1.0".to_string())),
+            ),
+            (
+                "   1a2b3c4..5d6e7f8  main       -> origin/main",
+                Some(updated("origin/main", "1a2b3c4", "5d6e7f8", false)),
+            ),
+            (
+                " + 1a2b3c4...5d6e7f8 wip        -> origin/wip  (forced update)",
+                Some(updated("origin/wip", "1a2b3c4", "5d6e7f8", true)),
+            ),
+            (
+                " - [deleted]         (none)     -> origin/old",
+                Some(RefUpdate::Deleted("origin/old".to_string())),
+            ),
+            (
+                " ! [rejected]        main       -> main  (non-fast-forward)",
+                None,
+            ),
+            (" = [up to date]      main       -> origin/main", None),
+            ("From github.com:acme/api", None),
+            ("", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(parse_ref_update(line), expected, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn remote_web_urls() {
+        let page = |remote| ssh_to_https_url(remote);
+        assert_eq!(
+            page("git@github.com:o/r.git").as_deref(),
+            Some("https://github.com/o/r")
+        );
+        assert_eq!(
+            page("ssh://git@github.com:22/o/r").as_deref(),
+            Some("https://github.com/o/r")
+        );
+        assert_eq!(
+            page("https://github.com/o/r.git").as_deref(),
+            Some("https://github.com/o/r")
+        );
+        assert_eq!(
+            page("git@git.example.org:team/o/r.git").as_deref(),
+            Some("https://git.example.org/team/o/r")
+        );
+        assert_eq!(page("/srv/git/r.git"), None);
+        assert_eq!(page("file:///srv/git/r.git"), None);
+        assert_eq!(page("C:/src/r"), None);
+
+        let config: Config = toml::from_str(
+            "git_hosts = [{ domain = \"git.corp.example\", https_base = \"https://code.corp.example/git/\" }]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config
+                .browser_url("git@GIT.corp.example:o/r.git")
+                .as_deref(),
+            Some("https://code.corp.example/git/o/r")
+        );
+        assert_eq!(
+            config.browser_url("git@github.com:o/r.git").as_deref(),
+            Some("https://github.com/o/r")
+        );
+    }
+
+    #[test]
+    fn push_plan_reasons() {
+        let root = scratch_dir("push-plan");
+        let origin = root.join("origin");
+        fixture_repo(&origin);
+        fixture_git(&root, &["clone", "-q", "origin", "api"]);
+        let api = root.join("api");
+        fixture_git(&api, &["commit", "-q", "--allow-empty", "-m", "Unpushed"]);
+        let api_repo = || RepoResult {
+            directory: api.to_string_lossy().into_owned(),
+            ..repo("api", "work")
+        };
+        let result = api_repo();
+
+        assert_eq!(push_plan(&result, false), Ok(Some(1)));
+        let failed = RepoResult {
+            local_error: Some("git status failed".to_string()),
+            ..api_repo()
+        };
+        assert_eq!(push_plan(&failed, false), Err("error"));
+
+        std::fs::write(api.join("new.txt"), "untracked\n").unwrap();
+        assert_eq!(push_plan(&result, false), Err("dirty"));
+        std::fs::remove_file(api.join("new.txt")).unwrap();
+
+        // A new branch is only pushed when asked to set its upstream.
+        let local = RepoResult {
+            upstream: None,
+            ahead: None,
+            ..api_repo()
+        };
+        assert_eq!(push_plan(&local, false), Err("no upstream"));
+        assert_eq!(push_plan(&local, true), Ok(None));
+
+        fixture_git(&api, &["checkout", "-q", "--detach"]);
+        assert_eq!(push_plan(&result, true), Err("detached"));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn only_table_and_github_are_for_people() {
+        let machine = [
+            OutputFormat::Prometheus,
+            OutputFormat::Oneline { counts: true },
+            OutputFormat::Porcelain,
+            OutputFormat::Compact,
+            OutputFormat::Tsv,
+            OutputFormat::Yaml,
+        ];
+        assert!(machine.iter().all(|format| format.is_machine()));
+        assert!(!OutputFormat::Table.is_machine());
+        assert!(!OutputFormat::Github.is_machine());
+    }
+
+    #[test]
+    fn delimited_escapes_separators_and_line_breaks() {
+        let repos = [
+            RepoResult {
+                last_commit: Some("Split\tthe\\path\r\nout".to_string()),
+                ..repo("api", "work")
+            },
+            RepoResult {
+                clean: Some(false),
+                ..repo("web", "work")
+            },
+        ];
+        let columns = [Column::Repository, Column::Status, Column::LastCommit];
+        let tsv = render_delimited(&repos, &columns, true, '\t', &render_opts(true));
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines.len(), 3, "{tsv}");
+        assert_eq!(lines[0].split('\t').count(), 3);
+        assert!(
+            lines[1].ends_with("\tSplit\\tthe\\\\path\\r\\nout"),
+            "{tsv}"
+        );
+        assert!(lines[2].starts_with("web\tdirty\t"), "{tsv}");
+        assert!(!tsv.contains('\x1b'));
+
+        let without = render_delimited(&repos, &columns, false, '\t', &render_opts(false));
+        assert_eq!(without.lines().collect::<Vec<_>>(), lines[1..]);
+    }
+
+    #[test]
+    fn browse_links_per_forge() {
+        let branch = BrowseTarget::Branch("fix/a#b".to_string());
+        let commit = BrowseTarget::Commit("abc123".to_string());
+        assert_eq!(
+            browse_url("https://github.com/o/r", &branch),
+            "https://github.com/o/r/tree/fix/a%23b"
+        );
+        assert_eq!(
+            browse_url("https://github.com/o/r", &commit),
+            "https://github.com/o/r/commit/abc123"
+        );
+        assert_eq!(
+            browse_url("https://gitlab.com/o/r", &commit),
+            "https://gitlab.com/o/r/-/commit/abc123"
+        );
+        assert_eq!(
+            browse_url("https://bitbucket.org/o/r/", &branch),
+            "https://bitbucket.org/o/r/src/fix/a%23b"
+        );
+        assert_eq!(
+            browse_url("https://git.example.org/o/r", &branch),
+            "https://git.example.org/o/r"
+        );
+        assert_eq!(
+            browse_url("https://git.example.org/o/r", &BrowseTarget::Repo),
+            "https://git.example.org/o/r"
+        );
+    }
+
+    #[test]
+    fn resolve_repo_by_prefix_then_substring() {
+        let (config, rows) = rows_of(
+            "[work]\napi = \"/src/api\"\napi-gateway = \"/src/gw\"\nweb-frontend = \"/src/web\"\n\
+             admin-frontend = \"/src/admin\"\ntools-web = \"/src/tools\"\n",
+        );
+        let found = |reference| resolve_repo(&config, &rows, reference).map(|row| row.directory);
+        // An exact name wins over the longer names it starts.
+        assert_eq!(found("api").unwrap(), "/src/api");
+        assert_eq!(found("api-g").unwrap(), "/src/gw");
+        // A unique prefix is taken before looking at substrings.
+        assert_eq!(found("WEB").unwrap(), "/src/web");
+        assert_eq!(found("gate").unwrap(), "/src/gw");
+
+        let ambiguous = found("frontend").unwrap_err();
+        assert!(ambiguous.downcast_ref::<AmbiguousRepo>().is_some());
+        assert_eq!(
+            ambiguous.to_string(),
+            "frontend matches more than one repo: work/web-frontend, work/admin-frontend"
+        );
+        assert_eq!(
+            found("mobile").unwrap_err().to_string(),
+            "No repo named mobile"
+        );
+    }
+
+    #[test]
+    fn man_page_sections() {
+        let page = man_page();
+        for section in [
+            "NAME",
+            "SYNOPSIS",
+            "OPTIONS",
+            "CONFIGURATION",
+            "EXIT STATUS",
+        ] {
+            assert!(
+                page.contains(&format!("\n.SH {section}\n")),
+                "no {section} section"
+            );
+        }
+        for flag in FLAGS {
+            let names = roff_escape(&flag.spellings().join(", "));
+            assert!(
+                page.contains(&format!(".B {names}")),
+                "{} missing",
+                flag.name
+            );
+        }
+    }
+
+    /// A value `parse_args` accepts after `flag`, if it takes one.
+    fn sample_value(flag: &Flag) -> Option<String> {
+        match (flag.name, &flag.completion) {
+            ("watch" | "oneline", _) | (_, Completion::Nothing) => None,
+            ("column-alias", _) => Some("branch=Br".to_string()),
+            ("size-threshold", _) => Some("1G".to_string()),
+            (_, Completion::Words(words)) => words.split(' ').next().map(str::to_string),
+            _ => Some("2".to_string()),
+        }
+    }
+
+    #[test]
+    fn every_flag_and_alias_parses_alike() {
+        for flag in FLAGS {
+            let parsed: Vec<String> = flag
+                .spellings()
+                .into_iter()
+                .map(|spelling| {
+                    let argv = std::iter::once(spelling.clone()).chain(sample_value(flag));
+                    match parse_args(argv) {
+                        Ok(Invocation::Run(args)) => format!("{args:?}"),
+                        Ok(Invocation::Help) => "help".to_string(),
+                        Ok(Invocation::Version) => "version".to_string(),
+                        // Subcommand flags given without their subcommand may be refused,
+                        // but never as unknown.
+                        Err(e) => {
+                            let e = e.to_string();
+                            assert!(!e.starts_with("Unknown argument"), "{spelling}: {e}");
+                            e
+                        }
+                    }
+                })
+                .collect();
+            assert!(
+                parsed.windows(2).all(|pair| pair[0] == pair[1]),
+                "{}: {parsed:?}",
+                flag.name
+            );
+        }
+        let error = parse_args(["--no-such-flag".to_string()]).err().unwrap();
+        assert!(error.to_string().starts_with("Unknown argument"));
+    }
+
+    #[test]
+    fn usage_and_completions_list_every_flag() {
+        let usage = usage();
+        let scripts: Vec<String> = [Shell::Bash, Shell::Zsh, Shell::Fish]
+            .into_iter()
+            .map(completion_script)
+            .collect();
+        for flag in FLAGS {
+            if flag.subcommand {
+                let long = format!("--{}", flag.name);
+                assert!(
+                    SUBCOMMAND_USAGE.iter().any(|line| line.contains(&long)),
+                    "{long} isn't on any subcommand's line"
+                );
+            } else {
+                assert!(usage.contains(&flag.usage()), "{} missing", flag.name);
+            }
+            for script in &scripts[..2] {
+                assert!(script.contains(&format!("--{}", flag.name)));
+            }
+            assert!(scripts[2].contains(&format!("-l {}", flag.name)));
+        }
+        assert!(usage.contains("[-j|--jobs <n>]") && usage.contains("[-h|--help]"));
+        assert!(usage.lines().all(|line| line.len() <= 100), "{usage}");
+    }
+
+    #[test]
+    fn clean_streak_carries_over_and_resets() {
+        let clean = repo("api", "work");
+        assert_eq!(compute_streak(None, &clean, true), 1);
+        let previous = SnapshotEntry {
+            clean_streak: 3,
+            ..entry("work", "api")
+        };
+        assert_eq!(compute_streak(Some(&previous), &clean, true), 4);
+        assert_eq!(compute_streak(Some(&previous), &clean, false), 3);
+
+        let dirty = RepoResult {
+            clean: Some(false),
+            ..repo("api", "work")
+        };
+        assert_eq!(compute_streak(Some(&previous), &dirty, false), 0);
+        let broken = RepoResult {
+            local_error: Some("Not a Git repository".to_string()),
+            ..repo("api", "work")
+        };
+        assert_eq!(compute_streak(Some(&previous), &broken, true), 0);
+    }
+
+    fn stat(files: u32, insertions: u32, deletions: u32) -> DiffStat {
+        DiffStat {
+            files,
+            insertions,
+            deletions,
+        }
+    }
+    #[test]
+    fn shortstat_segments() {
+        let cases = [
+            ("", stat(0, 0, 0)),
+            (" 1 file changed", stat(1, 0, 0)),
+            (" 3 files changed", stat(3, 0, 0)),
+            (" 1 file changed, 1 insertion(+)", stat(1, 1, 0)),
+            (" 2 files changed, 10 insertions(+)", stat(2, 10, 0)),
+            (" 1 file changed, 1 deletion(-)", stat(1, 0, 1)),
+            (" 4 files changed, 7 deletions(-)", stat(4, 0, 7)),
+            (
+                " 1 file changed, 1 insertion(+), 1 deletion(-)",
+                stat(1, 1, 1),
+            ),
+            (
+                " 3 files changed, 10 insertions(+), 2 deletions(-)\n",
+                stat(3, 10, 2),
+            ),
+        ];
+        for (out, expected) in cases {
+            assert_eq!(parse_shortstat(out), expected, "{out:?}");
+        }
+    }
+    #[test]
+    fn shortstat_ignores_unknown_segments() {
+        assert_eq!(
+            parse_shortstat("garbage, 5 insertions(+), x deletions(-)"),
+            stat(0, 5, 0)
+        );
+    }
+    #[test]
+    fn diffstat_display() {
+        assert_eq!(stat(1, 3, 0).to_string(), "+3 \u{2212}0 across 1 file");
+        assert_eq!(
+            stat(7, 120, 45).to_string(),
+            "+120 \u{2212}45 across 7 files"
+        );
+    }
+
+    #[test]
+    fn single_repo_exit_statuses() {
+        let clean = repo("api", "work");
+        let broken = RepoResult {
+            local_error: Some("Not a Git repository".to_string()),
+            ..repo("api", "work")
+        };
+        let dirty = RepoResult {
+            clean: Some(false),
+            ..repo("api", "work")
+        };
+        let ahead = RepoResult {
+            ahead: Some(1),
+            ..repo("api", "work")
+        };
+        let behind = RepoResult {
+            fetch_status: FetchStatus::Behind(2),
+            ..repo("api", "work")
+        };
+        let codes = [clean, broken, dirty, ahead, behind].map(|repo| single_repo_exit(&repo));
+        assert_eq!(codes, [0, 1, 3, 4, 4]);
+    }
+
+    #[test]
+    fn yaml_has_the_machine_json_fields() {
+        let tricky = RepoResult {
+            branch: Some("yes".to_string()),
+            upstream: Some("no".to_string()),
+            last_commit: Some("Merge \"x\"\nsecond line\ttab".to_string()),
+            remote_url: Some(String::new()),
+            local_error: Some("~".to_string()),
+            ..repo("- api: v2 # main", "")
+        };
+        let repos = [repo("web", "work"), tricky];
+
+        let mut lines = Vec::new();
+        for repo in &repos {
+            write_ndjson_result(repo, &mut lines).unwrap();
+        }
+        let json: Vec<Json> = String::from_utf8(lines)
+            .unwrap()
+            .lines()
+            .map(|line| parse_json(line).unwrap())
+            .collect();
+
+        // Each value is written as a JSON string, number or boolean, never as a
+        // plain YAML scalar that yes/no, `#` or a leading `- ` could change.
+        let out = render_yaml(&repos);
+        let mut yaml: Vec<Vec<(String, Json)>> = Vec::new();
+        for line in out.lines().skip(1) {
+            let (marker, field) = line.split_at(2);
+            if marker == "- " {
+                yaml.push(Vec::new());
+            } else {
+                assert_eq!(marker, "  ", "{line}");
+            }
+            let (key, value) = field.split_once(": ").unwrap();
+            assert!(key.chars().all(|c| c.is_ascii_lowercase() || c == '_'));
+            let value = parse_json(value).unwrap();
+            assert!(
+                matches!(value, Json::String(_) | Json::Number(_) | Json::Bool(_)),
+                "{line}"
+            );
+            yaml.last_mut().unwrap().push((key.to_string(), value));
+        }
+        let yaml: Vec<Json> = yaml.into_iter().map(Json::Object).collect();
+        assert_eq!(yaml, json);
+        assert_eq!(
+            out.lines().next(),
+            Some(format!("# generated by jiancha v{}", env!("CARGO_PKG_VERSION")).as_str())
+        );
+        assert_eq!(render_yaml(&[]).lines().last(), Some("[]"));
+    }
+
+    #[test]
+    fn escapes_per_color_capability() {
+        let header = |mode| {
+            Theme::by_name("default", mode)
+                .unwrap()
+                .adapted()
+                .section_line("WORK")
+        };
+        assert_eq!(
+            header(ColorMode::TrueColor),
+            "\x1b[1;38;2;255;140;0mWORK\x1b[0m"
+        );
+        assert_eq!(header(ColorMode::Ansi256), "\x1b[1;38;5;208mWORK\x1b[0m");
+        assert_eq!(header(ColorMode::Ansi16), "\x1b[1;33mWORK\x1b[0m");
+
+        assert_eq!(fg_sgr(Color::Red).as_deref(), Some("91"));
+        assert_eq!(bg_sgr(Color::DarkBlue).as_deref(), Some("44"));
+        assert_eq!(fg_sgr(Color::AnsiValue(208)).as_deref(), Some("38;5;208"));
+        assert_eq!(fg_sgr(Color::Reset), None);
+        assert_eq!(
+            ColorMode::Ansi16.adapt(Color::AnsiValue(244)),
+            Color::DarkGrey
+        );
+        assert_eq!(ColorMode::Ansi16.adapt(Color::Cyan), Color::Cyan);
+
+        // With color off there are no escapes at all, whatever the terminal.
+        let repos = [repo("api", "work")];
+        let groups = one_group(&repos);
+        assert!(!render_all(&repos, &groups, &render_opts(false)).contains('\x1b'));
+    }
+
+    #[test]
+    fn single_repo_config_needs_a_requested_config() {
+        let root = scratch_dir("single-config");
+        let dir = root.join("api");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_string_lossy().into_owned();
+        let status_args = |extra: &[&Path]| {
+            let argv = ["status", dir.as_str()].map(String::from).into_iter();
+            let extra = extra
+                .iter()
+                .flat_map(|path| ["--config".to_string(), path.to_string_lossy().into_owned()]);
+            run_args(argv.chain(extra))
+        };
+
+        let missing = root.join("missing.toml");
+        let err = single_repo_config(&status_args(&[&missing]), None).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read"), "{err}");
+        assert!(single_repo_config(&status_args(&[]), Some("no-such-profile")).is_err());
+
+        // A readable config without the directory still checks it ad hoc.
+        let path = root.join("config.toml");
+        std::fs::write(&path, "[work]\nweb = \"/nowhere/web\"\n").unwrap();
+        let config = single_repo_config(&status_args(&[&path]), None).unwrap();
+        assert_eq!(config.sections.keys().collect::<Vec<_>>(), [ADHOC_SECTION]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn watch_keeps_the_last_good_config() {
+        let root = scratch_dir("watch-config");
+        let path = root.join("config.toml");
+        let mut version = 0;
+        let mut save = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            // Each save gets its own mtime, however coarse the filesystem's clock.
+            version += 1;
+            let modified = UNIX_EPOCH + Duration::from_secs(1_000_000 + version);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(modified))
+                .unwrap();
+        };
+        let names = |config: Config| config.sections.keys().cloned().collect::<Vec<_>>();
+        let mut state = WatchState::default();
+
+        assert!(state.load_config(path.clone(), false).is_err());
+        save("[work]\napi = \"/src/api\"\n");
+        assert_eq!(
+            names(state.load_config(path.clone(), false).unwrap()),
+            ["work"]
+        );
+        assert_eq!(state.notice, None);
+
+        save("[work\napi = ");
+        assert_eq!(
+            names(state.load_config(path.clone(), false).unwrap()),
+            ["work"]
+        );
+        assert!(state.config_error.is_some());
+        assert_eq!(state.notice, None);
+        // Still broken, and still not rereading it.
+        assert_eq!(
+            names(state.load_config(path.clone(), false).unwrap()),
+            ["work"]
+        );
+        assert!(state.config_error.is_some());
+
+        save("[home]\ndots = \"/src/dots\"\n");
+        assert_eq!(
+            names(state.load_config(path.clone(), false).unwrap()),
+            ["home"]
+        );
+        assert_eq!(state.notice, Some("config reloaded"));
+        assert_eq!(state.config_error, None);
+        assert_eq!(
+            names(state.load_config(path.clone(), false).unwrap()),
+            ["home"]
+        );
+        assert_eq!(state.notice, None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn watch_holds_sections_until_their_interval() {
+        let config = parse_config(
+            "[personal]\nrefresh_interval_secs = 60\ndots = \"/src/dots\"\n\n\
+             [work]\napi = \"/src/api\"\n",
+            false,
+        )
+        .unwrap();
+        let keys = |rows: &[RepoResult]| {
+            rows.iter()
+                .map(|row| format!("{}/{}", row.repo_key, row.last_commit.as_deref().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let scan = |commit: &str, sections: &[&str]| {
+            sections
+                .iter()
+                .map(|&section| RepoResult {
+                    last_commit: Some(commit.to_string()),
+                    ..repo(if section == "work" { "api" } else { "dots" }, section)
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut state = WatchState::default();
+
+        assert!(state.held_sections(&config).is_empty());
+        let rows = state.merge(
+            &config,
+            scan("first", &["personal", "work"]),
+            &HashSet::new(),
+            Instant::now(),
+        );
+        assert_eq!(keys(&rows), ["dots/first", "api/first"]);
+
+        // Within the minute only work is scanned; personal keeps its rows and place.
+        let held = state.held_sections(&config);
+        assert_eq!(held, HashSet::from(["personal".to_string()]));
+        let rows = state.merge(&config, scan("second", &["work"]), &held, Instant::now());
+        assert_eq!(keys(&rows), ["dots/first", "api/second"]);
+
+        let long_ago = Instant::now() - Duration::from_secs(61);
+        state.sections.get_mut("personal").unwrap().0 = long_ago;
+        assert!(state.held_sections(&config).is_empty());
+    }
+
+    #[test]
+    fn pull_without_a_fetch_is_skipped() {
+        for status in [FetchStatus::Pending, FetchStatus::Skipped] {
+            let row = RepoResult {
+                fetch_status: status,
+                ..repo("api", "work")
+            };
+            assert_eq!(pull_repo(&row, true), "SKIPPED: not fetched");
+        }
+    }
+
+    #[test]
+    fn pull_and_push_need_the_upstream_check() {
+        let root = scratch_dir("pull-unchecked");
+        let unchecked = root.join("unchecked.toml");
+        std::fs::write(
+            &unchecked,
+            "[settings]\ncheck_upstream = false\n\n[work]\napi = \"/src/api\"\n",
+        )
+        .unwrap();
+        let checked = root.join("checked.toml");
+        std::fs::write(&checked, "[work]\napi = \"/src/api\"\n").unwrap();
+        let run = |argv: &[&str], config: &Path| {
+            let argv = argv.iter().map(|arg| arg.to_string());
+            let config = [
+                "--config".to_string(),
+                config.to_string_lossy().into_owned(),
+            ];
+            let args = run_args(argv.chain(config));
+            run_command(&args, None, &mut 0).map(|_| ())
+        };
+
+        for command in ["pull", "push"] {
+            for (argv, config) in [
+                (&[command][..], &unchecked),
+                (&[command, "--no-fetch-check"], &checked),
+            ] {
+                let err = run(argv, config).unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    "pull and push cannot run with --no-fetch-check or check_upstream = false",
+                    "{argv:?}"
+                );
+            }
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn help_and_version_are_returned_not_printed() {
+        let parse = |arg: &str| parse_args([arg.to_string()]).unwrap();
+        assert!(matches!(parse("--help"), Invocation::Help));
+        assert!(matches!(parse("-V"), Invocation::Version));
+        assert!(matches!(parse("--offline"), Invocation::Run(_)));
+    }
+
+    #[test]
+    fn config_warnings_are_returned() {
+        let config = parse_config(
+            "ignore_sections = [\"nope\"]\ndisplay_order = [\"work\", \"gone\"]\n\n\
+             [work]\napi = \"/src/api\"\nclients = \"/nowhere/work-*\"\n",
+            false,
+        )
+        .unwrap();
+        assert_eq!(config.sections.keys().collect::<Vec<_>>(), ["work"]);
+        assert_eq!(
+            config.warnings(),
+            [
+                "ignore_sections lists unknown section \"nope\"",
+                "display_order lists unknown section \"gone\"",
+                "work.clients = \"/nowhere/work-*\" matches no directories",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_envrc_follows_the_git_options() {
+        let dir = scratch_dir("envrc");
+        std::fs::write(dir.join(".envrc"), "export FOO=1\n").unwrap();
+        let dir = dir.to_str().unwrap();
+        let program = || git(dir).get_program().to_os_string();
+        let load_envrc = GitOptions {
+            load_envrc: true,
+            ..GitOptions::default()
+        };
+
+        assert_eq!(program(), "git");
+        let (inside, _) = with_git_options(load_envrc, || {
+            // Another thread keeps its own options.
+            let other = thread::scope(|scope| scope.spawn(program).join().unwrap());
+            (program(), other)
+        });
+        assert_eq!(inside, ("direnv".into(), "git".into()));
+        assert_eq!(program(), "git");
+    }
+}
@@ -0,0 +1,264 @@
+//! Runs the `jiancha` binary against throwaway repos and config, for what only
+//! shows at the process boundary: which stream each line goes to, exit statuses,
+//! and flags read straight from the command line.
+
+mod support;
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use support::{fixture_git, fixture_repo, parse_json, scratch_dir, Json};
+
+/// A home directory with a config holding one repo, one missing directory, and a
+/// glob that matches nothing, which draws a warning.
+fn fixture(name: &str) -> PathBuf {
+    let home = scratch_dir(&format!("cli-{name}"));
+    let repo = home.join("src/api");
+    fixture_repo(&repo);
+    fixture_git(
+        &repo,
+        &["commit", "-q", "--allow-empty", "-m", "Say \"hi\": #1"],
+    );
+    let config = home.join(".config/jiancha");
+    std::fs::create_dir_all(&config).unwrap();
+    std::fs::write(
+        config.join("config.toml"),
+        format!(
+            "[work]\napi = {:?}\ngone = {:?}\nextra = {:?}\n",
+            repo.to_string_lossy(),
+            home.join("missing").to_string_lossy(),
+            home.join("nothing-*").to_string_lossy()
+        ),
+    )
+    .unwrap();
+    home
+}
+
+fn jiancha(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_jiancha"))
+        .args(args)
+        .env_clear()
+        .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+        .env("HOME", home)
+        .env("LANG", "C.UTF-8")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn list_json_stdout_is_only_json() {
+    let home = fixture("list-json");
+    let output = jiancha(&home, &["list", "--json"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let list = parse_json(stdout.trim_end()).unwrap_or_else(|| panic!("not JSON: {stdout}"));
+    let Some(Json::Array(repos)) = list.get("repos") else {
+        panic!("no repos: {stdout}");
+    };
+    let exists: Vec<_> = repos.iter().map(|repo| repo.get("exists")).collect();
+    assert_eq!(
+        exists,
+        [Some(&Json::Bool(true)), Some(&Json::Bool(false))],
+        "{stdout}"
+    );
+
+    let paths = jiancha(&home, &["list", "--paths", "--json"]);
+    let paths = String::from_utf8(paths.stdout).unwrap();
+    let Some(Json::Array(paths)) = parse_json(paths.trim_end()) else {
+        panic!("not a JSON array: {paths}");
+    };
+    assert_eq!(paths.len(), 2);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("matches no directories"), "{stderr}");
+    assert!(
+        stderr.lines().all(|line| line.starts_with("warning: ")),
+        "{stderr}"
+    );
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn machine_json_stdout_is_only_json() {
+    let home = fixture("machine-json");
+    let output = jiancha(&home, &["--machine-json", "--offline"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Json> = stdout
+        .lines()
+        .map(|line| parse_json(line).unwrap_or_else(|| panic!("not JSON: {line}")))
+        .collect();
+    assert_eq!(rows.len(), 2, "{stdout}");
+    let field = |row: &Json, key| row.get(key).and_then(Json::as_str).map(str::to_string);
+    assert_eq!(field(&rows[0], "repo").as_deref(), Some("api"));
+    assert_eq!(
+        field(&rows[0], "last_commit").as_deref(),
+        Some("Say \"hi\": #1")
+    );
+    assert_eq!(
+        field(&rows[1], "error").as_deref(),
+        Some("Not a valid directory")
+    );
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("matches no directories"), "{stderr}");
+    assert!(
+        stderr.lines().all(|line| line.starts_with("warning: ")),
+        "{stderr}"
+    );
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn warnings_stay_off_stdout() {
+    let home = fixture("formats");
+    for args in [
+        &["--format", "porcelain"][..],
+        &["--format", "prometheus"],
+        &["--format", "tsv"],
+        &["--format", "yaml"],
+        &["--format", "table"],
+        &["--oneline"],
+        &["--compact"],
+    ] {
+        let output = jiancha(&home, &[&["--offline"][..], args].concat());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stdout.contains("warning"), "{args:?}: {stdout}");
+        assert!(!stdout.contains('\x1b'), "{args:?}: {stdout}");
+        assert!(stderr.contains("warning: work.extra"), "{args:?}: {stderr}");
+    }
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn version_and_unknown_flags() {
+    let home = fixture("flags");
+    let output = jiancha(&home, &["--version"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("jiancha {}\n", env!("CARGO_PKG_VERSION"))
+    );
+
+    let output = jiancha(&home, &["--bogus"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--help"), "{stderr}");
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+/// `--help` has to match `tests/cmd/help.stdout`, so a change to the usage shows
+/// up in review; after a deliberate one, regenerate the file with
+/// `cargo run -q -- --help > tests/cmd/help.stdout`.
+#[test]
+fn help_matches_the_snapshot() {
+    let home = scratch_dir("cli-help");
+    for flag in ["--help", "-h"] {
+        let output = jiancha(&home, &[flag]);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            include_str!("cmd/help.stdout"),
+            "{flag}"
+        );
+    }
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn config_flag_reads_that_file() {
+    let home = fixture("config-flag");
+    let other = home.join("other.toml");
+    std::fs::write(
+        &other,
+        format!(
+            "[other]\napi = {:?}\n",
+            home.join("src/api").to_string_lossy()
+        ),
+    )
+    .unwrap();
+    let other = other.to_str().unwrap();
+    let output = jiancha(&home, &["--offline", "--machine-json", "--config", other]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Json> = stdout.lines().filter_map(parse_json).collect();
+    assert_eq!(rows.len(), 1, "{stdout}");
+    assert_eq!(rows[0].get("section").and_then(Json::as_str), Some("other"));
+
+    let output = jiancha(&home, &["--config", other, "--profile", "work"]);
+    assert_eq!(output.status.code(), Some(1));
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn dirty_keeps_only_repos_with_changes() {
+    let home = fixture("dirty");
+    let output = jiancha(&home, &["--offline", "--machine-json", "--dirty"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+
+    // An unstaged edit doesn't invalidate the local-state cache the first run wrote.
+    std::fs::write(home.join("src/api/README"), "changed\n").unwrap();
+    let output = jiancha(
+        &home,
+        &["--offline", "--machine-json", "--dirty", "--no-cache"],
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<Json> = stdout.lines().filter_map(parse_json).collect();
+    assert_eq!(rows.len(), 1, "{stdout}");
+    assert_eq!(rows[0].get("repo").and_then(Json::as_str), Some("api"));
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn github_format_fails_on_error_annotations() {
+    let home = fixture("github");
+    let output = jiancha(&home, &["--offline", "--format", "github"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("::error title=jiancha::work/gone: error"),
+        "{stdout}"
+    );
+    assert_eq!(output.status.code(), Some(1));
+
+    let repo = home.join("src/api");
+    let output = jiancha(
+        &home,
+        &["--offline", "--format", "github", repo.to_str().unwrap()],
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("::error"), "{stdout}");
+    assert_eq!(output.status.code(), Some(0));
+    std::fs::remove_dir_all(home).unwrap();
+}
+
+#[test]
+fn pull_says_why_each_repo_is_skipped() {
+    let home = fixture("pull");
+    let output = jiancha(&home, &["pull", "--dry-run", "--machine-json"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let actions: Vec<_> = stdout
+        .lines()
+        .filter_map(parse_json)
+        .map(|row| {
+            let field = |key| row.get(key).and_then(Json::as_str).map(str::to_string);
+            (field("repo"), field("action"))
+        })
+        .collect();
+    let action = |repo: &str, action: &str| (Some(repo.to_string()), Some(action.to_string()));
+    assert_eq!(
+        actions,
+        [
+            action("gone", "SKIPPED: error"),
+            action("api", "SKIPPED: no upstream")
+        ],
+        "{stdout}"
+    );
+
+    let output = jiancha(&home, &["pull", "--no-fetch-check"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("check_upstream = false"), "{stderr}");
+    std::fs::remove_dir_all(home).unwrap();
+}
@@ -0,0 +1,177 @@
+//! Fixtures and checks shared by the unit tests in `lib.rs` and the integration
+//! tests under `tests/`.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// An empty directory under the system temp dir, unique to this test process.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jiancha-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs git in `dir` to set up a fixture, with a fixed identity and branch.
+pub fn fixture_git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args([
+            "-c",
+            "init.defaultBranch=main",
+            "-c",
+            "commit.gpgsign=false",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?} failed");
+}
+
+/// A repo at `dir` with one commit.
+pub fn fixture_repo(dir: &Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    fixture_git(dir, &["init", "-q"]);
+    std::fs::write(dir.join("README"), "fixture\n").unwrap();
+    fixture_git(dir, &["add", "README"]);
+    fixture_git(dir, &["commit", "-q", "-m", "Initial commit"]);
+}
+
+/// A parsed JSON value, enough to check what jiancha writes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Parses `text` as exactly one compact JSON value, the way jiancha writes it (no
+/// whitespace between tokens); `None` if it is anything else.
+pub fn parse_json(text: &str) -> Option<Json> {
+    fn value(s: &[char], i: &mut usize) -> Option<Json> {
+        match *s.get(*i)? {
+            '"' => string(s, i).map(Json::String),
+            '[' => {
+                *i += 1;
+                let mut items = Vec::new();
+                if s.get(*i) == Some(&']') {
+                    *i += 1;
+                    return Some(Json::Array(items));
+                }
+                loop {
+                    items.push(value(s, i)?);
+                    match s.get(*i)? {
+                        ',' => *i += 1,
+                        ']' => {
+                            *i += 1;
+                            return Some(Json::Array(items));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                *i += 1;
+                let mut members = Vec::new();
+                if s.get(*i) == Some(&'}') {
+                    *i += 1;
+                    return Some(Json::Object(members));
+                }
+                loop {
+                    let key = string(s, i)?;
+                    if s.get(*i) != Some(&':') {
+                        return None;
+                    }
+                    *i += 1;
+                    members.push((key, value(s, i)?));
+                    match s.get(*i)? {
+                        ',' => *i += 1,
+                        '}' => {
+                            *i += 1;
+                            return Some(Json::Object(members));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            _ => {
+                let start = *i;
+                while s
+                    .get(*i)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || "-+.".contains(*c))
+                {
+                    *i += 1;
+                }
+                let word: String = s[start..*i].iter().collect();
+                match word.as_str() {
+                    "null" => Some(Json::Null),
+                    "true" => Some(Json::Bool(true)),
+                    "false" => Some(Json::Bool(false)),
+                    _ => word.parse().ok().map(Json::Number),
+                }
+            }
+        }
+    }
+    fn string(s: &[char], i: &mut usize) -> Option<String> {
+        if s.get(*i) != Some(&'"') {
+            return None;
+        }
+        *i += 1;
+        let mut out = String::new();
+        loop {
+            let c = *s.get(*i)?;
+            *i += 1;
+            match c {
+                '"' => return Some(out),
+                '\\' => {
+                    let escaped = *s.get(*i)?;
+                    *i += 1;
+                    out.push(match escaped {
+                        '"' | '\\' | '/' => escaped,
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let hex: String = s.get(*i..*i + 4)?.iter().collect();
+                            *i += 4;
+                            char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                        }
+                        _ => return None,
+                    });
+                }
+                c if c >= ' ' => out.push(c),
+                _ => return None,
+            }
+        }
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    let parsed = value(&chars, &mut i)?;
+    (i == chars.len()).then_some(parsed)
+}